@@ -0,0 +1,106 @@
+use std::time::Instant;
+
+use crate::bitboard::*;
+use crate::log::send;
+use crate::perft::{self, PerftTable};
+use crate::position::*;
+
+const BENCH_POSITIONS: [&str; 4] = ["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                                   "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                                   "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                                   "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"];
+
+const ATTACK_LOOKUPS: u64 = 1 << 24;
+
+struct BenchResult {
+    perft_nodes: u64,
+    perft_ms: u64,
+    attacks_checksum: u64,
+    attacks_ms: u64
+}
+
+/**
+ * runs perft on the bench positions and a sliding attack look up benchmark with every available
+ * slider attack implementation, checks that all implementations produce identical results and prints the timings.
+ * The output goes through send!, so the uci 'bench' command is logged like the other protocol output.
+ */
+pub fn slider_attacks_bench(depth: u32) {
+    let previous = SliderAttacks::selected();
+    let mut results = Vec::new();
+
+    for method in SliderAttacks::ALL.into_iter().filter(|m| m.is_available()) {
+        //builds the tables before the timing starts
+        method.select();
+
+        let result = run(depth);
+
+        let mut line = format!("{method}: perft nodes {} time {}", result.perft_nodes, result.perft_ms);
+        if let Some(nps) = (result.perft_nodes * 1000).checked_div(result.perft_ms) {
+            line += &format!(" nps {nps}");
+        }
+        line += &format!(", attack look ups {ATTACK_LOOKUPS} time {}", result.attacks_ms);
+        if let Some(per_second) = (ATTACK_LOOKUPS * 1000).checked_div(result.attacks_ms) {
+            line += &format!(" per second {per_second}");
+        }
+        send!("{line}");
+
+        results.push((method, result));
+    }
+
+    previous.select();
+
+    let (reference_method, reference) = &results[0];
+    let mut identical = true;
+
+    for (method, result) in results.iter().skip(1) {
+        if result.perft_nodes != reference.perft_nodes || result.attacks_checksum != reference.attacks_checksum {
+            send!("results of {method} differ from {reference_method}");
+            identical = false;
+        } else {
+            send!("{method}: {:.2}x the speed of {reference_method} in perft, {:.2}x in attack look ups", 
+                reference.perft_ms as f64 / result.perft_ms.max(1) as f64,
+                reference.attacks_ms as f64 / result.attacks_ms.max(1) as f64);
+        }
+    }
+
+    if identical {
+        send!("all implementations produced identical results");
+    }
+}
+
+fn run(depth: u32) -> BenchResult {
+    let table = PerftTable::new(PerftTable::DEFAULT_MB_SIZE);
+
+    let start_time = Instant::now();
+
+    let mut perft_nodes = 0;
+    for fen in BENCH_POSITIONS {
+        let mut pos = Position::from_fen_string(fen).expect("bench positions should be valid");
+        perft_nodes += perft::perft(&mut pos, depth, &table);
+    }
+
+    let perft_ms = start_time.elapsed().as_millis() as u64;
+
+    let start_time = Instant::now();
+
+    let mut attacks_checksum = 0;
+    let mut state: u64 = 0x9e3779b97f4a7c15;
+    for i in 0..ATTACK_LOOKUPS {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        let occupied = Bitboard::from_u64(state & (state >> 17));
+        let square = (i % 64) as u8;
+        attacks_checksum ^= (Bitboard::rook_attacks(square, occupied) | Bitboard::bishop_attacks(square, occupied)).b.rotate_left(square as u32);
+    }
+
+    let attacks_ms = start_time.elapsed().as_millis() as u64;
+
+    BenchResult {
+        perft_nodes,
+        perft_ms,
+        attacks_checksum,
+        attacks_ms
+    }
+}
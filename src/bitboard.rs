@@ -1,5 +1,9 @@
 use std::fmt;
 
+mod magic;
+
+pub use magic::SliderAttacks;
+
 #[derive(Copy, Clone, PartialEq)]
 pub struct Bitboard {
     pub b: u64
@@ -102,11 +106,27 @@ impl Bitboard {
         !(Self::line(s1, s2) & Bitboard::from_square(s3)).is_empty()
     }
 
+    //number of king moves between two squares
+    pub const fn distance(s1: u8, s2: u8) -> u8 {
+        let file_distance = (s1 % 8).abs_diff(s2 % 8);
+        let rank_distance = (s1 / 8).abs_diff(s2 / 8);
+        if file_distance > rank_distance { file_distance } else { rank_distance }
+    }
+
+    //the implementation selected by SliderAttacks::init() or SliderAttacks::select()
     pub fn bishop_attacks(square: u8, occupied: Bitboard) -> Bitboard {
-       Bitboard::diagonal_attacks(square, occupied) | Bitboard::antidiagonal_attacks(square, occupied)
+        magic::bishop_attacks(square, occupied)
     }
 
     pub fn rook_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+        magic::rook_attacks(square, occupied)
+    }
+
+    pub fn kindergarten_bishop_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+       Bitboard::diagonal_attacks(square, occupied) | Bitboard::antidiagonal_attacks(square, occupied)
+    }
+
+    pub fn kindergarten_rook_attacks(square: u8, occupied: Bitboard) -> Bitboard {
         Bitboard::rank_attacks(square, occupied) | Bitboard::file_attacks(square, occupied)
     }
 
@@ -145,6 +165,12 @@ impl Bitboard {
     }
 }
 
+impl Default for Bitboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IntoIterator for Bitboard {
     type Item = u8;
     type IntoIter = BitboardIterator;
@@ -0,0 +1,363 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+use super::Bitboard;
+
+/*
+ * sliding piece attacks with fancy magic bitboards and, if the "pext" feature is enabled and the cpu
+ * supports BMI2, with the pext instruction. The kindergarten implementation in bitboard.rs is kept as
+ * a third alternative and is used to initialise the tables.
+ *
+ * the implementation is selected once by SliderAttacks::init() (or by select() in the bench), which
+ * builds its tables and stores its attack functions, so a look up is a single indirect call.
+ */
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SliderAttacks {
+    Kindergarten,
+    Magic,
+    Pext
+}
+
+type AttackFn = fn(u8, Bitboard) -> Bitboard;
+
+//until init() is called the kindergarten attacks are used, they don't need any tables
+static SELECTED_SLIDER_ATTACKS: AtomicU8 = AtomicU8::new(SliderAttacks::Kindergarten as u8);
+static BISHOP_ATTACKS: AtomicPtr<()> = AtomicPtr::new(Bitboard::kindergarten_bishop_attacks as *mut ());
+static ROOK_ATTACKS: AtomicPtr<()> = AtomicPtr::new(Bitboard::kindergarten_rook_attacks as *mut ());
+
+impl SliderAttacks {
+    pub const ALL: [SliderAttacks; 3] = [SliderAttacks::Kindergarten, SliderAttacks::Magic, SliderAttacks::Pext];
+
+    pub fn is_available(&self) -> bool {
+        match self {
+            SliderAttacks::Kindergarten | SliderAttacks::Magic => true,
+            SliderAttacks::Pext => pext_supported()
+        }
+    }
+
+    //the fastest implementation available on this cpu
+    pub fn detect() -> SliderAttacks {
+        if SliderAttacks::Pext.is_available() {
+            SliderAttacks::Pext
+        } else {
+            SliderAttacks::Magic
+        }
+    }
+
+    //selects the fastest implementation, if no other one was selected yet. Called before the first search.
+    pub fn init() {
+        static INIT: OnceLock<()> = OnceLock::new();
+        INIT.get_or_init(|| {
+            SliderAttacks::detect().select();
+        });
+    }
+
+    pub fn selected() -> SliderAttacks {
+        match SELECTED_SLIDER_ATTACKS.load(Ordering::Relaxed) {
+            1 => SliderAttacks::Magic,
+            2 => SliderAttacks::Pext,
+            _ => SliderAttacks::Kindergarten
+        }
+    }
+
+    /**
+     * selects the implementation used by Bitboard::bishop_attacks and Bitboard::rook_attacks and builds
+     * its tables. Returns false (and keeps the current selection) if the implementation is not available.
+     */
+    pub fn select(self) -> bool {
+        if !self.is_available() {
+            return false;
+        }
+
+        let (bishop, rook): (AttackFn, AttackFn) = match self {
+            SliderAttacks::Kindergarten => (Bitboard::kindergarten_bishop_attacks, Bitboard::kindergarten_rook_attacks),
+            SliderAttacks::Magic => {
+                bishop_magic_table();
+                rook_magic_table();
+                (magic_bishop_attacks, magic_rook_attacks)
+            },
+            SliderAttacks::Pext => pext_attack_fns()
+        };
+
+        BISHOP_ATTACKS.store(bishop as *mut (), Ordering::Relaxed);
+        ROOK_ATTACKS.store(rook as *mut (), Ordering::Relaxed);
+        SELECTED_SLIDER_ATTACKS.store(self as u8, Ordering::Relaxed);
+        true
+    }
+}
+
+pub fn bishop_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+    //only attack functions are stored in BISHOP_ATTACKS
+    let f = unsafe { std::mem::transmute::<*mut (), AttackFn>(BISHOP_ATTACKS.load(Ordering::Relaxed)) };
+    f(square, occupied)
+}
+
+pub fn rook_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+    //only attack functions are stored in ROOK_ATTACKS
+    let f = unsafe { std::mem::transmute::<*mut (), AttackFn>(ROOK_ATTACKS.load(Ordering::Relaxed)) };
+    f(square, occupied)
+}
+
+impl std::fmt::Display for SliderAttacks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            SliderAttacks::Kindergarten => "kindergarten",
+            SliderAttacks::Magic => "magic",
+            SliderAttacks::Pext => "pext"
+        })
+    }
+}
+
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+fn pext_supported() -> bool {
+    std::is_x86_feature_detected!("bmi2")
+}
+
+#[cfg(not(all(feature = "pext", target_arch = "x86_64")))]
+fn pext_supported() -> bool {
+    false
+}
+
+struct SliderTable {
+    masks: [u64; 64],
+    magics: [u64; 64],
+    shifts: [u32; 64],
+    offsets: [usize; 64],
+    attacks: Vec<Bitboard>
+}
+
+static ROOK_MAGIC_TABLE: OnceLock<SliderTable> = OnceLock::new();
+static BISHOP_MAGIC_TABLE: OnceLock<SliderTable> = OnceLock::new();
+
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+static ROOK_PEXT_TABLE: OnceLock<SliderTable> = OnceLock::new();
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+static BISHOP_PEXT_TABLE: OnceLock<SliderTable> = OnceLock::new();
+
+#[derive(Copy, Clone, PartialEq)]
+enum Slider {
+    Bishop,
+    Rook
+}
+
+impl SliderTable {
+    /*
+     * builds the attack table for all squares. index_fn maps (square, occupancy of the relevant squares)
+     * to the index inside the square's part of the table.
+     */
+    fn new<F: Fn(&SliderTable, usize, u64) -> usize>(slider: Slider, magics: [u64; 64], index_fn: F) -> SliderTable {
+        let mut table = SliderTable {
+            masks: [0; 64],
+            magics,
+            shifts: [0; 64],
+            offsets: [0; 64],
+            attacks: Vec::new()
+        };
+
+        let mut size = 0;
+        for square in 0..64 {
+            let mask = relevant_occupancy(slider, square as u8);
+            table.masks[square] = mask;
+            table.shifts[square] = 64 - mask.count_ones();
+            table.offsets[square] = size;
+            size += 1 << mask.count_ones();
+        }
+
+        table.attacks = vec![Bitboard::new(); size];
+
+        for square in 0..64 {
+            let mask = table.masks[square];
+
+            //enumerate all subsets of the mask (carry-rippler)
+            let mut occupied: u64 = 0;
+            loop {
+                let attacks = match slider {
+                    Slider::Bishop => Bitboard::kindergarten_bishop_attacks(square as u8, Bitboard::from_u64(occupied)),
+                    Slider::Rook => Bitboard::kindergarten_rook_attacks(square as u8, Bitboard::from_u64(occupied))
+                };
+
+                let index = table.offsets[square] + index_fn(&table, square, occupied);
+                debug_assert!(table.attacks[index].is_empty() || table.attacks[index] == attacks, "magic index collision");
+                table.attacks[index] = attacks;
+
+                occupied = occupied.wrapping_sub(mask) & mask;
+                if occupied == 0 {
+                    break;
+                }
+            }
+        }
+
+        table
+    }
+
+    fn magic_index(&self, square: usize, occupied: u64) -> usize {
+        ((occupied & self.masks[square]).wrapping_mul(self.magics[square]) >> self.shifts[square]) as usize
+    }
+
+    fn magic_attacks(&self, square: u8, occupied: Bitboard) -> Bitboard {
+        self.attacks[self.offsets[square as usize] + self.magic_index(square as usize, occupied.b)]
+    }
+}
+
+//a table that select() has built before it stored the attack functions that use it
+fn built(table: &'static OnceLock<SliderTable>) -> &'static SliderTable {
+    debug_assert!(table.get().is_some(), "slider table used before it was built");
+    unsafe { table.get().unwrap_unchecked() }
+}
+
+//squares whose occupancy influences the attacks of a slider on the given square. The last square of each ray is excluded.
+fn relevant_occupancy(slider: Slider, square: u8) -> u64 {
+    let files = Bitboard::file(0) | Bitboard::file(7);
+    let ranks = Bitboard::rank(0) | Bitboard::rank(7);
+
+    let mask = match slider {
+        Slider::Bishop => Bitboard::kindergarten_bishop_attacks(square, Bitboard::new()) & !files & !ranks,
+        Slider::Rook => (Bitboard::rank_attacks(square, Bitboard::new()) & !files) | (Bitboard::file_attacks(square, Bitboard::new()) & !ranks)
+    };
+
+    mask.b
+}
+
+fn rook_magic_table() -> &'static SliderTable {
+    ROOK_MAGIC_TABLE.get_or_init(|| SliderTable::new(Slider::Rook, ROOK_MAGICS, SliderTable::magic_index))
+}
+
+fn bishop_magic_table() -> &'static SliderTable {
+    BISHOP_MAGIC_TABLE.get_or_init(|| SliderTable::new(Slider::Bishop, BISHOP_MAGICS, SliderTable::magic_index))
+}
+
+fn magic_rook_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+    built(&ROOK_MAGIC_TABLE).magic_attacks(square, occupied)
+}
+
+fn magic_bishop_attacks(square: u8, occupied: Bitboard) -> Bitboard {
+    built(&BISHOP_MAGIC_TABLE).magic_attacks(square, occupied)
+}
+
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+mod pext {
+    use std::arch::x86_64::_pext_u64;
+
+    use super::*;
+
+    fn pext_index(table: &SliderTable, square: usize, occupied: u64) -> usize {
+        //the tables are only built after pext support has been detected
+        unsafe { pext_u64(occupied, table.masks[square]) as usize }
+    }
+
+    #[target_feature(enable = "bmi2")]
+    unsafe fn pext_u64(a: u64, mask: u64) -> u64 {
+        _pext_u64(a, mask)
+    }
+
+    fn rook_table() -> &'static SliderTable {
+        ROOK_PEXT_TABLE.get_or_init(|| SliderTable::new(Slider::Rook, [0; 64], pext_index))
+    }
+
+    fn bishop_table() -> &'static SliderTable {
+        BISHOP_PEXT_TABLE.get_or_init(|| SliderTable::new(Slider::Bishop, [0; 64], pext_index))
+    }
+
+    fn pext_attacks(table: &SliderTable, square: u8, occupied: Bitboard) -> Bitboard {
+        table.attacks[table.offsets[square as usize] + pext_index(table, square as usize, occupied.b)]
+    }
+
+    //builds the tables and returns the bishop and rook attack functions that use them
+    pub fn pext_attack_fns() -> (AttackFn, AttackFn) {
+        bishop_table();
+        rook_table();
+        (|square, occupied| pext_attacks(built(&BISHOP_PEXT_TABLE), square, occupied),
+         |square, occupied| pext_attacks(built(&ROOK_PEXT_TABLE), square, occupied))
+    }
+}
+
+#[cfg(all(feature = "pext", target_arch = "x86_64"))]
+use pext::pext_attack_fns;
+
+//without the pext feature SliderAttacks::Pext is never available, so select() doesn't get here
+#[cfg(not(all(feature = "pext", target_arch = "x86_64")))]
+fn pext_attack_fns() -> (AttackFn, AttackFn) {
+    unreachable!("pext attacks are not available")
+}
+
+/*
+ * magic numbers for the fancy magic bitboards (one per square, a8 = 0). They were found by trial
+ * and error with sparse random numbers and are verified when the tables are initialised in debug builds.
+ */
+
+const ROOK_MAGICS: [u64; 64] = [
+    0x0a80004000801220, 0x10c0100040002000, 0x0100102000410009, 0x0b0021000c100008,
+    0x4080080080040002, 0x0200019004080200, 0x0400080a10112684, 0x20800a4d00062080,
+    0x2091800020804000, 0x0044401000200040, 0x1001002000401108, 0x1001800801100081,
+    0x0001000500080010, 0x1000808002000400, 0x0404000482100108, 0x0003000182610002,
+    0x0440848002c00420, 0x2010890040010021, 0x8800110020044300, 0x0208010100201000,
+    0x1222020004102008, 0x0000808002000400, 0x20040400094a9008, 0x0000420000804401,
+    0x0040002880004680, 0x0000200240100040, 0x0020008180201001, 0x01080080800c1000,
+    0x0104040080800800, 0x4800020080040080, 0x0002000200840108, 0x00a1000100006082,
+    0x8004400088800260, 0x0100804000802008, 0x0010008010802002, 0x000c801000800800,
+    0x0c51800402800800, 0x0002800200800400, 0x0000820804000110, 0x4003808042000401,
+    0x00208020c0018000, 0x4400402010004009, 0x22100400a800e000, 0x0e020021400a0013,
+    0x10a0080100110005, 0x0004010002004040, 0x0024080102040010, 0x4154089108420014,
+    0x0182400080002380, 0x0000400110802100, 0x0000100080200480, 0x100a000820401200,
+    0x8081004020801002, 0x0002000408100200, 0x03223a1008010c00, 0x000000831c014200,
+    0x4200208009001041, 0xc001004000881021, 0x1008200100100841, 0x0000082240920032,
+    0x4002000804201102, 0xb821000804000201, 0x4080c208102100a4, 0x02020900418c0ca2,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x002a840401840308, 0x0002048404004000, 0x1088508106020000, 0x0604040484000420,
+    0x1002021004380001, 0x8041048240000a30, 0x4084044104103110, 0x0081004044200840,
+    0x04424110a1010901, 0x0042820841040080, 0x1001080204002c09, 0x00014804a1041815,
+    0x0004820210041001, 0x0001810403400040, 0x080240410420a084, 0x0410120201010900,
+    0x0240048504280200, 0x9402000490040325, 0x200300101c098030, 0x0004002840400800,
+    0x0002010402110140, 0x000040320100a060, 0x0042000061046000, 0x0188300084684808,
+    0x0010101108021022, 0x8724048021090c00, 0x502c0202c4080010, 0x0008082248020020,
+    0x0001020084008400, 0x0891004002082001, 0x000a021004248200, 0x00110200012a8402,
+    0x2042208430203904, 0x0c08620816111880, 0x0002045004410100, 0x0800120180180080,
+    0x0140010012c10040, 0x0050100840402400, 0x0808020080004801, 0x0004820341020100,
+    0x001a01242010c080, 0x2018620210012008, 0x80210400220a0400, 0x0020014200820801,
+    0x010008810041c400, 0x0020040880205a01, 0x001081011102e420, 0x00810604810b0208,
+    0x0000621004210094, 0x0200210802105811, 0x8008008058080200, 0x5800400084040010,
+    0x0000009002022880, 0x9000883001021010, 0x804a828404040006, 0x2010121801002800,
+    0x4012020084010846, 0x8002421202020200, 0x6104040020841000, 0x0000000205048804,
+    0x0808008041102480, 0x2305904002040440, 0x0810404282020204, 0x0588200102002100,
+];
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn random_occupancies() -> impl Iterator<Item = Bitboard> {
+        let mut state: u64 = 0x9e3779b97f4a7c15;
+        (0..2000).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let a = state;
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            Bitboard::from_u64(a & state)
+        })
+    }
+
+    //other tests may run in the meantime, which is fine because all implementations give the same attacks
+    #[test]
+    fn slider_attacks_agree() {
+        for method in SliderAttacks::ALL.into_iter().filter(|m| m.is_available()) {
+            assert!(method.select());
+            assert_eq!(SliderAttacks::selected(), method);
+
+            for occupied in random_occupancies() {
+                for square in 0..64 {
+                    assert_eq!(Bitboard::kindergarten_rook_attacks(square, occupied), Bitboard::rook_attacks(square, occupied), "{method}, square {square}, occupancy {occupied:?}");
+                    assert_eq!(Bitboard::kindergarten_bishop_attacks(square, occupied), Bitboard::bishop_attacks(square, occupied), "{method}, square {square}, occupancy {occupied:?}");
+                }
+            }
+        }
+
+        SliderAttacks::detect().select();
+    }
+}
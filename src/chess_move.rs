@@ -15,6 +15,16 @@ enum SpecialMoveType {
     Castling
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoveError {
+    InvalidFormat(String),
+    InvalidSquare(SquareError),
+    InvalidPromotionPiece(char),
+    InvalidSan(String),
+    IllegalMove(String),
+    AmbiguousMove(String)
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub struct Move {
     /**
@@ -54,20 +64,20 @@ impl Move {
         }
     }
 
-    pub fn from_string(string: &str, pos: &mut Position) -> Result<Move, ()> {
+    pub fn from_string(string: &str, pos: &mut Position) -> Result<Move, MoveError> {
         //check for non ascii chararcters right away, so we can safely index into the string
-        if !string.is_ascii() {
-            return Err(());
+        if !string.is_ascii() || string.len() < 4 || string.len() > 5 {
+            return Err(MoveError::InvalidFormat(string.to_string()));
         }
 
-        let from = Position::parse_square(string.get(0..2).ok_or(())?)?;
-        let to = Position::parse_square(string.get(2..4).ok_or(())?)?;
+        let from = Position::parse_square(&string[0..2])?;
+        let to = Position::parse_square(&string[2..4])?;
         let promote_to = string.chars().nth(4).map(|c| match c {
             'q' => Ok(Piece::Queen),
             'r' => Ok(Piece::Rook),
             'b' => Ok(Piece::Bishop),
             'n' => Ok(Piece::Knight),
-            _ => Err(())
+            _ => Err(MoveError::InvalidPromotionPiece(c))
         }).transpose()?;
 
         for m in pos.legal_moves() {
@@ -76,7 +86,160 @@ impl Move {
             }
         }
 
-        Err(())
+        Err(MoveError::IllegalMove(string.to_string()))
+    }
+
+    //parses a move in standard algebraic notation like 'Nf3', 'exd5', 'O-O' or 'e8=Q+'. The parts of the move are matched
+    //against the legal moves, so superfluous disambiguation like 'Nbd7' and promotions without '=' like 'e8Q' are accepted.
+    pub fn from_san(string: &str, pos: &mut Position) -> Result<Move, MoveError> {
+        let invalid = || MoveError::InvalidSan(string.to_string());
+
+        let san = string.trim_end_matches(['+', '#', '!', '?']);
+        if !san.is_ascii() {
+            return Err(invalid());
+        }
+
+        let castling = match san {
+            "O-O" | "0-0" => Some([CastlingType::WhiteCastleKingside, CastlingType::BlackCastleKingside]),
+            "O-O-O" | "0-0-0" => Some([CastlingType::WhiteCastleQueenside, CastlingType::BlackCastleQueenside]),
+            _ => None
+        };
+        if let Some(types) = castling {
+            return pos.legal_moves().into_iter()
+                .find(|m| m.castling_type().is_some_and(|t| types.contains(&t)))
+                .ok_or_else(|| MoveError::IllegalMove(string.to_string()));
+        }
+
+        let (piece, rest) = match san.chars().next().and_then(Self::parse_piece_char) {
+            Some(p) => (p, &san[1..]),
+            None => (Piece::Pawn, san)
+        };
+
+        //the promotion piece follows the target square, with or without '='
+        let (rest, promote_to) = match rest.chars().last().and_then(Self::parse_piece_char) {
+            Some(p) => (rest[..rest.len() - 1].trim_end_matches('='), Some(p)),
+            None => (rest, None)
+        };
+
+        if rest.len() < 2 {
+            return Err(invalid());
+        }
+        let to = Position::parse_square(&rest[rest.len() - 2..])?;
+
+        let mut capture = false;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in rest[..rest.len() - 2].chars() {
+            match c {
+                'x' | ':' if !capture => capture = true,
+                'a'..='h' if from_file.is_none() && !capture => from_file = Some(c as u8 - b'a'),
+                '1'..='8' if from_rank.is_none() && !capture => from_rank = Some(b'8' - c as u8),
+                _ => return Err(invalid())
+            }
+        }
+
+        let candidates: Vec<Move> = pos.legal_moves().into_iter()
+            .filter(|m| m.to() == to && m.promote_to() == promote_to && m.castling_type().is_none())
+            .filter(|m| pos.piece_on(m.from()) == piece)
+            .filter(|m| from_file.is_none_or(|f| m.from() % 8 == f) && from_rank.is_none_or(|r| m.from() / 8 == r))
+            .filter(|m| !capture || pos.is_capture(*m))
+            .collect();
+
+        match candidates[..] {
+            [m] => Ok(m),
+            [] => Err(MoveError::IllegalMove(string.to_string())),
+            _ => Err(MoveError::AmbiguousMove(string.to_string()))
+        }
+    }
+
+    //the move in standard algebraic notation. The move has to be legal in the given position.
+    pub fn to_san(&self, pos: &mut Position) -> String {
+        let mut san = match self.castling_type() {
+            Some(CastlingType::WhiteCastleKingside) | Some(CastlingType::BlackCastleKingside) => String::from("O-O"),
+            Some(_) => String::from("O-O-O"),
+            None => {
+                let piece = pos.piece_on(self.from());
+                let mut san = String::new();
+
+                if piece == Piece::Pawn {
+                    if pos.is_capture(*self) {
+                        san.push_str(&Position::square_to_string(self.from())[0..1]);
+                    }
+                } else {
+                    san.push(Self::piece_char(piece));
+
+                    //disambiguate between pieces of the same type that can move to the same square
+                    let others: Vec<u8> = pos.legal_moves().into_iter()
+                        .filter(|m| m.to() == self.to() && m.from() != self.from() && pos.piece_on(m.from()) == piece)
+                        .map(|m| m.from())
+                        .collect();
+
+                    let from = Position::square_to_string(self.from());
+                    if !others.is_empty() {
+                        if others.iter().all(|s| s % 8 != self.from() % 8) {
+                            san.push_str(&from[0..1]);
+                        } else if others.iter().all(|s| s / 8 != self.from() / 8) {
+                            san.push_str(&from[1..2]);
+                        } else {
+                            san.push_str(&from);
+                        }
+                    }
+                }
+
+                if pos.is_capture(*self) {
+                    san.push('x');
+                }
+                san.push_str(&Position::square_to_string(self.to()));
+
+                if let Some(p) = self.promote_to() {
+                    san.push('=');
+                    san.push(Self::piece_char(p));
+                }
+
+                san
+            }
+        };
+
+        pos.make_move(*self);
+        if pos.in_check() {
+            san.push(if pos.legal_moves().is_empty() { '#' } else { '+' });
+        }
+        pos.unmake_move(*self);
+
+        san
+    }
+
+    fn piece_char(piece: Piece) -> char {
+        match piece {
+            Piece::Pawn => 'P',
+            Piece::Knight => 'N',
+            Piece::Bishop => 'B',
+            Piece::Rook => 'R',
+            Piece::Queen => 'Q',
+            Piece::King => 'K',
+            Piece::NoPiece => panic!("no piece to move")
+        }
+    }
+
+    //pawns have no letter in SAN, so 'P' is not accepted
+    fn parse_piece_char(c: char) -> Option<Piece> {
+        match c {
+            'N' => Some(Piece::Knight),
+            'B' => Some(Piece::Bishop),
+            'R' => Some(Piece::Rook),
+            'Q' => Some(Piece::Queen),
+            'K' => Some(Piece::King),
+            _ => None
+        }
+    }
+
+    //the move in its 16 bit encoding, used to store moves in files
+    pub fn to_bits(self) -> u16 {
+        self.m
+    }
+
+    pub fn from_bits(m: u16) -> Move {
+        Move { m }
     }
 
     pub fn from(&self) -> u8 {
@@ -150,6 +313,27 @@ impl std::fmt::Display for Move {
     }
 }
 
+impl From<SquareError> for MoveError {
+    fn from(e: SquareError) -> Self {
+        MoveError::InvalidSquare(e)
+    }
+}
+
+impl std::fmt::Display for MoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::InvalidFormat(s) => write!(f, "expected a move in long algebraic notation like 'e2e4' or 'e7e8q', got '{s}'"),
+            MoveError::InvalidSquare(e) => write!(f, "{e}"),
+            MoveError::InvalidPromotionPiece(c) => write!(f, "invalid promotion piece '{c}'"),
+            MoveError::InvalidSan(s) => write!(f, "expected a move in standard algebraic notation like 'Nf3', 'exd5' or 'e8=Q', got '{s}'"),
+            MoveError::IllegalMove(s) => write!(f, "illegal move '{s}'"),
+            MoveError::AmbiguousMove(s) => write!(f, "ambiguous move '{s}'")
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
 const CASTLE_MOVES_TABLE: [Move; 4] = castling_move_table();
 
 const fn castling_move_table() -> [Move; 4] {
@@ -168,4 +352,273 @@ const fn castling_move_table() -> [Move; 4] {
     }
 
     m
-}
\ No newline at end of file
+}
+
+/**
+ * Fixed capacity, stack allocated move list. Every move has a score attached to it that can be
+ * used for move ordering. The scores are not initialised by the move generator.
+ */
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MoveList::CAPACITY],
+    scores: [i32; MoveList::CAPACITY],
+    len: usize
+}
+
+pub struct MoveListIntoIter {
+    list: MoveList,
+    index: usize
+}
+
+impl MoveList {
+    //no legal chess position has more than 218 legal moves
+    pub const CAPACITY: usize = 256;
+
+    pub fn new() -> MoveList {
+        MoveList {
+            moves: [Move { m: 0 }; MoveList::CAPACITY],
+            scores: [0; MoveList::CAPACITY],
+            len: 0
+        }
+    }
+
+    pub fn push(&mut self, m: Move) {
+        self.moves[self.len] = m;
+        self.scores[self.len] = 0;
+        self.len += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_slice(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+
+    pub fn score(&self, index: usize) -> i32 {
+        self.scores[..self.len][index]
+    }
+
+    pub fn set_score(&mut self, index: usize, score: i32) {
+        self.scores[..self.len][index] = score;
+    }
+
+    pub fn swap(&mut self, i: usize, j: usize) {
+        self.moves[..self.len].swap(i, j);
+        self.scores[..self.len].swap(i, j);
+    }
+
+    /**
+     * keeps only the moves for which f returns true. The order of the remaining moves is preserved.
+     */
+    pub fn retain<F: FnMut(Move) -> bool>(&mut self, mut f: F) {
+        let mut kept = 0;
+        for i in 0..self.len {
+            if f(self.moves[i]) {
+                self.moves[kept] = self.moves[i];
+                self.scores[kept] = self.scores[i];
+                kept += 1;
+            }
+        }
+        self.len = kept;
+    }
+
+    /**
+     * sorts the moves in the index range [start, end) by descending score. The sort is stable,
+     * so moves with equal scores keep their relative order.
+     */
+    pub fn sort_range_by_score(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.len);
+
+        for i in start+1..end {
+            let score = self.scores[i];
+            let m = self.moves[i];
+            let mut j = i;
+            while j > start && self.scores[j-1] < score {
+                self.scores[j] = self.scores[j-1];
+                self.moves[j] = self.moves[j-1];
+                j -= 1;
+            }
+            self.scores[j] = score;
+            self.moves[j] = m;
+        }
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for MoveList {
+    type Target = [Move];
+
+    fn deref(&self) -> &Self::Target {
+        self.as_slice()
+    }
+}
+
+impl std::ops::Index<usize> for MoveList {
+    type Output = Move;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.as_slice()[index]
+    }
+}
+
+impl std::ops::IndexMut<usize> for MoveList {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.moves[..self.len][index]
+    }
+}
+
+impl std::fmt::Debug for MoveList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl IntoIterator for MoveList {
+    type Item = Move;
+    type IntoIter = MoveListIntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        MoveListIntoIter {
+            list: self,
+            index: 0
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl Iterator for MoveListIntoIter {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        if self.index < self.list.len {
+            self.index += 1;
+            Some(self.list.moves[self.index - 1])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn list(moves: &[(u8, u8, i32)]) -> MoveList {
+        let mut list = MoveList::new();
+        for (i, &(from, to, score)) in moves.iter().enumerate() {
+            list.push(Move::new(from, to));
+            list.set_score(i, score);
+        }
+        list
+    }
+
+    #[test]
+    fn move_list() {
+        let mut moves = list(&[(52, 36, 10), (51, 35, 20), (62, 45, 30)]);
+        assert_eq!(moves.len(), 3);
+        assert_eq!(moves[1], Move::new(51, 35));
+
+        moves.swap(0, 2);
+        assert_eq!(moves.as_slice(), &[Move::new(62, 45), Move::new(51, 35), Move::new(52, 36)]);
+        assert_eq!((moves.score(0), moves.score(2)), (30, 10));
+
+        moves.retain(|m| m != Move::new(51, 35));
+        assert_eq!(moves.iter().copied().collect::<Vec<_>>(), [Move::new(62, 45), Move::new(52, 36)]);
+        assert_eq!(moves.score(1), 10);
+        assert_eq!(moves.clone().into_iter().count(), 2);
+
+        moves.clear();
+        assert!(moves.is_empty());
+        assert_eq!((&moves).into_iter().next(), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn swap_beyond_length() {
+        let mut moves = list(&[(52, 36, 10)]);
+        moves.swap(0, 1);
+    }
+
+    #[test]
+    fn sort_by_score() {
+        let mut moves = list(&[(52, 36, 10), (51, 35, 20), (62, 45, 10), (57, 42, 5)]);
+        moves.sort_range_by_score(0, 3);
+        assert_eq!(moves.as_slice(), &[Move::new(51, 35), Move::new(52, 36), Move::new(62, 45), Move::new(57, 42)]);
+    }
+
+    #[test]
+    fn san() {
+        //kiwipete: castling, captures, and knights that need disambiguation
+        let mut pos = Position::from_fen_string("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let cases = [("e1g1", "O-O"), ("e1c1", "O-O-O"), ("e5f7", "Nxf7"), ("d5e6", "dxe6"), ("f3f6", "Qxf6"), ("c3b1", "Nb1"),
+                     ("e2a6", "Bxa6"), ("d2c1", "Bc1"), ("e5g6", "Nxg6"), ("f3h3", "Qxh3")];
+
+        for (uci, san) in cases {
+            let m = Move::from_string(uci, &mut pos).unwrap();
+            assert_eq!(m.to_san(&mut pos), san);
+            assert_eq!(Move::from_san(san, &mut pos), Ok(m));
+        }
+
+        //promotion with check, file and rank disambiguation and mate
+        let mut pos = Position::from_fen_string("3k4/1P6/8/8/R6R/8/8/R3K3 w - - 0 1").unwrap();
+        for (uci, san) in [("b7b8q", "b8=Q+"), ("h4d4", "Rhd4+"), ("a4a2", "R4a2"), ("a1a3", "R1a3")] {
+            let m = Move::from_string(uci, &mut pos).unwrap();
+            assert_eq!(m.to_san(&mut pos), san);
+            assert_eq!(Move::from_san(san, &mut pos), Ok(m));
+        }
+
+        let mut pos = Position::from_fen_string("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let m = Move::from_string("a1a8", &mut pos).unwrap();
+        assert_eq!(m.to_san(&mut pos), "Ra8#");
+        assert_eq!(Move::from_san("Ra8", &mut pos), Ok(m));
+        assert!(Move::from_san("Rb9", &mut pos).is_err());
+    }
+
+    #[test]
+    fn san_parts() {
+        let mut pos = Position::new();
+        let m = Move::from_string("g1f3", &mut pos).unwrap();
+        for san in ["Nf3", "Ngf3", "N1f3", "Ng1f3", "Nf3!?"] {
+            assert_eq!(Move::from_san(san, &mut pos), Ok(m));
+        }
+        assert_eq!(Move::from_san("Nxf3", &mut pos), Err(MoveError::IllegalMove("Nxf3".to_string())));
+        assert_eq!(Move::from_san("Nhf3", &mut pos), Err(MoveError::IllegalMove("Nhf3".to_string())));
+        assert_eq!(Move::from_san("e4", &mut pos), Move::from_string("e2e4", &mut pos));
+        assert!(matches!(Move::from_san("Pe4", &mut pos), Err(MoveError::InvalidSan(_))));
+        assert!(matches!(Move::from_san("N", &mut pos), Err(MoveError::InvalidSan(_))));
+
+        //both knights can go to d7
+        let mut pos = Position::from_fen_string("rnbqkb1r/ppp1pppp/5n2/3p4/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(Move::from_san("Nd7", &mut pos), Err(MoveError::AmbiguousMove("Nd7".to_string())));
+        assert_eq!(Move::from_san("Nbd7", &mut pos), Move::from_string("b8d7", &mut pos));
+        assert_eq!(Move::from_san("Nb8d7", &mut pos), Move::from_string("b8d7", &mut pos));
+        assert_eq!(Move::from_san("N6d7", &mut pos), Move::from_string("f6d7", &mut pos));
+
+        let mut pos = Position::from_fen_string("3k4/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let m = Move::from_string("b7b8q", &mut pos).unwrap();
+        for san in ["b8=Q", "b8Q", "b8=Q+", "bb8Q"] {
+            assert_eq!(Move::from_san(san, &mut pos), Ok(m));
+        }
+        assert_eq!(Move::from_san("b8=N", &mut pos), Move::from_string("b7b8n", &mut pos));
+        assert!(Move::from_san("b8", &mut pos).is_err());
+
+        let mut pos = Position::from_fen_string("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1").unwrap();
+        assert_eq!(Move::from_san("O-O", &mut pos), Move::from_string("e8g8", &mut pos));
+        assert_eq!(Move::from_san("0-0-0", &mut pos), Move::from_string("e8c8", &mut pos));
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::engine::{Engine, SearchObserver, EvalFileError, DEFAULT_PV_LENGTH, MAX_PV_LENGTH};
+use crate::engine::book::BookError;
+use crate::engine::tablebase::TablebaseError;
+use crate::perft;
+use crate::uci::{DEFAULT_TTABLE_SIZE, MIN_TTABLE_SIZE, MAX_TTABLE_SIZE};
+
+/*
+ * settings of the binary, from a settings file and the command line. The file has one 'key = value'
+ * per line, '#' starts a comment. On the command line every key is an option '--key value', and the
+ * first argument that is not an option selects the run mode. Everything after it belongs to the mode.
+ * Command line options override the settings file:
+ *
+ *   carlito --config carlito.conf --hash 256 perft 6
+ */
+
+pub const USAGE: &str = "\
+usage: carlito [--config FILE] [--KEY VALUE]... [MODE [ARGS]...]
+
+keys, in the settings file as 'key = value':
+  mode        uci, xboard, bench, perft, play, epd, tablebase or replay. Without a mode, the first line of input selects uci or xboard
+  hash        transposition table size in MB
+  threads     threads for perft, the search uses one thread
+  book        opening book file, one line of moves in coordinate notation per opening
+  tablebases  directory with tablebase files
+  eval-file   evaluation parameters that replace the defaults
+  log-file    log of the uci or xboard traffic, uci logs can be replayed
+  pv-length   maximum number of moves in the reported principal variations
+
+modes:
+  uci | xboard
+  bench [DEPTH]
+  perft DEPTH [FEN]
+  play [--color white|black] [--fen FEN] [--time MS] [--depth N] [--nodes N] [--skill N]
+  epd FILE [--time MS] [--depth N] [--nodes N]
+  tablebase DIR ENDGAME...
+  replay LOG_FILE [--nodes N]";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RunMode {
+    //uci or xboard, whichever command comes first
+    Auto,
+    Uci,
+    Xboard,
+    Bench,
+    Perft,
+    Play,
+    Epd,
+    Tablebase,
+    Replay
+}
+
+impl RunMode {
+    fn from_name(name: &str) -> Option<RunMode> {
+        match name {
+            "uci" => Some(RunMode::Uci),
+            "xboard" => Some(RunMode::Xboard),
+            "bench" => Some(RunMode::Bench),
+            "perft" => Some(RunMode::Perft),
+            "play" => Some(RunMode::Play),
+            "epd" => Some(RunMode::Epd),
+            "tablebase" => Some(RunMode::Tablebase),
+            "replay" => Some(RunMode::Replay),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub mode: RunMode,
+    pub hash: usize,
+    pub threads: usize,
+    pub book: Option<PathBuf>,
+    pub tablebases: Option<PathBuf>,
+    pub eval_file: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub pv_length: usize
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            mode: RunMode::Auto,
+            hash: DEFAULT_TTABLE_SIZE,
+            threads: perft::default_threads(),
+            book: None,
+            tablebases: None,
+            eval_file: None,
+            log_file: None,
+            pv_length: DEFAULT_PV_LENGTH
+        }
+    }
+}
+
+impl Config {
+    /**
+     * parses the command line arguments without the program name. Returns the configuration and
+     * the arguments of the run mode.
+     */
+    pub fn from_args(args: &[String]) -> Result<(Config, Vec<String>), ConfigError> {
+        let mut config_file = None;
+        let mut options = Vec::new();
+        let mut mode = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(key) => {
+                    let value = iter.next().ok_or_else(|| ConfigError::MissingValue(key.to_string()))?;
+                    if key == "config" {
+                        config_file = Some(value);
+                    } else {
+                        options.push((key, value));
+                    }
+                },
+                None => {
+                    mode = Some(arg);
+                    break;
+                }
+            }
+        }
+
+        let mut config = match config_file {
+            Some(path) => Config::load(Path::new(path))?,
+            None => Config::default()
+        };
+
+        for (key, value) in options {
+            config.set(key, value)?;
+        }
+
+        if let Some(mode) = mode {
+            config.set("mode", mode)?;
+        }
+
+        Ok((config, iter.cloned().collect()))
+    }
+
+    //reads a settings file. Keys that are not in the file have their default value.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim())?,
+                None => return Err(ConfigError::Syntax(i + 1, line.to_string()))
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::InvalidValue(key.to_string(), value.to_string());
+
+        //an empty path turns the feature off
+        let path = || Some(PathBuf::from(value)).filter(|_| !value.is_empty());
+
+        match key {
+            "mode" => self.mode = RunMode::from_name(value).ok_or_else(invalid)?,
+            "hash" => self.hash = value.parse().ok().filter(|n| (MIN_TTABLE_SIZE..=MAX_TTABLE_SIZE).contains(n)).ok_or_else(invalid)?,
+            "threads" => self.threads = value.parse().ok().filter(|n| (1..=1024).contains(n)).ok_or_else(invalid)?,
+            "book" => self.book = path(),
+            "tablebases" => self.tablebases = path(),
+            "eval-file" => self.eval_file = path(),
+            "log-file" => self.log_file = path(),
+            "pv-length" => self.pv_length = value.parse().ok().filter(|n| (1..=MAX_PV_LENGTH).contains(n)).ok_or_else(invalid)?,
+            _ => return Err(ConfigError::UnknownKey(key.to_string()))
+        }
+
+        Ok(())
+    }
+
+    //creates an engine with the hash size, principal variation length, tablebases, book and evaluation parameters of the configuration
+    pub fn create_engine(&self, observer: Arc<dyn SearchObserver>) -> Result<Engine, ConfigError> {
+        let mut engine = Engine::new(self.hash, observer);
+        engine.set_pv_length(self.pv_length);
+
+        if let Some(dir) = self.tablebases.as_ref() {
+            engine.load_tablebases(dir).map_err(|e| ConfigError::Tablebases(dir.clone(), e))?;
+        }
+        if let Some(path) = self.book.as_ref() {
+            engine.load_book(path).map_err(|e| ConfigError::Book(path.clone(), e))?;
+        }
+        if let Some(path) = self.eval_file.as_ref() {
+            engine.load_eval_params(path).map_err(|e| ConfigError::EvalFile(path.clone(), e))?;
+        }
+
+        Ok(engine)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Syntax(usize, String),
+    UnknownKey(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    Tablebases(PathBuf, TablebaseError),
+    Book(PathBuf, BookError),
+    EvalFile(PathBuf, EvalFileError)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read '{}': {e}", path.display()),
+            ConfigError::Syntax(line, text) => write!(f, "line {line}: expected 'key = value', found '{text}'"),
+            ConfigError::UnknownKey(key) => write!(f, "unknown setting '{key}'"),
+            ConfigError::MissingValue(key) => write!(f, "missing value for --{key}"),
+            ConfigError::InvalidValue(key, value) => write!(f, "invalid value for {key}: '{value}'"),
+            ConfigError::Tablebases(dir, e) => write!(f, "could not load tablebases from '{}': {e}", dir.display()),
+            ConfigError::Book(path, e) => write!(f, "could not load book '{}': {e}", path.display()),
+            ConfigError::EvalFile(path, e) => write!(f, "could not load eval file '{}': {e}", path.display())
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn settings_file_and_arguments() {
+        let config = Config::parse("# engine settings\nhash = 128\n\nbook = openings.txt # a comment\nmode = xboard\n").unwrap();
+        assert_eq!(config.hash, 128);
+        assert_eq!(config.book, Some(PathBuf::from("openings.txt")));
+        assert_eq!(config.mode, RunMode::Xboard);
+        assert_eq!(config.tablebases, None);
+
+        let (config, mode_args) = Config::from_args(&args("--hash 16 --threads 2 --pv-length 8 play --color black")).unwrap();
+        assert_eq!((config.hash, config.threads, config.pv_length, config.mode), (16, 2, 8, RunMode::Play));
+        assert_eq!(mode_args, args("--color black"));
+
+        let (config, mode_args) = Config::from_args(&[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert!(mode_args.is_empty());
+
+        assert!(matches!(Config::parse("hash 16"), Err(ConfigError::Syntax(1, _))));
+        assert!(matches!(Config::parse("hash = 0"), Err(ConfigError::InvalidValue(..))));
+        assert!(matches!(Config::parse("pv-length = 0"), Err(ConfigError::InvalidValue(..))));
+        assert!(matches!(Config::from_args(&args("--hash")), Err(ConfigError::MissingValue(_))));
+        assert!(matches!(Config::from_args(&args("--colour white uci")), Err(ConfigError::UnknownKey(_))));
+        assert!(matches!(Config::from_args(&args("chess")), Err(ConfigError::InvalidValue(..))));
+    }
+}
@@ -1,18 +1,33 @@
+use std::path::Path;
 use std::sync::{atomic, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
 
 mod timer;
-mod eval;
+pub(crate) mod eval;
+mod endgame;
+mod kpk;
 mod score;
 mod ttable;
+mod observer;
+pub mod strength;
+pub mod tablebase;
+pub mod book;
 
 
-use timer::Timer;
-use score::Score;
-use ttable::{TTable, EntryType};
+use timer::{Timer, TimeScaling};
+use ttable::EntryType;
+use strength::Strength;
+use tablebase::{Tablebases, TablebaseError, Dtm};
+use book::{Book, BookError};
 
+pub use score::Score;
+pub use ttable::{TTable, TableFileError};
+pub use eval::{EvalParams, EvalFileError};
+pub use observer::{SearchObserver, SilentObserver, IterationReport, ProgressReport};
+
+use crate::bitboard::SliderAttacks;
 use crate::chess_move::*;
 use crate::position::*;
 
@@ -20,9 +35,18 @@ use self::eval::Evaluator;
 
 pub struct Engine {
     thread_data: Option<Arc<ThreadData>>,
-    worker_thread: Option<thread::JoinHandle<TTable>>,
+    worker_thread: Option<thread::JoinHandle<(TTable, MoveSorter, SearchResult)>>,
     timer: Option<Timer>,
-    ttable: Option<TTable>
+    //the transposition table and the move ordering statistics are kept from one search to the next
+    ttable: Option<TTable>,
+    move_sorter: Option<MoveSorter>,
+    tablebases: Arc<Tablebases>,
+    book: Arc<Book>,
+    eval_params: Arc<EvalParams>,
+    report_interval: Duration,
+    random_seed: Option<u64>,
+    pv_length: usize,
+    observer: Arc<dyn SearchObserver>
 }
 
 pub struct ThreadData {
@@ -32,26 +56,73 @@ pub struct ThreadData {
     start_time: Instant,
     max_time: Mutex<Option<Duration>>,
     min_time: Mutex<Option<Duration>>,
+    //the scaled soft limit after the last iteration, measured from start_time
+    soft_limit: Mutex<Option<Duration>>,
+
+    //successful tablebase probes of all threads searching this position
+    tbhits: atomic::AtomicU64,
 
     position: Position,
-    options: EngineOptions
+    options: EngineOptions,
+    tablebases: Arc<Tablebases>,
+    book: Arc<Book>,
+    eval_params: Arc<EvalParams>,
+    report_interval: Duration,
+    random_seed: Option<u64>,
+    pv_length: usize,
+    observer: Arc<dyn SearchObserver>
 }
 
 struct SearchData {
     pv: Vec<Move>,
     evaluator: Evaluator,
     nodes: u64,
+    seldepth: u16,
     move_sorter: MoveSorter,
-    ttable: TTable
+    ttable: TTable,
+
+    //root moves that are skipped when searching the further principal variations of a weakened engine
+    excluded_root_moves: Vec<Move>,
+    eval_noise: i32,
+    noise_seed: u64,
+
+    //score of a claimable draw for the side to move at the root
+    root_draw_score: Score,
+
+    //the ply and the table move of the node that is searched without that move for a singular extension
+    singular_exclusion: Option<(u16, Move)>,
+
+    last_progress_report: Instant
 }
 
 struct MoveSorter {
-    killer_moves: Vec<(Move, Move)>,
+    killer_moves: [(Move, Move); MAX_PLY as usize],
+    //quiet moves that caused cut-offs by player, origin and target square, weighted by depth
+    history: Box<[[[i32; 64]; 64]; 2]>
 }
 
 const DRAW_SCORE: Score = Score { s: 0 };
 
-#[derive(Debug, Clone)]
+//iterative deepening stops here, e.g. if every root move leads to a known draw
+const MAX_DEPTH: u16 = 100;
+
+//the search does not go deeper than this, whatever the extensions. Nodes at this ply return the static evaluation.
+const MAX_PLY: u16 = 128;
+
+//singular extensions need this depth, and a table entry that is at most 3 plies shallower
+const SINGULAR_MIN_DEPTH: u16 = 8;
+
+//internal iterative deepening and reductions for nodes without a table move need this depth
+const IID_MIN_DEPTH: u16 = 5;
+
+//moves of the principal variation that are taken from the transposition table after each iteration. A longer variation can't come from the search.
+pub const DEFAULT_PV_LENGTH: usize = 20;
+pub const MAX_PV_LENGTH: usize = MAX_PLY as usize;
+
+//by default, the current root move and the periodic progress reports are only sent after the search has been running this long
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Default)]
 pub struct EngineOptions {
     pub search_moves: Vec<Move>,
     pub ponder: bool,
@@ -64,69 +135,159 @@ pub struct EngineOptions {
     pub depth: Option<u64>,
     pub nodes: Option<u64>,
     pub mate_in: Option<u64>,
-    pub move_time: Option<u64>
+    pub move_time: Option<u64>,
+
+    //weakened play, see strength.rs. None or strength::MAX_SKILL_LEVEL for full strength.
+    pub skill_level: Option<u8>,
+
+    //centipawns the engine is willing to give up to avoid a draw by repetition, the 50 move rule or
+    //insufficient material. Negative values make the engine prefer these draws.
+    pub contempt: i16,
+    //draws are scored symmetrically when analysing, contempt is ignored
+    pub analyse_mode: bool,
+
+    //milliseconds that are subtracted from the time of every move to compensate gui and network lag
+    pub move_overhead: u64,
+
+    //the engine ponders on the opponent's time, so it can use a bit more time for its own moves
+    pub pondering_enabled: bool
+}
+
+//result of a search. score, depth and pv belong to the last completed iteration. score is None if no iteration was completed.
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub ponder_move: Option<Move>,
+    pub score: Option<Score>,
+    pub depth: u16,
+    pub seldepth: u16,
+    pub nodes: u64,
+    pub tbhits: u64,
+    pub pv: Vec<Move>
 }
 
 impl Engine {
-    const MAX_PV_LENGTH: usize = 20;
+    pub fn new(mb_table_size: usize, observer: Arc<dyn SearchObserver>) -> Engine {
+        SliderAttacks::init();
+        kpk::init();
 
-    pub fn new(mb_table_size: usize) -> Engine {
         Engine {
             thread_data: None,
             worker_thread: None,
             timer: None,
-            ttable: Some(TTable::new(mb_table_size))
+            ttable: Some(TTable::new(mb_table_size)),
+            move_sorter: Some(MoveSorter::new()),
+            tablebases: Arc::new(Tablebases::new()),
+            book: Arc::new(Book::new()),
+            eval_params: Arc::new(EvalParams::default()),
+            report_interval: REPORT_INTERVAL,
+            random_seed: None,
+            pv_length: DEFAULT_PV_LENGTH,
+            observer
         }
     }
 
     pub fn start(&mut self, position: Position, options: EngineOptions) {
         self.stop();
 
+        self.thread_data = Some(self.new_thread_data(position, options));
+
+        self.timer = Timer::new(self.thread_data.clone().unwrap());
+        
+
+        let thread_data_ref = self.thread_data.clone().unwrap();
+        let ttable = self.ttable.take().unwrap();
+        let move_sorter = self.move_sorter.take().unwrap();
+
+        self.worker_thread = Some(thread::spawn(move || Engine::analyze(thread_data_ref, ttable, move_sorter)));
+    }
+
+    /**
+     * searches the position on the calling thread and blocks until the search is finished. The search
+     * has to be limited by depth, nodes, move time or the clock, 'ponder' is ignored and an 'infinite'
+     * search without other limits never returns. The observer is notified like for asynchronous searches.
+     */
+    pub fn search_sync(&mut self, position: Position, mut options: EngineOptions) -> SearchResult {
+        self.stop();
+
+        options.ponder = false;
+        let thread_data = self.new_thread_data(position, options);
+
+        let timer = Timer::new(thread_data.clone());
+
+        let (ttable, move_sorter, result) = Engine::analyze(thread_data, self.ttable.take().unwrap(), self.move_sorter.take().unwrap());
+        
+        drop(timer);
+        self.ttable = Some(ttable);
+        self.move_sorter = Some(move_sorter);
+
+        result
+    }
 
-        self.thread_data = Some(Arc::new(ThreadData {
+    fn new_thread_data(&self, position: Position, mut options: EngineOptions) -> Arc<ThreadData> {
+        if let Some(strength) = options.skill_level.and_then(Strength::new) {
+            options.nodes = Some(options.nodes.unwrap_or(u64::MAX).min(strength.node_limit()));
+        }
+
+        Arc::new(ThreadData {
             stop: atomic::AtomicBool::new(false),
             ponder: atomic::AtomicBool::new(options.ponder),
 
             start_time: Instant::now(),
             max_time: Mutex::new(None),
             min_time: Mutex::new(None),
+            soft_limit: Mutex::new(None),
 
-            position,
-            options
-        }));
-
-
-        self.timer = Timer::new(self.thread_data.clone().unwrap());
-        
-
-        let thread_data_ref = self.thread_data.clone().unwrap();
-        let ttable = self.ttable.take().unwrap();
+            tbhits: atomic::AtomicU64::new(0),
 
-        self.worker_thread = Some(thread::spawn(move || {
-            Engine::analyze(thread_data_ref, ttable)
-        }));
+            position,
+            options,
+            tablebases: self.tablebases.clone(),
+            book: self.book.clone(),
+            eval_params: self.eval_params.clone(),
+            report_interval: self.report_interval,
+            random_seed: self.random_seed,
+            pv_length: self.pv_length,
+            observer: self.observer.clone()
+        })
     }
 
-    pub fn stop(&mut self) {    
-        match self.worker_thread.take() {
-            None => (),
-            Some(handle) => {
-                self.thread_data.as_ref().unwrap().stop.store(true, atomic::Ordering::Release);
-                let ttable = handle.join().expect("error when joining worker thread");
-                if self.ttable.is_none() {
-                    self.ttable = Some(ttable);
-                }
+    //stops the search that was started with start and returns its result, None if no search was running
+    pub fn stop(&mut self) -> Option<SearchResult> {
+        let result = self.worker_thread.take().map(|handle| {
+            self.thread_data.as_ref().unwrap().stop.store(true, atomic::Ordering::Release);
+            let (ttable, move_sorter, result) = handle.join().expect("error when joining worker thread");
+            if self.ttable.is_none() {
+                self.ttable = Some(ttable);
             }
-        }
+            if self.move_sorter.is_none() {
+                self.move_sorter = Some(move_sorter);
+            }
+            result
+        });
 
         self.thread_data = None;
         self.timer = None;
+
+        result
     }
 
+    /**
+     * the opponent played the expected move, the search continues on the clock. The time spent
+     * pondering counts towards the soft limit, so the search stops right away if it already thought
+     * long enough. The clock only runs from now on, so the hard limit is measured from now.
+     */
     pub fn ponderhit(&mut self) {
         if let Some(thread_data) = self.thread_data.as_ref() {
             thread_data.ponder.store(false, atomic::Ordering::Release);
-            self.timer = Timer::new(thread_data.clone());
+
+            let soft_limit = *thread_data.soft_limit.lock().unwrap();
+            if soft_limit.is_some_and(|limit| thread_data.start_time.elapsed() >= limit) {
+                thread_data.observer.debug("soft time limit already reached when pondering, search finished");
+                thread_data.stop.store(true, atomic::Ordering::Release);
+            } else {
+                self.timer = Timer::new(thread_data.clone());
+            }
         }
     }
 
@@ -136,13 +297,91 @@ impl Engine {
         }
     }
 
+    //the size of the transposition table in MB, None while it is used by a search
+    pub fn table_size(&self) -> Option<usize> {
+        self.ttable.as_ref().map(|t| t.mb_size())
+    }
+
+    //replaces the transposition table by one that was allocated elsewhere, e.g. on another thread because a large table takes a while
+    pub fn set_table(&mut self, table: TTable) {
+        self.ttable = Some(table);
+    }
+
+    //forgets the transposition table and the move ordering statistics, e.g. for a new game
     pub fn clear_table(&mut self) {
         if let Some(table) = self.ttable.as_mut() {
             table.clear();
         }
+        if self.move_sorter.is_some() {
+            self.move_sorter = Some(MoveSorter::new());
+        }
     }
 
-    fn analyze(thread_data: Arc<ThreadData>, ttable: TTable) -> TTable {       
+    //saves the transposition table to a file. A running search is stopped.
+    pub fn save_table(&mut self, path: &Path) -> Result<(), TableFileError> {
+        self.stop();
+        self.ttable.as_ref().expect("the table is returned when the search stops").save(path)
+    }
+
+    //replaces the transposition table with one saved by save_table. A running search is stopped.
+    pub fn load_table(&mut self, path: &Path, max_mb_size: usize) -> Result<(), TableFileError> {
+        self.stop();
+        self.ttable = Some(TTable::load(path, max_mb_size)?);
+        Ok(())
+    }
+
+    //the tablebases are used from the next search on
+    pub fn set_tablebases(&mut self, tablebases: Arc<Tablebases>) {
+        self.tablebases = tablebases;
+    }
+
+    //loads all tablebase files in the directory and returns the number of tables
+    pub fn load_tablebases(&mut self, dir: &Path) -> Result<usize, TablebaseError> {
+        let tablebases = Tablebases::load(dir)?;
+        let tables = tablebases.tables().len();
+        self.set_tablebases(Arc::new(tablebases));
+        Ok(tables)
+    }
+
+    //the book is used from the next search on. An empty book turns it off.
+    pub fn set_book(&mut self, book: Arc<Book>) {
+        self.book = book;
+    }
+
+    //loads an opening book and returns the number of book positions
+    pub fn load_book(&mut self, path: &Path) -> Result<usize, BookError> {
+        let book = Book::load(path)?;
+        let positions = book.len();
+        self.set_book(Arc::new(book));
+        Ok(positions)
+    }
+
+    //how long a search runs before the current root move and the periodic progress are reported, from the next search on
+    pub fn set_report_interval(&mut self, interval: Duration) {
+        self.report_interval = interval;
+    }
+
+    //seeds the random choices of the book and of weakened play, mixed with the position hash, from the next search on. None seeds them from the clock.
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.random_seed = seed;
+    }
+
+    //the maximum length of the reported principal variations, from 1 to MAX_PV_LENGTH, from the next search on
+    pub fn set_pv_length(&mut self, length: usize) {
+        self.pv_length = length.clamp(1, MAX_PV_LENGTH);
+    }
+
+    //the evaluation parameters are used from the next search on
+    pub fn set_eval_params(&mut self, params: Arc<EvalParams>) {
+        self.eval_params = params;
+    }
+
+    pub fn load_eval_params(&mut self, path: &Path) -> Result<(), EvalFileError> {
+        self.set_eval_params(Arc::new(EvalParams::load(path)?));
+        Ok(())
+    }
+
+    fn analyze(thread_data: Arc<ThreadData>, ttable: TTable, mut move_sorter: MoveSorter) -> (TTable, MoveSorter, SearchResult) {
 
         //TODO check for mate in start position
 
@@ -150,132 +389,303 @@ impl Engine {
 
         let mut depth: u16 = 1;
 
+        let mut strength = thread_data.options.skill_level.and_then(Strength::new);
+        if let (Some(s), Some(seed)) = (strength.as_mut(), thread_data.random_seed) {
+            s.set_seed(seed ^ position.hash());
+        }
+
         let mut data = SearchData {
             pv: Vec::new(),
-            evaluator: Evaluator::new(),
+            evaluator: Evaluator::new(&thread_data.eval_params),
             nodes: 0,
-            move_sorter: MoveSorter::new(),
-            ttable
+            seldepth: 0,
+            move_sorter: {
+                move_sorter.age();
+                move_sorter
+            },
+            ttable,
+            excluded_root_moves: Vec::new(),
+            eval_noise: strength.as_ref().map_or(0, |s| s.eval_noise()),
+            noise_seed: strength.as_ref().map_or(0, |s| s.noise_seed()),
+            root_draw_score: if thread_data.options.analyse_mode {
+                DRAW_SCORE
+            } else {
+                Score::from_centi_pawns(-(thread_data.options.contempt as i32))
+            },
+            singular_exclusion: None,
+            last_progress_report: thread_data.start_time
         };
 
+        let observer = &thread_data.observer;
+        let time_limits = (*thread_data.min_time.lock().unwrap(), *thread_data.max_time.lock().unwrap());
+        match time_limits {
+            (Some(min_time), Some(max_time)) => observer.debug(&format!("time limits: min {} ms, max {} ms", min_time.as_millis(), max_time.as_millis())),
+            _ => observer.debug("no time limit")
+        }
+        if let Some(s) = strength.as_ref() {
+            observer.debug(&format!("weakened play: node limit {}, eval noise {} cp, {} principal variations", s.node_limit(), s.eval_noise(), s.multi_pv()));
+        }
+
+        //book moves are played without a search, but not when pondering or analysing
+        let options = &thread_data.options;
+        let use_book = !options.ponder && !options.infinite && !options.analyse_mode && options.search_moves.is_empty() && options.mate_in.is_none();
+        let book_move = if use_book { thread_data.book.probe(&mut position, thread_data.random_seed) } else { None };
+        if let Some(m) = book_move {
+            observer.debug(&format!("book move {m}"));
+            observer.best_move(Some(m), None);
+
+            let result = SearchResult { best_move: Some(m), ponder_move: None, score: None, depth: 0, seldepth: 0, nodes: 0, tbhits: 0, pv: vec![m] };
+            return (data.ttable, data.move_sorter, result);
+        }
+
+        let mut root_moves = position.legal_moves();
+        if !thread_data.options.search_moves.is_empty() {
+            root_moves.retain(|m| thread_data.options.search_moves.contains(&m));
+        }
+        let multi_pv = strength.as_ref().map_or(1, |s| s.multi_pv()).min(root_moves.len().max(1));
+
+        let mut time_scaling = TimeScaling::new(root_moves.len());
+
         let mut pv: Vec<Move> = Vec::new();
+        let mut result_score = None;
+        let mut result_depth = 0;
+        let mut result_seldepth = 0;
+
+        //the best root moves of the last completed iteration with their scores, only used by a weakened engine
+        let mut candidates: Vec<(Move, Score)> = Vec::new();
 
-        loop {
+        'iterative_deepening: loop {
             data.pv = Vec::new();
+            data.seldepth = 0;
+            data.excluded_root_moves.clear();
 
             let score = match Engine::search(&mut position, depth, 0, Score::NEGATIVE_INFTY, Score::POSITIVE_INFTY, true, &mut data, &thread_data) {
-                None => break,
+                None => {
+                    observer.debug(&format!("search stopped during iteration {depth}"));
+                    break;
+                },
                 Some(s) => s
             };
 
-            pv = Self::extract_pv(&mut position, &mut data.ttable);
+            pv = Self::extract_pv(&mut position, &mut data.ttable, thread_data.pv_length);
+            result_score = Some(score);
+            result_depth = depth;
+            result_seldepth = data.seldepth;
 
-            let search_time = Instant::now().duration_since(thread_data.start_time);
-            let search_time_ms = search_time.as_millis() as u64;
+            //search the further principal variations with the better moves excluded
+            let mut iteration_candidates = Vec::new();
+            if let Some(&m) = data.pv.last() {
+                iteration_candidates.push((m, score));
+            }
 
-            //print info about search depth
+            while iteration_candidates.len() < multi_pv {
+                data.excluded_root_moves = iteration_candidates.iter().map(|(m, _)| *m).collect();
+                data.pv = Vec::new();
 
-            print!("info depth {depth}");
-            if let Some(s) = score.centi_pawns() {
-                print!(" score cp {s}");
-            } else {
-                print!(" score mate {}", score.mate().unwrap());
-            }
+                let score = match Engine::search(&mut position, depth, 0, Score::NEGATIVE_INFTY, Score::POSITIVE_INFTY, true, &mut data, &thread_data) {
+                    None => break 'iterative_deepening,
+                    Some(s) => s
+                };
 
-            print!(" nodes {}", data.nodes);
-            print!(" time {}", search_time_ms);
-            if search_time_ms > 50 {
-                print!(" nps {}", data.nodes * 1000 / search_time_ms);
+                match data.pv.last() {
+                    Some(&m) => iteration_candidates.push((m, score)),
+                    None => break
+                }
             }
+            candidates = iteration_candidates;
 
-            print!(" pv");
-            for m in pv.iter() {
-                print!(" {m}");
-            }
+            let search_time = Instant::now().duration_since(thread_data.start_time);
 
-            println!();
+            thread_data.observer.iteration_complete(&IterationReport {
+                depth,
+                seldepth: data.seldepth,
+                score,
+                nodes: data.nodes,
+                tbhits: thread_data.tbhits.load(atomic::Ordering::Relaxed),
+                time: search_time,
+                hashfull: data.ttable.hashfull(),
+                pv: pv.clone()
+            });
 
             //end search if we found a mate
             if score.mate().is_some() {
+                observer.debug("mate found, search finished");
                 break;
             }
 
-            //end search if we have been searching longer than min_time
-            if let Some(min_time) = *thread_data.min_time.lock().unwrap() {
-                if search_time >= min_time {
-                    break;
+            time_scaling.iteration_complete(pv.first().copied(), score);
+
+            //end search if we have been searching longer than the soft time limit. While pondering, the limit is only kept for the ponder hit.
+            let time_limits = (*thread_data.min_time.lock().unwrap(), *thread_data.max_time.lock().unwrap());
+            if let (Some(min_time), Some(max_time)) = time_limits {
+                //a fixed move time is not scaled
+                let soft_limit = if time_scaling.single_move() {
+                    Duration::ZERO
+                } else if thread_data.options.move_time.is_some() {
+                    min_time
+                } else {
+                    time_scaling.soft_limit(min_time, max_time)
+                };
+                *thread_data.soft_limit.lock().unwrap() = Some(soft_limit);
+
+                if !thread_data.ponder.load(atomic::Ordering::Acquire) {
+                    if time_scaling.single_move() {
+                        observer.debug("only one legal move, search finished");
+                        break;
+                    }
+
+                    if search_time >= soft_limit {
+                        observer.debug(&format!("soft time limit of {} ms reached after iteration {depth} (factor {:.2})", soft_limit.as_millis(), time_scaling.factor()));
+                        break;
+                    }
                 }
             }
 
             if let Some(max_depth) = thread_data.options.depth {
                 if max_depth == depth as u64 {
+                    observer.debug("depth limit reached");
                     break;
                 }
             }
 
+            if depth == MAX_DEPTH {
+                observer.debug("maximum depth reached");
+                break;
+            }
+
             depth += 1;
         }
 
-        print!("bestmove");
-        if pv.len() > 0 {
-            print!(" {}", pv[0]);
-        } else {
-            print!(" {}", thread_data.position.clone().legal_moves()[0])
+        //the best move of a pondering or infinite search must not be reported before 'ponderhit' or 'stop', even if the search ended with a mate
+        while (thread_data.options.infinite || thread_data.ponder.load(atomic::Ordering::Acquire)) && !thread_data.stop.load(atomic::Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
         }
-        if pv.len() > 1 {
-            print!(" ponder {}", pv[1]);
+
+        let mut best_move = pv.first().copied().or_else(|| thread_data.position.clone().legal_moves().first().copied());
+        let mut ponder_move = pv.get(1).copied();
+
+        if let Some(m) = strength.as_mut().and_then(|s| s.pick_move(&candidates)) {
+            if best_move != Some(m) {
+                best_move = Some(m);
+                ponder_move = None;
+            }
         }
-        println!();
 
-        data.ttable
+        let (probes, hits) = data.evaluator.pawn_cache_stats();
+        if probes > 0 {
+            observer.debug(&format!("pawn cache: {hits} hits in {probes} probes ({:.1}%)", hits as f64 * 100.0 / probes as f64));
+        }
+
+        thread_data.observer.best_move(best_move, ponder_move);
+
+        let result = SearchResult {
+            best_move,
+            ponder_move,
+            score: result_score,
+            depth: result_depth,
+            seldepth: result_seldepth,
+            nodes: data.nodes,
+            tbhits: thread_data.tbhits.load(atomic::Ordering::Relaxed),
+            pv
+        };
+
+        (data.ttable, data.move_sorter, result)
     }
 
-    fn search(position: &mut Position, depth: u16, ply: u16, mut alpha: Score, beta: Score, pv_node: bool, data: &mut SearchData, thread_data: &ThreadData) -> Option<Score> {
+    #[allow(clippy::too_many_arguments)]
+    fn search(position: &mut Position, mut depth: u16, ply: u16, mut alpha: Score, beta: Score, pv_node: bool, data: &mut SearchData, thread_data: &ThreadData) -> Option<Score> {
         
-        if thread_data.stop.load(atomic::Ordering::Acquire) {
+        //a search with a node limit stops at the same node as a search that was stopped after that many nodes
+        if thread_data.stop.load(atomic::Ordering::Acquire) || thread_data.options.nodes.unwrap_or(u64::MAX) <= data.nodes {
             return None;
         }
 
         data.nodes += 1;
+        data.seldepth = data.seldepth.max(ply);
 
-        if thread_data.options.nodes.unwrap_or(u64::MAX) < data.nodes {
-            return None
+        if data.nodes.is_multiple_of(4096) {
+            Self::report_progress(data, thread_data);
         }
 
-        if depth == 0 {
-            return Self::qsearch(position, ply, alpha, beta, pv_node, data, thread_data);
+        //check extension
+        if position.in_check() {
+            depth += 1;
         }
 
-        let moves = if ply != 0 || thread_data.options.search_moves.is_empty() {
-            position.legal_moves()
-        } else {
-            position.legal_moves().into_iter().filter(|m| thread_data.options.search_moves.contains(m)).collect()
-        };
+        if depth == 0 || ply >= MAX_PLY {
+            return Self::qsearch(position, ply, alpha, beta, data, thread_data);
+        }
+
+        let mut moves = position.legal_moves();
+        if ply == 0 && !thread_data.options.search_moves.is_empty() {
+            moves.retain(|m| thread_data.options.search_moves.contains(&m));
+        }
+        if ply == 0 && !data.excluded_root_moves.is_empty() {
+            moves.retain(|m| !data.excluded_root_moves.contains(&m));
+        }
+        //the table entry belongs to the best move, it must not be used or overwritten when the best moves are excluded
+        let excluded_move = data.singular_exclusion.filter(|(p, _)| *p == ply).map(|(_, m)| m);
+        let use_ttable = (ply > 0 || data.excluded_root_moves.is_empty()) && excluded_move.is_none();
 
         if moves.is_empty() {
             if position.in_check() {
-                return Some(Score::from_mate_distance(-(((ply+1)/2) as i16)));
+                return Some(Score::from_mate_distance(-(ply.div_ceil(2) as i16)));
             } else {
                 return Some(DRAW_SCORE);
             }
         }
 
-        if position.insufficient_material() || position.has_repetition(ply) || position.half_move_clock() >= 100 {
-            return Some(DRAW_SCORE);
-        }  
+        //the root position is searched even if it is a draw, the engine still has to play a move when the draw isn't claimed
+        if ply > 0 && (position.insufficient_material() || position.has_repetition(ply) || position.half_move_clock() >= 100) {
+            return Some(data.draw_score(ply));
+        }
 
-        let ttable_move;
+        //the side to move can repeat a position, so it can get at least a draw
+        if ply > 0 && alpha < data.draw_score(ply) && position.has_upcoming_repetition(ply) {
+            alpha = data.draw_score(ply);
+            if alpha >= beta {
+                return Some(alpha);
+            }
+        }
+
+        //drawn king and pawn against king endgames need no search
+        if ply > 0 && kpk::probe_position(position) == Some(false) {
+            return Some(data.draw_score(ply));
+        }
+
+        //positions in the tablebases have an exact score
+        if ply > 0 {
+            if let Some(dtm) = thread_data.tablebases.probe(position) {
+                thread_data.tbhits.fetch_add(1, atomic::Ordering::Relaxed);
+                return Some(match dtm {
+                    Dtm::Draw => data.draw_score(ply),
+                    Dtm::Win(d) => Score::from_mate_distance((ply + d as u16).div_ceil(2) as i16),
+                    Dtm::Loss(d) => Score::from_mate_distance(-((ply + d as u16).div_ceil(2) as i16))
+                });
+            }
+        }
+
+        if let Some(excluded_move) = excluded_move {
+            moves.retain(|m| m != excluded_move);
+            if moves.is_empty() {
+                return Some(alpha);
+            }
+        }
 
         //transposition table look up
-        if let Some(table_entry) = data.ttable.lookup(position.hash()) {
-            if table_entry.depth == depth {
+        let table_entry = data.ttable.lookup(position.hash()).filter(|_| use_ttable).copied();
+        if let Some(table_entry) = table_entry {
+            //the stored scores don't know about the 50 move rule, so they are not trusted when it is close. The table move is still used.
+            if table_entry.depth == depth && position.half_move_clock() < 90 {
                 match table_entry.entry_type {
-                    ttable::EntryType::Exact => {
-                        if ply == 0 {
+                    //a table loaded from a file can hold the move of another position with the same hash
+                    ttable::EntryType::Exact if ply == 0 => {
+                        if moves.contains(&table_entry.best_move) {
                             data.pv.push(table_entry.best_move);
+                            return Some(table_entry.score);
                         }
-                        return Some(table_entry.score)
                     },
+                    ttable::EntryType::Exact => return Some(table_entry.score),
                     ttable::EntryType::Upper => {
                         if alpha >= table_entry.score {
                             return Some(table_entry.score);
@@ -288,27 +698,63 @@ impl Engine {
                     }
                 }
             }
+        }
 
-            ttable_move = Some(table_entry.best_move);
-        } else {
-            ttable_move = None;
+        let mut ttable_move = table_entry.map(|e| e.best_move);
+
+        //without a table move, pv nodes search a move to try first and the other nodes are reduced
+        if ttable_move.is_none() && ply > 0 && use_ttable && depth >= IID_MIN_DEPTH {
+            if pv_node {
+                Self::search(position, depth - 2, ply, alpha, beta, true, data, thread_data)?;
+                ttable_move = data.ttable.lookup(position.hash()).map(|e| e.best_move);
+            } else {
+                depth -= 1;
+            }
+        }
+
+        /*
+         * singular extension: the table move is extended if all other moves fail low against a
+         * margin below its score in a search with reduced depth. There is only one such search at a time.
+         */
+        let mut singular_move = None;
+        if let Some(entry) = table_entry.filter(|e| ply > 0 && data.singular_exclusion.is_none() && depth >= SINGULAR_MIN_DEPTH
+                && e.entry_type != EntryType::Upper && e.depth + 3 >= depth && moves.len() > 1 && moves.contains(&e.best_move)) {
+            if let Some(centi_pawns) = entry.score.centi_pawns() {
+                let singular_beta = Score::from_centi_pawns(centi_pawns as i32 - 2 * depth as i32);
+
+                data.singular_exclusion = Some((ply, entry.best_move));
+                let score = Self::search(position, depth / 2, ply, Score { s: singular_beta.s - 1 }, singular_beta, false, data, thread_data);
+                data.singular_exclusion = None;
+
+                if score? < singular_beta {
+                    singular_move = Some(entry.best_move);
+                }
+            }
         }
 
         let mut best_move = moves[0];
 
-        for (i, m) in data.move_sorter.sort(position, moves, ply, ttable_move).into_iter().enumerate() {
+        data.move_sorter.sort(position, &mut moves, ply, ttable_move);
+
+        for (i, m) in moves.into_iter().enumerate() {
+            if ply == 0 && thread_data.start_time.elapsed() >= thread_data.report_interval {
+                thread_data.observer.current_move(m, i + 1, depth);
+            }
+
+            let new_depth = if Some(m) == singular_move { depth } else { depth - 1 };
+
             position.make_move(m);
 
             let mut move_score;
             
 
             if pv_node && i != 0 {
-                move_score = -Engine::search(position, depth - 1, ply + 1, - (Score { s: (alpha.s + 1) }), -alpha, false, data, thread_data)?;
+                move_score = -Engine::search(position, new_depth, ply + 1, - (Score { s: (alpha.s + 1) }), -alpha, false, data, thread_data)?;
                 if move_score > alpha {
-                    move_score = -Engine::search(position, depth - 1, ply + 1, -beta, -alpha, true,  data, thread_data)?;
+                    move_score = -Engine::search(position, new_depth, ply + 1, -beta, -alpha, true,  data, thread_data)?;
                 }
             } else {
-                move_score = -Engine::search(position, depth - 1, ply + 1, -beta, -alpha, pv_node, data, thread_data)?;
+                move_score = -Engine::search(position, new_depth, ply + 1, -beta, -alpha, pv_node, data, thread_data)?;
             }
 
 
@@ -320,19 +766,23 @@ impl Engine {
 
                 if alpha >= beta {
 
-                    data.move_sorter.cut_off_move(m, ply);
+                    data.move_sorter.cut_off_move(position, m, ply, depth);
 
-                    data.ttable.insert(position.hash(), EntryType::Lower, move_score, best_move, depth);
+                    if use_ttable {
+                        data.ttable.insert(position.hash(), EntryType::Lower, move_score, best_move, depth);
+                    }
                     
                     return Some(alpha);
                 }
             }
         }
 
-        if pv_node {
-            data.ttable.insert(position.hash(), EntryType::Exact, alpha, best_move, depth);
-        } else {
-            data.ttable.insert(position.hash(), EntryType::Upper, alpha, Move::new(0,0), depth);
+        if use_ttable {
+            if pv_node {
+                data.ttable.insert(position.hash(), EntryType::Exact, alpha, best_move, depth);
+            } else {
+                data.ttable.insert(position.hash(), EntryType::Upper, alpha, Move::new(0,0), depth);
+            }
         }
 
         if ply == 0 {
@@ -342,34 +792,50 @@ impl Engine {
         Some(alpha)
     }
 
-    fn qsearch(position: &mut Position, ply: u16, mut alpha: Score, beta: Score, pv_node: bool, data: &mut SearchData, thread_data: &ThreadData) -> Option<Score> {
-        if thread_data.stop.load(atomic::Ordering::Acquire) {
+    fn qsearch(position: &mut Position, ply: u16, mut alpha: Score, beta: Score, data: &mut SearchData, thread_data: &ThreadData) -> Option<Score> {
+        //a search with a node limit stops at the same node as a search that was stopped after that many nodes
+        if thread_data.stop.load(atomic::Ordering::Acquire) || thread_data.options.nodes.unwrap_or(u64::MAX) <= data.nodes {
             return None;
         }
 
         data.nodes += 1;
+        data.seldepth = data.seldepth.max(ply);
 
-        if thread_data.options.nodes.unwrap_or(u64::MAX) < data.nodes {
-            return None
+        if data.nodes.is_multiple_of(4096) {
+            Self::report_progress(data, thread_data);
         }
 
-        let moves = position.legal_moves();
+        let mut moves = position.legal_moves();
         
         if moves.is_empty() {
             if position.in_check() {
-                return Some(Score::from_mate_distance(-(((ply+1)/2) as i16)));
+                return Some(Score::from_mate_distance(-(ply.div_ceil(2) as i16)));
             } else {
                 return Some(DRAW_SCORE);
             }
         }
 
         if position.insufficient_material() || position.has_repetition(ply) || position.half_move_clock() >= 100 {
-            return Some(DRAW_SCORE);
+            return Some(data.draw_score(ply));
+        }
+
+        if alpha < data.draw_score(ply) && position.has_upcoming_repetition(ply) {
+            alpha = data.draw_score(ply);
+            if alpha >= beta {
+                return Some(alpha);
+            }
         }
 
         let in_check = position.in_check();
 
-        let standing_pat = data.evaluator.evaluate(position);
+        let mut standing_pat = data.evaluator.evaluate(position);
+        if data.eval_noise > 0 {
+            standing_pat = standing_pat + Score::from_centi_pawns(Strength::noise(data.noise_seed, position.hash(), data.eval_noise));
+        }
+
+        if ply >= MAX_PLY {
+            return Some(standing_pat);
+        }
 
         if !in_check {
             if standing_pat >= beta {
@@ -380,12 +846,13 @@ impl Engine {
             }
         }
 
-        let moves = position.legal_moves();
-        for m in MoveSorter::sort_qsearch(position, moves).into_iter() {
+        MoveSorter::sort_qsearch(position, &mut moves);
+
+        for m in moves.into_iter() {
 
             if !in_check {
 
-                if !position.is_capture(m) && !m.promote_to().is_some() {
+                if !position.is_capture(m) && m.promote_to().is_none() {
                     continue;
                 }
 
@@ -410,7 +877,7 @@ impl Engine {
 
             position.make_move(m);
 
-            let score = -Self::qsearch(position, ply + 1, -beta, -alpha, pv_node, data, thread_data)?;
+            let score = -Self::qsearch(position, ply + 1, -beta, -alpha, data, thread_data)?;
 
             position.unmake_move(m);
 
@@ -426,12 +893,28 @@ impl Engine {
         Some(alpha)
     }
 
-    fn extract_pv(pos: &mut Position, ttable: &mut TTable) -> Vec<Move> {
+    fn report_progress(data: &mut SearchData, thread_data: &ThreadData) {
+        let now = Instant::now();
+        if now.duration_since(data.last_progress_report) < thread_data.report_interval {
+            return;
+        }
+        data.last_progress_report = now;
+
+        thread_data.observer.progress(&ProgressReport {
+            nodes: data.nodes,
+            tbhits: thread_data.tbhits.load(atomic::Ordering::Relaxed),
+            time: now.duration_since(thread_data.start_time),
+            hashfull: data.ttable.hashfull()
+        });
+    }
+
+    fn extract_pv(pos: &mut Position, ttable: &mut TTable, max_length: usize) -> Vec<Move> {
         let mut pv = Vec::new();
         
-        for _ in 0..Engine::MAX_PV_LENGTH {
+        for _ in 0..max_length {
             if let Some(entry) = ttable.lookup(pos.hash()) {
-                if entry.entry_type == EntryType::Exact {
+                //the moves are checked, because the table can come from a file
+                if entry.entry_type == EntryType::Exact && pos.legal_moves().contains(&entry.best_move) {
                     pv.push(entry.best_move);
                     pos.make_move(entry.best_move);
                 } else {
@@ -461,6 +944,17 @@ impl Engine {
 }
 
 
+impl SearchData {
+    //the draw score from the point of view of the side to move at the given ply
+    fn draw_score(&self, ply: u16) -> Score {
+        if ply.is_multiple_of(2) {
+            self.root_draw_score
+        } else {
+            -self.root_draw_score
+        }
+    }
+}
+
 impl Drop for Engine {
     fn drop(&mut self) {
         self.stop();
@@ -468,13 +962,24 @@ impl Drop for Engine {
 }
 
 impl MoveSorter {
+    //history values are kept below this, so that they can't overflow
+    const MAX_HISTORY: i32 = 1 << 24;
+
     pub fn new() -> MoveSorter {
         MoveSorter {
-            killer_moves: Vec::new()
+            killer_moves: [(Move::new(0,0), Move::new(0,0)); MAX_PLY as usize],
+            history: Box::new([[[0; 64]; 64]; 2])
         }
     }
 
-    fn move_to_front(moves: &mut [Move], m: Move, front_index: &mut usize) {
+    //called before every search. The history of earlier searches still helps, but less than the new one.
+    pub fn age(&mut self) {
+        for value in self.history.iter_mut().flatten().flatten() {
+            *value /= 2;
+        }
+    }
+
+    fn move_to_front(moves: &mut MoveList, m: Move, front_index: &mut usize) {
         for i in *front_index..moves.len() {
             if moves[i] == m 
             {
@@ -484,35 +989,39 @@ impl MoveSorter {
         }
     }
 
-    pub fn sort(&mut self, position: &mut Position, mut moves: Vec<Move>, ply: u16, ttable_move: Option<Move>) -> Vec<Move> {
+    pub fn sort(&mut self, position: &mut Position, moves: &mut MoveList, ply: u16, ttable_move: Option<Move>) {
         let mut sorted_moves = 0;
         
         if let Some(m) = ttable_move {
-            Self::move_to_front(&mut moves, m, &mut sorted_moves);
+            Self::move_to_front(moves, m, &mut sorted_moves);
         }
 
-        if self.killer_moves.len() > ply as usize {
-            Self::move_to_front(&mut moves, self.killer_moves[ply as usize].0, &mut sorted_moves);
-            Self::move_to_front(&mut moves, self.killer_moves[ply as usize].1, &mut sorted_moves);
-        } else {
-            self.killer_moves.push((Move::new(0,0), Move::new(0,0)));
-        }
+        Self::move_to_front(moves, self.killer_moves[ply as usize].0, &mut sorted_moves);
+        Self::move_to_front(moves, self.killer_moves[ply as usize].1, &mut sorted_moves);
 
-        Self::sort_captures(position, &mut moves[sorted_moves..]);
+        let quiet_moves = Self::sort_captures(position, moves, sorted_moves);
 
-        moves
+        let history = &self.history[position.current_player() as usize];
+        for i in quiet_moves..moves.len() {
+            moves.set_score(i, history[moves[i].from() as usize][moves[i].to() as usize]);
+        }
+        moves.sort_range_by_score(quiet_moves, moves.len());
     }
 
-    pub fn sort_qsearch(position: &mut Position, mut moves: Vec<Move>) -> Vec<Move> {
-        Self::sort_captures(position, &mut moves);
-        moves
+    pub fn sort_qsearch(position: &mut Position, moves: &mut MoveList) {
+        Self::sort_captures(position, moves, 0);
     }
 
-    pub fn cut_off_move(&mut self, m: Move, ply: u16) {
+    pub fn cut_off_move(&mut self, position: &Position, m: Move, ply: u16, depth: u16) {
         if self.killer_moves[ply as usize].0 != m {
             self.killer_moves[ply as usize].1 = self.killer_moves[ply as usize].0;
             self.killer_moves[ply as usize].0 = m;
         }
+
+        if !position.is_capture(m) {
+            let value = &mut self.history[position.current_player() as usize][m.from() as usize][m.to() as usize];
+            *value = (*value + depth as i32 * depth as i32).min(Self::MAX_HISTORY);
+        }
     }
 
     fn lva_mvv_values(position: &mut Position, m: Move) -> u8 {
@@ -525,32 +1034,390 @@ impl MoveSorter {
         16*victim_value - attacker_value
     }
 
-    fn sort_captures(position: &mut Position, moves: &mut [Move]) {
-        let mut move_scores = Vec::new();
+    //sorts the captures in moves[start..] by lva/mvv value and moves them in front of the quiet moves. Returns the index of the first quiet move.
+    fn sort_captures(position: &mut Position, moves: &mut MoveList, start: usize) -> usize {
+        let mut captures_end = start;
         
-        for i in 0..moves.len() {
+        for i in start..moves.len() {
             if position.is_capture(moves[i]) {
-                move_scores.push(Self::lva_mvv_values(position, moves[i]));
-                moves.swap(i, move_scores.len()-1);
+                moves.swap(i, captures_end);
+                moves.set_score(captures_end, Self::lva_mvv_values(position, moves[captures_end]) as i32);
+                captures_end += 1;
             }
         }
 
-        for i in 1..move_scores.len() {
-            let score = move_scores[i];
-            let m = moves[i];
-            let mut index = 0;
-            for j in (0..i).rev() {
-                if move_scores[j] < score {
-                    move_scores[j+1] = move_scores[j];
-                    moves[j+1] = moves[j];
-                } else {
-                    index = j+1;
-                    break;
+        moves.sort_range_by_score(start, captures_end);
+        captures_end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn search(fen: &str, options: EngineOptions) -> (Position, SearchResult) {
+        let mut engine = Engine::new(16, Arc::new(SilentObserver));
+        let mut position = Position::from_fen_string(fen).unwrap();
+        let result = engine.search_sync(position.clone(), options);
+
+        if let Some(m) = result.best_move {
+            assert!(position.legal_moves().contains(&m), "illegal best move {m}");
+        }
+
+        (position, result)
+    }
+
+    fn depth(depth: u64) -> EngineOptions {
+        EngineOptions {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tablebase_hits() {
+        let mut tablebases = Tablebases::new();
+        tablebases.generate("KRK").unwrap();
+
+        let mut engine = Engine::new(16, Arc::new(SilentObserver));
+        let result = engine.search_sync(Position::from_fen_string("8/8/3k4/8/8/8/8/R3K3 w - - 0 1").unwrap(), depth(3));
+        assert_eq!(result.tbhits, 0, "no tablebases loaded");
+
+        engine.set_tablebases(Arc::new(tablebases));
+        let result = engine.search_sync(Position::from_fen_string("8/8/3k4/8/8/8/8/R3K3 w - - 0 1").unwrap(), depth(3));
+        assert!(result.tbhits > 0);
+        assert!(result.score.unwrap().mate().is_some());
+    }
+
+    #[test]
+    fn finds_mate_in_3() {
+        let (mut position, result) = search("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1", depth(6));
+
+        assert_eq!(result.score.unwrap().mate(), Some(3));
+        assert_eq!(result.best_move, Some(Move::from_string("f6a6", &mut position).unwrap()));
+    }
+
+    #[test]
+    fn finds_mate_in_3_with_king_and_rook() {
+        let (mut position, result) = search("7k/8/8/4K3/8/8/8/R7 w - - 0 1", depth(8));
+
+        assert_eq!(result.score.unwrap().mate(), Some(3));
+        assert_eq!(result.best_move, Some(Move::from_string("e5f6", &mut position).unwrap()));
+    }
+
+    #[test]
+    fn finds_mate_in_2_for_black() {
+        let (mut position, result) = search("6k1/pp4p1/2p5/2bp4/8/P5Pb/1P3rrP/2BRRN1K b - - 0 1", depth(6));
+
+        assert_eq!(result.score.unwrap().mate(), Some(2));
+        assert_eq!(result.best_move, Some(Move::from_string("g2g1", &mut position).unwrap()));
+        assert_eq!(result.pv[0], result.best_move.unwrap());
+        assert_eq!(result.ponder_move, result.pv.get(1).copied());
+    }
+
+    //the check extension lets a search of depth 2 see the quiet mate after the check
+    #[test]
+    fn check_extension() {
+        let (mut position, result) = search("k7/8/2K5/8/8/8/8/5Q2 w - - 0 1", depth(2));
+
+        assert_eq!(result.score.unwrap().mate(), Some(2));
+        assert_eq!(result.best_move, Some(Move::from_string("f1a6", &mut position).unwrap()));
+    }
+
+    #[test]
+    fn no_legal_moves() {
+        let (_, result) = search("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1", depth(3));
+        assert_eq!(result.best_move, None);
+
+        let (_, result) = search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", depth(3));
+        assert_eq!(result.best_move, None);
+    }
+
+    #[test]
+    fn draw_rules() {
+        //a mate with the 100th half move is not a draw by the 50 move rule
+        let (_, result) = search("7k/8/6K1/8/8/8/8/R7 w - - 99 80", depth(3));
+        assert_eq!(result.score.unwrap().mate(), Some(1));
+
+        //the engine still finds a move in a threefold repetition that was not claimed
+        let mut position = Position::new();
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8"] {
+            let m = Move::from_string(m, &mut position).unwrap();
+            position.make_move(m);
+        }
+        let result = Engine::new(16, Arc::new(SilentObserver)).search_sync(position, depth(3));
+        assert!(!result.pv.is_empty());
+    }
+
+    #[test]
+    fn contempt() {
+        //a draw by insufficient material scores -contempt for the engine
+        let fen = "8/8/3k4/8/8/3K4/3B4/8 w - - 0 1";
+        for (contempt, analyse_mode, expected) in [(0, false, 0), (50, false, -50), (-30, false, 30), (50, true, 0)] {
+            let (_, result) = search(fen, EngineOptions { depth: Some(3), contempt, analyse_mode, ..Default::default() });
+            assert_eq!(result.score, Some(Score::from_centi_pawns(expected)));
+        }
+    }
+
+    #[test]
+    fn reports_progress() {
+        #[derive(Default)]
+        struct CountingObserver {
+            current_moves: atomic::AtomicUsize,
+            progress_reports: atomic::AtomicUsize,
+            debug_messages: atomic::AtomicUsize
+        }
+
+        impl SearchObserver for CountingObserver {
+            fn iteration_complete(&self, report: &IterationReport) {
+                assert!(report.hashfull <= 1000);
+            }
+            fn best_move(&self, _best_move: Option<Move>, _ponder_move: Option<Move>) { }
+            fn current_move(&self, _m: Move, move_number: usize, _depth: u16) {
+                assert!(move_number >= 1);
+                self.current_moves.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+            fn progress(&self, _report: &ProgressReport) {
+                self.progress_reports.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+            fn debug(&self, _message: &str) {
+                self.debug_messages.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+        }
+
+        let observer = Arc::new(CountingObserver::default());
+        let mut engine = Engine::new(16, observer.clone());
+        //reports right away, so the test doesn't depend on how fast the search is
+        engine.set_report_interval(Duration::ZERO);
+        engine.search_sync(Position::from_fen_string(START_FEN).unwrap(), EngineOptions { depth: Some(4), move_time: Some(60_000), ..Default::default() });
+
+        assert!(observer.current_moves.load(atomic::Ordering::Relaxed) > 0);
+        assert!(observer.progress_reports.load(atomic::Ordering::Relaxed) > 0);
+        assert!(observer.debug_messages.load(atomic::Ordering::Relaxed) >= 2);
+    }
+
+    #[test]
+    fn time_management() {
+        //a single legal move is played after the first iteration
+        let (_, result) = search("7k/8/8/8/8/8/6q1/K7 w - - 0 1", EngineOptions { wtime: Some(60_000), btime: Some(60_000), ..Default::default() });
+        assert_eq!(result.depth, 1);
+
+        let mut scaling = TimeScaling::new(20);
+        let (m1, m2) = (Move::new(52, 36), Move::new(51, 35));
+        scaling.iteration_complete(Some(m1), Score::from_centi_pawns(30));
+        let unstable = scaling.factor();
+        for _ in 0..4 {
+            scaling.iteration_complete(Some(m1), Score::from_centi_pawns(30));
+        }
+        assert!(scaling.factor() < unstable);
+
+        //a dropping score and a new best move take more time
+        let stable = scaling.factor();
+        scaling.iteration_complete(Some(m2), Score::from_centi_pawns(-70));
+        assert!(scaling.factor() > stable * 2.0);
+        assert_eq!(scaling.soft_limit(Duration::from_millis(1000), Duration::from_millis(2000)), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn pondering() {
+        #[derive(Default)]
+        struct BestMoveObserver {
+            reported: atomic::AtomicBool
+        }
+
+        impl SearchObserver for BestMoveObserver {
+            fn iteration_complete(&self, _report: &IterationReport) { }
+            fn best_move(&self, _best_move: Option<Move>, _ponder_move: Option<Move>) {
+                self.reported.store(true, atomic::Ordering::Release);
+            }
+            fn current_move(&self, _m: Move, _move_number: usize, _depth: u16) { }
+            fn progress(&self, _report: &ProgressReport) { }
+            fn debug(&self, _message: &str) { }
+        }
+
+        //the search of a single legal move would end after one iteration, but not before the ponder hit
+        let observer = Arc::new(BestMoveObserver::default());
+        let mut engine = Engine::new(16, observer.clone());
+        let options = EngineOptions { ponder: true, wtime: Some(60_000), btime: Some(60_000), ..Default::default() };
+        engine.start(Position::from_fen_string("7k/8/8/8/8/8/6q1/K7 w - - 0 1").unwrap(), options);
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(!observer.reported.load(atomic::Ordering::Acquire));
+
+        //the soft limit was reached while pondering, so the move is played right away
+        engine.ponderhit();
+        let start = Instant::now();
+        while !observer.reported.load(atomic::Ordering::Acquire) {
+            assert!(start.elapsed() < Duration::from_secs(5), "no best move after ponder hit");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        //the table and the move ordering statistics are kept for the next search
+        engine.stop();
+        assert!(engine.ttable.is_some() && engine.move_sorter.is_some());
+    }
+
+    #[test]
+    fn save_and_load_table() {
+        let path = std::env::temp_dir().join(format!("carlito_table_test_{}.hash", std::process::id()));
+        let position = Position::from_fen_string(START_FEN).unwrap();
+
+        let mut engine = Engine::new(2, Arc::new(SilentObserver));
+        let first = engine.search_sync(position.clone(), depth(6));
+        engine.save_table(&path).unwrap();
+
+        //the loaded table replaces the one of a different size and makes the search cheaper
+        let mut engine = Engine::new(1, Arc::new(SilentObserver));
+        engine.load_table(&path, 1).unwrap_err();
+        engine.load_table(&path, 16).unwrap();
+        let second = engine.search_sync(position, depth(6));
+        assert_eq!(first.best_move, second.best_move);
+        assert!(second.nodes < first.nodes);
+
+        //corrupted files are rejected
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.pop();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(engine.load_table(&path, 16), Err(TableFileError::WrongFileSize { .. })));
+
+        bytes[8] = 2;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(engine.load_table(&path, 16), Err(TableFileError::UnsupportedVersion(2))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn illegal_table_moves() {
+        let mut position = Position::from_fen_string(START_FEN).unwrap();
+        let e4 = Move::from_string("e2e4", &mut position).unwrap();
+        let illegal = Move::new(0, 63);
+
+        //the principal variation ends before an illegal move
+        let mut table = TTable::new(1);
+        table.insert(position.hash(), EntryType::Exact, Score::from_centi_pawns(0), e4, 2);
+        position.make_move(e4);
+        table.insert(position.hash(), EntryType::Exact, Score::from_centi_pawns(0), illegal, 1);
+        position.unmake_move(e4);
+        assert_eq!(Engine::extract_pv(&mut position, &mut table, DEFAULT_PV_LENGTH), vec![e4]);
+        assert!(Engine::extract_pv(&mut position, &mut table, 0).is_empty());
+        assert_eq!(position.to_fen_string(), START_FEN);
+
+        //an exact entry with an illegal move doesn't end the search at the root
+        let mut engine = Engine::new(1, Arc::new(SilentObserver));
+        engine.ttable.as_mut().unwrap().insert(position.hash(), EntryType::Exact, Score::from_centi_pawns(0), illegal, 1);
+        let result = engine.search_sync(position.clone(), depth(1));
+        assert!(result.best_move.is_some_and(|m| position.legal_moves().contains(&m)));
+        assert!(!result.pv.contains(&illegal));
+    }
+
+    #[test]
+    fn pv_length() {
+        let mut engine = Engine::new(16, Arc::new(SilentObserver));
+        let result = engine.search_sync(Position::from_fen_string(START_FEN).unwrap(), depth(5));
+        assert!(result.pv.len() > 2);
+
+        engine.set_pv_length(2);
+        let result = engine.search_sync(Position::from_fen_string(START_FEN).unwrap(), depth(5));
+        assert_eq!(result.pv.len(), 2);
+    }
+
+    #[test]
+    fn respects_limits() {
+        let (_, result) = search(START_FEN, depth(4));
+        assert_eq!(result.depth, 4);
+        assert!(result.best_move.is_some());
+
+        let (_, result) = search(START_FEN, EngineOptions { nodes: Some(10_000), ..Default::default() });
+        assert_eq!(result.nodes, 10_000);
+        assert!(result.best_move.is_some());
+
+        let (_, result) = search(START_FEN, EngineOptions { move_time: Some(100), ..Default::default() });
+        assert!(result.depth >= 1);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn weakened_play() {
+        //the weakest level picks different moves, but never an illegal one
+        let mut moves = Vec::new();
+        for _ in 0..8 {
+            let (_, result) = search(START_FEN, EngineOptions { skill_level: Some(0), ..Default::default() });
+            assert!(result.nodes <= Strength::new(0).unwrap().node_limit());
+            moves.push(result.best_move.unwrap());
+        }
+        assert!(moves.iter().any(|m| *m != moves[0]));
+
+        //with a fixed seed it always picks the same move
+        let seeded = || {
+            let mut engine = Engine::new(16, Arc::new(SilentObserver));
+            engine.set_random_seed(Some(1));
+            engine.search_sync(Position::new(), EngineOptions { skill_level: Some(0), ..Default::default() }).best_move
+        };
+        let first = seeded();
+        assert!((0..4).all(|_| seeded() == first));
+
+        //a mate is never thrown away
+        let (mut position, result) = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", EngineOptions { skill_level: Some(0), depth: Some(2), ..Default::default() });
+        assert_eq!(result.best_move, Some(Move::from_string("a1a8", &mut position).unwrap()));
+    }
+
+    /**
+     * self-play match between neighbouring skill levels, used to calibrate the elo mapping in strength.rs.
+     * run with: cargo test --release skill_level_self_play -- --ignored --nocapture
+     */
+    #[test]
+    #[ignore]
+    fn skill_level_self_play() {
+        const GAMES: usize = 40;
+        const MOVE_TIME: u64 = 100;
+
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut random = move |n: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+
+        for weaker in (0..strength::MAX_SKILL_LEVEL).step_by(4) {
+            let stronger = weaker + 4;
+            let mut points = 0.0;
+
+            for game in 0..GAMES {
+                let mut engines = [Engine::new(16, Arc::new(SilentObserver)), Engine::new(16, Arc::new(SilentObserver))];
+                let levels = if game % 2 == 0 { [stronger, weaker] } else { [weaker, stronger] };
+
+                let mut position = Position::new();
+                for _ in 0..4 {
+                    let moves = position.legal_moves();
+                    position.make_move(moves[random(moves.len())]);
                 }
+
+                let mut outcome = None;
+                for ply in 0..300 {
+                    outcome = position.outcome();
+                    if outcome.is_some() {
+                        break;
+                    }
+
+                    let options = EngineOptions { move_time: Some(MOVE_TIME), skill_level: Some(levels[ply % 2]), ..Default::default() };
+                    let m = engines[ply % 2].search_sync(position.clone(), options).best_move.unwrap();
+                    position.make_move(m);
+                }
+
+                //levels[0] plays white after the random opening moves
+                points += match outcome {
+                    Some(GameOutcome::Checkmate { winner: Color::White }) if levels[0] == stronger => 1.0,
+                    Some(GameOutcome::Checkmate { winner: Color::Black }) if levels[1] == stronger => 1.0,
+                    Some(GameOutcome::Checkmate { .. }) => 0.0,
+                    _ => 0.5
+                };
             }
-            
-            moves[index] = m;
-            move_scores[index] = score;
+
+            let score = points / GAMES as f64;
+            let elo_difference = -400.0 * (1.0 / score.clamp(0.01, 0.99) - 1.0).log10();
+            println!("level {stronger} vs level {weaker}: {points}/{GAMES}, {elo_difference:.0} elo");
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::chess_move::{Move, MoveError};
+use crate::position::Position;
+
+use super::strength::Rng;
+
+/*
+ * opening book in a plain text format. Every line is an opening in coordinate notation from the
+ * starting position, like "e2e4 e7e5 g1f3", and '#' starts a comment. Positions are looked up by
+ * hash, so lines that transpose share their moves. The book move is picked randomly among the
+ * moves of all lines through the position, moves of more lines are picked more often.
+ */
+
+#[derive(Default)]
+pub struct Book {
+    //the moves of every book position with the number of lines that play them
+    positions: HashMap<u64, Vec<(Move, u32)>>
+}
+
+impl Book {
+    pub fn new() -> Book {
+        Book::default()
+    }
+
+    pub fn load(path: &Path) -> Result<Book, BookError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Book, BookError> {
+        let mut book = Book::new();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut position = Position::new();
+
+            for token in line.split_whitespace() {
+                let m = Move::from_string(token, &mut position).map_err(|e| BookError::InvalidMove { line: line_number + 1, error: e })?;
+
+                let moves = book.positions.entry(position.hash()).or_default();
+                match moves.iter_mut().find(|(n, _)| *n == m) {
+                    Some((_, count)) => *count += 1,
+                    None => moves.push((m, 1))
+                }
+
+                position.make_move(m);
+            }
+        }
+
+        Ok(book)
+    }
+
+    //number of positions with book moves
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    //picks a move weighted by its count. With a seed the pick only depends on the seed and the position, otherwise on the clock.
+    pub fn probe(&self, position: &mut Position, seed: Option<u64>) -> Option<Move> {
+        let moves = self.positions.get(&position.hash())?;
+
+        //a hash collision must not lead to an illegal move
+        let legal_moves = position.legal_moves();
+        let moves: Vec<_> = moves.iter().filter(|(m, _)| legal_moves.contains(m)).collect();
+
+        let total: u32 = moves.iter().map(|(_, count)| count).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut rng = seed.map_or_else(Rng::from_time, |seed| Rng::new(seed ^ position.hash()));
+        let mut r = (rng.next() % total as u64) as u32;
+        for &&(m, count) in moves.iter() {
+            if r < count {
+                return Some(m);
+            }
+            r -= count;
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(io::Error),
+    InvalidMove { line: usize, error: MoveError }
+}
+
+impl From<io::Error> for BookError {
+    fn from(e: io::Error) -> Self {
+        BookError::Io(e)
+    }
+}
+
+impl std::fmt::Display for BookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookError::Io(e) => write!(f, "{e}"),
+            BookError::InvalidMove { line, error } => write!(f, "line {line}: {error}")
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn book_moves() {
+        let book = Book::parse("e2e4 e7e5 g1f3 # open game\n\ne2e4 c7c5\nd2d4 d7d5\n").unwrap();
+        assert_eq!(book.len(), 4);
+
+        let mut position = Position::new();
+        for _ in 0..20 {
+            let m = book.probe(&mut position, None).unwrap();
+            assert!(["e2e4", "d2d4"].contains(&m.to_string().as_str()));
+        }
+
+        for m in ["e2e4", "e7e5"] {
+            let m = Move::from_string(m, &mut position).unwrap();
+            position.make_move(m);
+        }
+        assert_eq!(book.probe(&mut position, None).map(|m| m.to_string()), Some("g1f3".to_string()));
+
+        let m = Move::from_string("g1f3", &mut position).unwrap();
+        position.make_move(m);
+        assert_eq!(book.probe(&mut position, None), None);
+
+        assert!(matches!(Book::parse("e2e4\ne2e5"), Err(BookError::InvalidMove { line: 2, .. })));
+    }
+
+    #[test]
+    fn seeded_probe() {
+        let book = Book::parse("e2e4\nd2d4\nc2c4\ng1f3\n").unwrap();
+        let mut position = Position::new();
+
+        let first = book.probe(&mut position, Some(7));
+        for _ in 0..20 {
+            assert_eq!(book.probe(&mut position, Some(7)), first);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::bitboard::Bitboard;
+use crate::position::{Color::{self, *}, Piece::{self, *}, Position};
+
+use super::kpk;
+
+/*
+ * specialised evaluation of endgames, selected by the material signature of the position.
+ *
+ * value endgames replace the normal evaluation completely: KXK, KBNK, KPK with the bitbase, KRKP by the distances
+ * of the kings to the pawn, and the drawn KNNK.
+ * scale factors shrink the end game part of the normal evaluation for endgames that are harder to
+ * win than the material suggests: opposite coloured bishops, a bishop of the wrong color with rook
+ * pawns and pawnless endings with a small material advantage.
+ */
+
+//a value that is higher than any normal evaluation, but lower than a mate score
+pub const KNOWN_WIN: i32 = 10000;
+
+pub const SCALE_NORMAL: i32 = 64;
+const SCALE_DRAW: i32 = 0;
+
+const PIECES: [Piece; 5] = [Pawn, Knight, Bishop, Rook, Queen];
+
+//number of pieces of each type and color, 4 bits each. Kings are not counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaterialSignature(u64);
+
+impl MaterialSignature {
+    pub fn from_position(pos: &Position) -> MaterialSignature {
+        let mut signature = 0;
+
+        for player in [White, Black] {
+            for piece in PIECES {
+                signature |= (pos.pieces(piece, player).count_squares().min(15) as u64) << Self::shift(piece, player);
+            }
+        }
+
+        MaterialSignature(signature)
+    }
+
+    //parses signatures like "KBNK", the pieces up to the second king are white
+    pub const fn from_pieces(pieces: &str) -> MaterialSignature {
+        let bytes = pieces.as_bytes();
+        let mut player = Black;
+        let mut signature = 0;
+
+        let mut i = 0;
+        while i < bytes.len() {
+            let piece = match bytes[i] {
+                b'K' => {
+                    player = if let White = player { Black } else { White };
+                    i += 1;
+                    continue;
+                },
+                b'P' => Pawn,
+                b'N' => Knight,
+                b'B' => Bishop,
+                b'R' => Rook,
+                b'Q' => Queen,
+                _ => panic!("invalid material signature")
+            };
+
+            signature += 1 << Self::shift(piece, player);
+            i += 1;
+        }
+
+        MaterialSignature(signature)
+    }
+
+    pub const fn count(&self, piece: Piece, player: Color) -> u32 {
+        ((self.0 >> Self::shift(piece, player)) & 15) as u32
+    }
+
+    //the same material with the colors swapped
+    pub const fn mirrored(&self) -> MaterialSignature {
+        MaterialSignature((self.0 >> 20) | ((self.0 & 0xfffff) << 20))
+    }
+
+    pub fn is_bare_king(&self, player: Color) -> bool {
+        PIECES.iter().all(|&p| self.count(p, player) == 0)
+    }
+
+    //material of the knights, bishops, rooks and queens
+    fn non_pawn_material(&self, player: Color, material: &[i32; 5]) -> i32 {
+        PIECES[1..].iter().map(|&p| self.count(p, player) as i32 * material[p as usize]).sum()
+    }
+
+    //enough material to mate a bare king without pawns
+    fn can_force_mate(&self, pos: &Position, player: Color) -> bool {
+        let bishops = pos.pieces(Bishop, player);
+
+        self.count(Queen, player) > 0
+            || self.count(Rook, player) > 0
+            || (self.count(Bishop, player) > 0 && self.count(Knight, player) > 0)
+            || (!(bishops & Position::LIGHT_SQUARES).is_empty() && !(bishops & !Position::LIGHT_SQUARES).is_empty())
+    }
+
+    const fn shift(piece: Piece, player: Color) -> u32 {
+        (player as u32 * 5 + piece as u32) * 4
+    }
+}
+
+//evaluates the position from the point of view of the strong side, or returns None if the normal evaluation should be used
+type EndgameFn = fn(&Position, Color, &[i32; 5]) -> Option<i32>;
+
+//endgames with a dedicated evaluation, the strong side is white in the signature
+const ENDGAMES: [(MaterialSignature, EndgameFn); 4] = [
+    (MaterialSignature::from_pieces("KBNK"), kbnk),
+    (MaterialSignature::from_pieces("KPK"), kpk),
+    (MaterialSignature::from_pieces("KRKP"), krkp),
+    (MaterialSignature::from_pieces("KNNK"), draw)
+];
+
+//the evaluation from white's point of view if the position is a known endgame
+pub fn evaluate(pos: &Position, signature: MaterialSignature, material: &[i32; 5]) -> Option<i32> {
+    for (s, endgame) in ENDGAMES {
+        if s == signature {
+            return endgame(pos, White, material);
+        } else if s.mirrored() == signature {
+            return endgame(pos, Black, material).map(|v| -v);
+        }
+    }
+
+    for strong in [White, Black] {
+        if signature.is_bare_king(!strong) && signature.can_force_mate(pos, strong) {
+            let value = kxk(pos, strong, material);
+            return Some(if strong == White { value } else { -value });
+        }
+    }
+
+    None
+}
+
+//factor for the end game evaluation of the side that is ahead, SCALE_NORMAL leaves the evaluation unchanged
+pub fn scale_factor(pos: &Position, signature: MaterialSignature, strong: Color, material: &[i32; 5]) -> i32 {
+    let weak = !strong;
+
+    let strong_material = signature.non_pawn_material(strong, material);
+    let weak_material = signature.non_pawn_material(weak, material);
+
+    //without pawns, a small advantage is usually not enough to win
+    if signature.count(Pawn, strong) == 0 && strong_material - weak_material <= material[Bishop as usize] {
+        return if strong_material < material[Rook as usize] {
+            SCALE_DRAW
+        } else if weak_material <= material[Bishop as usize] {
+            4
+        } else {
+            14
+        };
+    }
+
+    //rook pawns and a bishop that does not control the promotion square can not win against a king in the corner
+    if strong_material == signature.count(Bishop, strong) as i32 * material[Bishop as usize] && signature.count(Bishop, strong) > 0 {
+        let pawns = pos.pieces(Pawn, strong);
+        let bishops = pos.pieces(Bishop, strong);
+
+        for file in [0, 7] {
+            if !pawns.is_empty() && (pawns & !Bitboard::file(file)).is_empty() {
+                let promotion_square = if strong == White { file } else { 56 + file };
+                let light_promotion_square = Position::LIGHT_SQUARES.contains(promotion_square);
+
+                if (bishops & if light_promotion_square { Position::LIGHT_SQUARES } else { !Position::LIGHT_SQUARES }).is_empty()
+                    && Bitboard::distance(pos.king_square(weak), promotion_square) <= 1 {
+                    return SCALE_DRAW;
+                }
+            }
+        }
+    }
+
+    //opposite coloured bishops
+    if signature.count(Bishop, White) == 1 && signature.count(Bishop, Black) == 1 {
+        let white_bishop_light = !(pos.pieces(Bishop, White) & Position::LIGHT_SQUARES).is_empty();
+        let black_bishop_light = !(pos.pieces(Bishop, Black) & Position::LIGHT_SQUARES).is_empty();
+
+        if white_bishop_light != black_bishop_light {
+            let only_bishops = strong_material == material[Bishop as usize] && weak_material == material[Bishop as usize];
+            let pawn_difference = signature.count(Pawn, strong) as i32 - signature.count(Pawn, weak) as i32;
+
+            return if !only_bishops {
+                44
+            } else if pawn_difference <= 1 {
+                16
+            } else {
+                32
+            };
+        }
+    }
+
+    SCALE_NORMAL
+}
+
+//bonus for driving the king to the edge of the board, from 0 in the center to 90 in a corner
+fn push_to_edge(square: u8) -> i32 {
+    let file = (square % 8) as i32;
+    let rank = (square / 8) as i32;
+    15 * ((3 - file).max(file - 4) + (3 - rank).max(rank - 4))
+}
+
+//bonus for bringing the attacking king close to the defending king
+fn push_close(s1: u8, s2: u8) -> i32 {
+    20 * (7 - Bitboard::distance(s1, s2) as i32)
+}
+
+//material of the side, including pawns
+fn material_value(pos: &Position, player: Color, material: &[i32; 5]) -> i32 {
+    PIECES.iter().map(|&p| pos.pieces(p, player).count_squares() as i32 * material[p as usize]).sum()
+}
+
+fn draw(_pos: &Position, _strong: Color, _material: &[i32; 5]) -> Option<i32> {
+    Some(0)
+}
+
+//mating material against a bare king: drive the king to the edge and approach it
+fn kxk(pos: &Position, strong: Color, material: &[i32; 5]) -> i32 {
+    let strong_king = pos.king_square(strong);
+    let weak_king = pos.king_square(!strong);
+
+    KNOWN_WIN + material_value(pos, strong, material) + push_to_edge(weak_king) + push_close(strong_king, weak_king)
+}
+
+//bishop and knight: the king can only be mated in a corner of the bishop's color
+fn kbnk(pos: &Position, strong: Color, material: &[i32; 5]) -> Option<i32> {
+    let strong_king = pos.king_square(strong);
+    let weak_king = pos.king_square(!strong);
+
+    let corners = if (pos.pieces(Bishop, strong) & Position::LIGHT_SQUARES).is_empty() { [7, 56] } else { [0, 63] };
+    let corner_distance = corners.iter().map(|&c| Bitboard::distance(weak_king, c)).min().unwrap() as i32;
+
+    Some(KNOWN_WIN + material_value(pos, strong, material) + 30 * (7 - corner_distance) + push_close(strong_king, weak_king))
+}
+
+//king and pawn against king, looked up in the bitbase
+fn kpk(pos: &Position, strong: Color, material: &[i32; 5]) -> Option<i32> {
+    if !kpk::probe_position(pos)? {
+        return Some(0);
+    }
+
+    //the further the pawn is advanced, the closer the win
+    let pawn = pos.pieces(Pawn, strong).into_iter().next().unwrap();
+    let rank = if strong == White { 8 - pawn / 8 } else { pawn / 8 + 1 };
+
+    Some(KNOWN_WIN + material[Pawn as usize] + 20 * rank as i32)
+}
+
+//rook against pawn: a win if the strong king stops the pawn or the weak king is far away from it, otherwise
+//it comes down to the race of the kings to the square in front of the pawn
+fn krkp(pos: &Position, strong: Color, material: &[i32; 5]) -> Option<i32> {
+    let weak = !strong;
+    let strong_king = pos.king_square(strong);
+    let weak_king = pos.king_square(weak);
+    let rook = pos.pieces(Rook, strong).into_iter().next().unwrap();
+    let pawn = pos.pieces(Pawn, weak).into_iter().next().unwrap();
+
+    //rows to go to the promotion row of the weak side
+    let to_promotion = |square: u8| if weak == White { square / 8 } else { 7 - square / 8 } as i32;
+    let promotion_square = if weak == White { pawn % 8 } else { 56 + pawn % 8 };
+    let in_front = if weak == White { pawn - 8 } else { pawn + 8 };
+    let distance = |s1: u8, s2: u8| Bitboard::distance(s1, s2) as i32;
+    let strong_to_move = (pos.current_player() == strong) as i32;
+    let weak_to_move = 1 - strong_to_move;
+
+    if strong_king % 8 == pawn % 8 && to_promotion(strong_king) < to_promotion(pawn) {
+        return Some(material[Rook as usize] - distance(strong_king, pawn));
+    }
+
+    if distance(weak_king, pawn) >= 3 + weak_to_move && distance(weak_king, rook) >= 3 {
+        return Some(material[Rook as usize] - distance(strong_king, pawn));
+    }
+
+    //the weak king escorts an advanced pawn and the strong king is too far away
+    if to_promotion(weak_king) <= 2 && distance(weak_king, pawn) == 1 && to_promotion(strong_king) >= 3
+        && distance(strong_king, pawn) > 2 + strong_to_move {
+        return Some(80 - 8 * distance(strong_king, pawn));
+    }
+
+    Some(200 - 8 * (distance(strong_king, in_front) - distance(weak_king, in_front) - distance(pawn, promotion_square)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MATERIAL: [i32; 5] = [100, 290, 310, 500, 900];
+
+    fn eval(fen: &str) -> Option<i32> {
+        let pos = Position::from_fen_string(fen).unwrap();
+        evaluate(&pos, MaterialSignature::from_position(&pos), &MATERIAL)
+    }
+
+    fn scale(fen: &str, strong: Color) -> i32 {
+        let pos = Position::from_fen_string(fen).unwrap();
+        scale_factor(&pos, MaterialSignature::from_position(&pos), strong, &MATERIAL)
+    }
+
+    #[test]
+    fn signature() {
+        let pos = Position::from_fen_string("4k3/8/8/8/8/8/8/1N2KB2 w - - 0 1").unwrap();
+        assert_eq!(MaterialSignature::from_position(&pos), MaterialSignature::from_pieces("KBNK"));
+        assert_eq!(MaterialSignature::from_pieces("KBNK").mirrored(), MaterialSignature::from_pieces("KKBN"));
+        assert_eq!(MaterialSignature::from_pieces("KRPKP").count(Pawn, Black), 1);
+        assert!(MaterialSignature::from_pieces("KQK").is_bare_king(Black));
+    }
+
+    #[test]
+    fn value_endgames() {
+        //mating nets: the closer the defending king is to the edge, the better
+        let center = eval("8/8/8/3k4/8/3K4/8/7R w - - 0 1").unwrap();
+        let edge = eval("3k4/8/3K4/8/8/8/8/7R w - - 0 1").unwrap();
+        assert!(center > KNOWN_WIN && edge > center);
+        assert!(eval("8/8/8/3K4/8/8/8/4k2r w - - 0 1").unwrap() < -KNOWN_WIN);
+
+        //no mating material
+        assert_eq!(eval("8/8/8/3k4/8/8/8/3NKN2 w - - 0 1"), Some(0));
+        assert_eq!(eval("8/8/8/3k4/8/8/8/3BKB2 w - - 0 1"), None);
+
+        //bishop and knight: the right corner is better than the wrong one
+        let right_corner = eval("k7/8/2K5/8/8/8/8/3BN3 w - - 0 1").unwrap();
+        let wrong_corner = eval("7k/8/5K2/8/8/8/8/3BN3 w - - 0 1").unwrap();
+        assert!(right_corner > wrong_corner && wrong_corner > KNOWN_WIN);
+
+        //king and pawn
+        assert!(eval("8/8/8/8/4P3/8/k7/4K3 w - - 0 1").unwrap() > KNOWN_WIN, "rule of the square");
+        assert!(eval("8/8/4K3/2k5/4P3/8/8/8 b - - 0 1").unwrap() > KNOWN_WIN, "key square");
+        assert_eq!(eval("4k3/8/8/8/4P3/8/8/4K3 w - - 0 1"), Some(0), "blocked");
+        assert_eq!(eval("k7/8/8/8/P7/8/8/4K3 w - - 0 1"), Some(0), "rook pawn");
+        assert!(eval("K7/8/8/8/8/4pk2/8/8 w - - 0 1").unwrap() < -KNOWN_WIN, "black pawn");
+        assert_eq!(eval("8/8/8/8/3k4/8/4P3/4K3 w - - 0 1"), Some(0), "the king can not protect the pawn");
+
+        //rook against pawn: the strong king in front of the pawn wins, an escorted pawn against a distant king is close to a draw
+        let in_front = eval("7k/8/8/8/4p3/8/4K3/R7 w - - 0 1").unwrap();
+        let distant_king = eval("k7/8/8/8/4p3/8/2K5/7R w - - 0 1").unwrap();
+        let escorted = eval("R7/8/8/8/7K/8/1kp5/8 w - - 0 1").unwrap();
+        let race = eval("8/8/4K3/8/8/2kp4/8/7R w - - 0 1").unwrap();
+        assert_eq!(in_front, MATERIAL[Rook as usize] - 2);
+        assert_eq!(distant_king, MATERIAL[Rook as usize] - 2);
+        assert_eq!(escorted, 80 - 8 * 5);
+        assert!(race > escorted && race < in_front);
+        assert_eq!(eval("r7/8/8/8/4P3/8/4k3/7K b - - 0 1"), Some(-in_front), "colors swapped");
+    }
+
+    #[test]
+    fn scale_factors() {
+        assert_eq!(scale("8/4k3/8/3b4/8/2B1P3/4K3/8 w - - 0 1", White), 16, "opposite coloured bishops");
+        assert_eq!(scale("8/4k3/8/3b4/8/3BP3/4K3/8 w - - 0 1", White), SCALE_NORMAL, "same coloured bishops");
+        assert_eq!(scale("k7/8/8/8/P7/8/3B4/4K3 w - - 0 1", White), SCALE_DRAW, "wrong bishop");
+        assert_eq!(scale("k7/8/8/8/P7/8/2B5/4K3 w - - 0 1", White), SCALE_NORMAL, "right bishop");
+        assert_eq!(scale("4k3/8/8/3n4/8/8/8/2R1K3 w - - 0 1", White), 4, "rook against knight");
+        assert_eq!(scale("4k3/8/8/8/8/8/8/2N1K3 w - - 0 1", White), SCALE_DRAW, "minor piece");
+    }
+}
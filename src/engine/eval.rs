@@ -1,16 +1,40 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
 use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::path::Path;
 
 use crate::{bitboard::{Bitboard, Direction::*}, position::{Color::{self, *}, Position}};
-use super::{score::Score, Piece::*};
+use crate::position::psqt::{MATERIAL, PIECE_SQUARE, phase_value};
+use super::{score::Score, endgame::{self, MaterialSignature, SCALE_NORMAL}, Piece::*};
 
 pub struct Evaluator {
     params: EvalParams,
+    //false if the material or piece square values differ from the defaults, which the position uses for its incremental values
+    incremental_psqt: bool,
     pawn_attacks: [Bitboard; 2],
     king_safety: [i32; 2],
     king_ring: [Bitboard; 2],
-    outpost_squares: [Bitboard; 2]
+    king_attack_units: [i32; 2],
+    king_attackers: [u32; 2],
+    outpost_squares: [Bitboard; 2],
+    passed_pawns: [Bitboard; 2],
+    attacked_by: [[Bitboard; 6]; 2],
+    pawn_cache: Vec<PawnEntry>,
+    pawn_cache_probes: u64,
+    pawn_cache_hits: u64
 }
 
+//the pawn structure evaluation of a position, which only depends on the pawns
+#[derive(Clone, Copy)]
+struct PawnEntry {
+    pawn_hash: u64,
+    score: P,
+    pawn_attacks: [Bitboard; 2],
+    outpost_squares: [Bitboard; 2],
+    passed_pawns: [Bitboard; 2]
+}
+
+#[derive(Clone)]
 pub struct EvalParams {
     pub material: [i32; 5],
     bishop_pair: P,
@@ -21,10 +45,23 @@ pub struct EvalParams {
     queen_mobility: [P; 28],
     stacked_pawns: P,
     isolated_pawn:  P,
+    doubled_isolated_pawn: P,
+    backward_pawn: P,
+    connected_pawn: [P; 6],
     passed_pawn: [P; 6],
+    passed_pawn_free_path: [P; 6],
+    passed_pawn_blocked: [P; 6],
+    passed_pawn_own_king_distance: P,
+    passed_pawn_enemy_king_distance: P,
+    unstoppable_passed_pawn: P,
     king_attack_ray: [i32; 8], //TODO: check if seperate parameters for file and diagonal are better
     king_ring_attacker: [i32; 5],
     king_ring_defender: [i32; 5],
+    king_attack_units: [i32; 5],
+    king_danger: [i32; 32],
+    threat_by_pawn: [P; 5],
+    threat_by_lower_piece: [P; 5],
+    hanging_piece: P,
     bishop_outpost: P,
     knight_outpost: P,
     open_rook_file: P,
@@ -81,86 +118,182 @@ impl Mul<i32> for P {
     }
 }
 
+//a parameter as it is written to an eval file, a list of numbers
+trait ParamValues {
+    fn len(&self) -> usize;
+    fn values(&self, out: &mut Vec<i32>);
+    //values has len() elements
+    fn set_values(&mut self, values: &[i32]);
+}
+
+impl ParamValues for i32 {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn values(&self, out: &mut Vec<i32>) {
+        out.push(*self);
+    }
+
+    fn set_values(&mut self, values: &[i32]) {
+        *self = values[0];
+    }
+}
+
+impl ParamValues for P {
+    fn len(&self) -> usize {
+        2
+    }
+
+    fn values(&self, out: &mut Vec<i32>) {
+        out.extend([self.0, self.1]);
+    }
+
+    fn set_values(&mut self, values: &[i32]) {
+        *self = P(values[0], values[1]);
+    }
+}
+
+impl<T: ParamValues, const N: usize> ParamValues for [T; N] {
+    fn len(&self) -> usize {
+        self.iter().map(|v| v.len()).sum()
+    }
+
+    fn values(&self, out: &mut Vec<i32>) {
+        for v in self.iter() {
+            v.values(out);
+        }
+    }
+
+    fn set_values(&mut self, mut values: &[i32]) {
+        for v in self.iter_mut() {
+            let (head, tail) = values.split_at(v.len());
+            v.set_values(head);
+            values = tail;
+        }
+    }
+}
+
+macro_rules! param_fields {
+    ($params:expr, $($field:ident),*) => {
+        vec![$((stringify!($field), &mut $params.$field as &mut dyn ParamValues)),*]
+    };
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Evaluator::DEFAULT_PARAMS
+    }
+}
+
+impl EvalParams {
+    fn fields(&mut self) -> Vec<(&'static str, &mut dyn ParamValues)> {
+        param_fields!(self, material, bishop_pair, piece_square, knight_mobility, bishop_mobility, rook_mobility, queen_mobility,
+            stacked_pawns, isolated_pawn, doubled_isolated_pawn, backward_pawn, connected_pawn, passed_pawn, passed_pawn_free_path,
+            passed_pawn_blocked, passed_pawn_own_king_distance, passed_pawn_enemy_king_distance, unstoppable_passed_pawn,
+            king_attack_ray, king_ring_attacker, king_ring_defender, king_attack_units, king_danger, threat_by_pawn,
+            threat_by_lower_piece, hanging_piece, bishop_outpost, knight_outpost, open_rook_file, half_open_rook_file)
+    }
+
+    /**
+     * reads an eval file with one parameter per line: the name of the parameter and its values, which are
+     * pairs of middle game and end game values for most parameters. Arrays are written as one list. '#'
+     * starts a comment. Parameters that are not in the file keep their default values.
+     */
+    pub fn load(path: &Path) -> Result<EvalParams, EvalFileError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<EvalParams, EvalFileError> {
+        let mut params = EvalParams::default();
+        let mut fields = params.fields();
+
+        for line in text.lines().map(|l| l.split('#').next().unwrap_or_default()) {
+            let mut tokens = line.split_whitespace();
+            let Some(name) = tokens.next() else {
+                continue;
+            };
+
+            let (_, field) = fields.iter_mut().find(|(n, _)| *n == name).ok_or_else(|| EvalFileError::UnknownParameter(name.to_string()))?;
+
+            let values = tokens.map(|t| t.parse::<i32>().map_err(|_| EvalFileError::InvalidNumber(t.to_string()))).collect::<Result<Vec<_>, _>>()?;
+            if values.len() != field.len() {
+                return Err(EvalFileError::WrongValueCount { name: name.to_string(), expected: field.len(), actual: values.len() });
+            }
+
+            field.set_values(&values);
+        }
+
+        Ok(params)
+    }
+
+    //writes all parameters in the format of load()
+    pub fn save(&self, path: &Path) -> Result<(), EvalFileError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        for (name, field) in self.clone().fields() {
+            let mut values = Vec::new();
+            field.values(&mut values);
+            writeln!(writer, "{name} {}", values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" "))?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalFileError {
+    Io(io::Error),
+    UnknownParameter(String),
+    InvalidNumber(String),
+    WrongValueCount { name: String, expected: usize, actual: usize }
+}
+
+impl From<io::Error> for EvalFileError {
+    fn from(e: io::Error) -> Self {
+        EvalFileError::Io(e)
+    }
+}
+
+impl std::fmt::Display for EvalFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalFileError::Io(e) => write!(f, "{e}"),
+            EvalFileError::UnknownParameter(name) => write!(f, "unknown parameter '{name}'"),
+            EvalFileError::InvalidNumber(s) => write!(f, "invalid number '{s}'"),
+            EvalFileError::WrongValueCount { name, expected, actual } => write!(f, "{name} has {expected} values, found {actual}")
+        }
+    }
+}
+
+impl std::error::Error for EvalFileError {}
+
+//the default piece square values are the ones that Position keeps up to date
+const fn default_piece_square() -> [[P; 64]; 6] {
+    let mut table = [[P(0, 0); 64]; 6];
+    let mut piece = 0;
+    while piece < 6 {
+        let mut square = 0;
+        while square < 64 {
+            table[piece][square] = P(PIECE_SQUARE[piece][square].0, PIECE_SQUARE[piece][square].1);
+            square += 1;
+        }
+        piece += 1;
+    }
+    table
+}
+
 impl Evaluator {
 
+    const PAWN_CACHE_SIZE: usize = 1 << 14;
+
     const DEFAULT_PARAMS: EvalParams = EvalParams {
-        material: [100, 290, 310, 500, 900],
+        material: MATERIAL,
 
         bishop_pair: P(10, 10),
 
-        piece_square: [
-            //pawn:
-            [
-                P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), 
-                P( 23, 28), P( 59, 23), P(-14,  8), P( 20,-16), P( -7, -3), P( 51,-18), P(-41, 15), P(-86, 37),
-                P(-16, 54), P( -3, 60), P( 16, 45), P( 21, 27), P( 55, 16), P( 46, 13), P( 15, 42), P(-30, 44), 
-                P(-14, 12), P( 13, 14), P(  6,  3), P( 21, -5), P( 23,-12), P( 12, -6), P( 17,  7), P(-23,  7), 
-                P(-27, 13), P( -2,  9), P( -5, -3), P( 12, -7), P( 17, -7), P(  6, -8), P( 10,  3), P(-25, -1), 
-                P(-26,  4), P( -4,  7), P( -4, -6), P(-10,  1), P(  3,  0), P(  3, -5), P( 33, -1), P(-12, -8), 
-                P(-35, 13), P( -1,  8), P(-20,  8), P(-23, 10), P(-15, 13), P( 24,  0), P( 38,  2), P(-22, -7), 
-                P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), P(  0,  0), 
-            ],
-
-            //knight:
-            [
-                P(-167,-58), P(-89,-38), P(-34,-13), P(-49,-28), P( 61,-31), P(-97,-27), P(-15,-63), P(-107,-99), 
-                P(-73,-25), P(-41, -8), P( 72,-25), P( 36, -2), P( 23, -9), P( 62,-25), P(  7,-24), P(-17,-52), 
-                P(-47,-24), P( 60,-20), P( 37, 10), P( 65,  9), P( 84, -1), P(129, -9), P( 73,-19), P( 44,-41), 
-                P( -9,-17), P( 17,  3), P( 19, 22), P( 53, 22), P( 37, 22), P( 69, 11), P( 18,  8), P( 22,-18), 
-                P(-13,-18), P(  4, -6), P( 16, 16), P( 13, 25), P( 28, 16), P( 19, 17), P( 21,  4), P( -8,-18), 
-                P(-23,-23), P( -9, -3), P( 12, -1), P( 10, 15), P( 19, 10), P( 17, -3), P( 25,-20), P(-16,-22), 
-                P(-29,-42), P(-53,-20), P(-12,-10), P( -3, -5), P( -1, -2), P( 18,-20), P(-14,-23), P(-19,-44), 
-                P(-105,-29), P(-21,-51), P(-58,-23), P(-33,-15), P(-17,-22), P(-28,-18), P(-19,-50), P(-23,-64),
-            ],
-
-            //bishop:
-            [
-                P(-29,-14), P(  4,-21), P(-82,-11), P(-37, -8), P(-25, -7), P(-42, -9), P(  7,-17), P( -8,-24), 
-                P(-26, -8), P( 16, -4), P(-18,  7), P(-13,-12), P( 30, -3), P( 59,-13), P( 18, -4), P(-47,-14), 
-                P(-16,  2), P( 37, -8), P( 43,  0), P( 40, -1), P( 35, -2), P( 50,  6), P( 37,  0), P( -2,  4), 
-                P( -4, -3), P(  5,  9), P( 19, 12), P( 50,  9), P( 37, 14), P( 37, 10), P(  7,  3), P( -2,  2), 
-                P( -6, -6), P( 13,  3), P( 13, 13), P( 26, 19), P( 34,  7), P( 12, 10), P( 10, -3), P(  4, -9), 
-                P(  0,-12), P( 15, -3), P( 15,  8), P( 15, 10), P( 14, 13), P( 27,  3), P( 18, -7), P( 10,-15), 
-                P(  4,-14), P( 15,-18), P( 16, -7), P(  0, -1), P(  7,  4), P( 21, -9), P( 33,-15), P(  1,-27), 
-                P(-33,-23), P( -3, -9), P(-14,-23), P(-21, -5), P(-13, -9), P(-12,-16), P(-39, -5), P(-21,-17), 
-            ],
-
-            //rook:
-            [
-                P( 32, 13), P( 42, 10), P( 32, 18), P( 51, 15), P( 63, 12), P(  9, 12), P( 31,  8), P( 43,  5), 
-                P( 27, 11), P( 32, 13), P( 58, 13), P( 62, 11), P( 80, -3), P( 67,  3), P( 26,  8), P( 44,  3), 
-                P( -5,  7), P( 19,  7), P( 26,  7), P( 36,  5), P( 17,  4), P( 45, -3), P( 61, -5), P( 16, -3), 
-                P(-24,  4), P(-11,  3), P(  7, 13), P( 26,  1), P( 24,  2), P( 35,  1), P( -8, -1), P(-20,  2), 
-                P(-36,  3), P(-26,  5), P(-12,  8), P( -1,  4), P(  9, -5), P( -7, -6), P(  6, -8), P(-23,-11), 
-                P(-45, -4), P(-25,  0), P(-16, -5), P(-17, -1), P(  3, -7), P(  0,-12), P( -5, -8), P(-33,-16), 
-                P(-44, -6), P(-16, -6), P(-20,  0), P( -9,  2), P( -1, -9), P( 11, -9), P( -6,-11), P(-71, -3), 
-                P(-19, -9), P(-13,  2), P(  1,  3), P( 17, -1), P( 16, -5), P(  7,-13), P(-37,  4), P(-26,-20), 
-            ],
-
-            //queen:
-            [
-                P(-28, -9), P(  0, 22), P( 29, 22), P( 12, 27), P( 59, 27), P( 44, 19), P( 43, 10), P( 45, 20), 
-                P(-24,-17), P(-39, 20), P( -5, 32), P(  1, 41), P(-16, 58), P( 57, 25), P( 28, 30), P( 54,  0), 
-                P(-13,-20), P(-17,  6), P(  7,  9), P(  8, 49), P( 29, 47), P( 56, 35), P( 47, 19), P( 57,  9), 
-                P(-27,  3), P(-27, 22), P(-16, 24), P(-16, 45), P( -1, 57), P( 17, 40), P( -2, 57), P(  1, 36), 
-                P( -9,-18), P(-26, 28), P( -9, 19), P(-10, 47), P( -2, 31), P( -4, 34), P(  3, 39), P( -3, 23), 
-                P(-14,-16), P(  2,-27), P(-11, 15), P( -2,  6), P( -5,  9), P(  2, 17), P( 14, 10), P(  5,  5), 
-                P(-35,-22), P( -8,-23), P( 11,-30), P(  2,-16), P(  8,-16), P( 15,-23), P( -3,-36), P(  1,-32), 
-                P( -1,-33), P(-18,-28), P( -9,-22), P( 10,-43), P(-15, -5), P(-25,-32), P(-31,-20), P(-50,-41), 
-            ],
-
-            //king:
-            [
-                P(-65,-74), P( 23,-35), P( 16,-18), P(-15,-18), P(-56,-11), P(-34, 15), P(  2,  4), P( 13,-17), 
-                P( 29,-12), P( -1, 17), P(-20, 14), P( -7, 17), P( -8, 17), P( -4, 38), P(-38, 23), P(-29, 11), 
-                P( -9, 10), P( 24, 17), P(  2, 23), P(-16, 15), P(-20, 20), P(  6, 45), P( 22, 44), P(-22, 13), 
-                P(-17, -8), P(-20, 22), P(-12, 24), P(-27, 27), P(-30, 26), P(-25, 33), P(-14, 26), P(-36,  3), 
-                P(-49,-18), P( -1, -4), P(-27, 21), P(-39, 24), P(-46, 27), P(-44, 23), P(-33,  9), P(-51,-11), 
-                P(-14,-19), P(-14, -3), P(-22, 11), P(-46, 21), P(-44, 23), P(-30, 16), P(-15,  7), P(-27, -9), 
-                P(  1,-27), P(  7,-11), P( -8,  4), P(-64, 13), P(-43, 14), P(-16,  4), P(  9, -5), P(  8,-17), 
-                P(-15,-53), P( 36,-34), P( 12,-21), P(-54,-11), P(  8,-28), P(-28,-14), P( 24,-24), P( 14,-43), 
-            ]
-        ],
+        piece_square: default_piece_square(),
         knight_mobility: [ 
             P(-30,-30), P( -7, -7), P(  0,  0), P(  3,  3), P(  6,  6), P( 10, 10), P( 13, 13), P( 15, 15), P( 16, 16) 
         ],
@@ -185,13 +318,46 @@ impl Evaluator {
 
         isolated_pawn: P(-10, -15),
 
+        doubled_isolated_pawn: P(-10, -20),
+
+        backward_pawn: P(-8, -10),
+
+        //by rank, for pawns that are defended by a pawn or have a pawn next to them
+        connected_pawn: [P(3, 2), P(5, 4), P(8, 8), P(14, 14), P(25, 30), P(40, 60)],
+
         passed_pawn: [P(10, 15), P(15, 20), P(20, 25), P(25, 35), P(50, 60), P(75 ,150)],
 
+        passed_pawn_free_path: [P(0, 0), P(0, 2), P(2, 6), P(5, 15), P(10, 30), P(15, 50)],
+
+        passed_pawn_blocked: [P(0, 0), P(-2, -3), P(-4, -6), P(-6, -12), P(-10, -20), P(-15, -35)],
+
+        //per square of distance to the square in front of the pawn and per rank
+        passed_pawn_own_king_distance: P(0, -2),
+
+        passed_pawn_enemy_king_distance: P(0, 5),
+
+        unstoppable_passed_pawn: P(0, 250),
+
         king_attack_ray: [0, -3, -5, -7, -8, -9, -10, -10],
 
         king_ring_attacker: [-6, -5, -5, -8, -12],
 
         king_ring_defender: [0, 0, 0, 0, 0], //TODO: remove if automatic tuning does not show significant change here.
+
+        king_attack_units: [0, 2, 2, 3, 5],
+
+        //by the attack units of all pieces that attack the king ring, if there are at least two
+        king_danger: [
+            0, 0, 1, 2, 4, 6, 8, 11, 14, 18, 22, 27, 32, 38, 44, 50,
+            57, 64, 72, 80, 89, 98, 108, 118, 128, 139, 150, 162, 174, 186, 199, 212
+        ],
+
+        //by the attacked piece
+        threat_by_pawn: [P(0, 0), P(45, 30), P(45, 30), P(55, 35), P(60, 40)],
+
+        threat_by_lower_piece: [P(0, 0), P(0, 0), P(0, 0), P(35, 20), P(40, 25)],
+
+        hanging_piece: P(20, 15),
         
         bishop_outpost: P(5, 5),
 
@@ -204,43 +370,96 @@ impl Evaluator {
 
 
     
-    pub fn new() -> Evaluator {
+    pub fn new(params: &EvalParams) -> Evaluator {
         Evaluator {
-            params: Evaluator::DEFAULT_PARAMS,
+            params: params.clone(),
+            incremental_psqt: params.material == Self::DEFAULT_PARAMS.material && params.piece_square == Self::DEFAULT_PARAMS.piece_square,
             pawn_attacks: [Bitboard::new(); 2],
             king_safety: [0,0],
             king_ring: [Bitboard::new(); 2],
-            outpost_squares: [Bitboard::new(); 2]
+            king_attack_units: [0, 0],
+            king_attackers: [0, 0],
+            outpost_squares: [Bitboard::new(); 2],
+            passed_pawns: [Bitboard::new(); 2],
+            attacked_by: [[Bitboard::new(); 6]; 2],
+            pawn_cache: vec![PawnEntry {
+                pawn_hash: 0,
+                score: P(0, 0),
+                pawn_attacks: [Bitboard::new(); 2],
+                outpost_squares: [Bitboard::new(); 2],
+                passed_pawns: [Bitboard::new(); 2]
+            }; Self::PAWN_CACHE_SIZE],
+            pawn_cache_probes: 0,
+            pawn_cache_hits: 0
         }
     }
+
+    //number of pawn cache probes and hits
+    pub fn pawn_cache_stats(&self) -> (u64, u64) {
+        (self.pawn_cache_probes, self.pawn_cache_hits)
+    }
     
     pub fn evaluate(&mut self, pos: &mut Position) -> Score {
         
-        //initialise fields for king danger evaluation.
+        //initialise fields for king danger and threat evaluation.
         self.king_safety = [0,0];
+        self.king_attack_units = [0, 0];
+        self.king_attackers = [0, 0];
+        self.attacked_by = [[Bitboard::new(); 6]; 2];
+
+        for player in [White, Black] {
+            let king_square = pos.king_square(player);
+            self.king_ring[player as usize] = Bitboard::king_attacks(king_square) | Bitboard::from_square(king_square);
+            self.attacked_by[player as usize][King as usize] = Bitboard::king_attacks(king_square);
+        }
 
-        let king_square = pos.king_square(White);
-        self.king_ring[White as usize] = Bitboard::king_attacks(king_square) | Bitboard::from_square(king_square);
-        let king_square = pos.king_square(Black);
-        self.king_ring[Black as usize] = Bitboard::king_attacks(king_square) | Bitboard::from_square(king_square);
 
+        debug_assert!(!self.incremental_psqt || (pos.psqt(), pos.game_phase()) == (self.psqt(pos), self.game_phase(pos)), "incremental evaluation differs for {}", pos.to_fen_string());
+
+        let signature = MaterialSignature::from_position(pos);
+        if let Some(value) = endgame::evaluate(pos, signature, &self.params.material) {
+            return Score::from_centi_pawns(if pos.current_player() == White { value } else { -value });
+        }
+
+        //material and piece square values are updated incrementally by the position, unless an eval file changed them
+        let (mg, eg) = if self.incremental_psqt { pos.psqt() } else { self.psqt(pos) };
+        let mut eval = P(mg, eg);
 
-        let mut eval = self.material(pos, White) - self.material(pos, Black);
+        eval += self.bishop_pair(pos, White) - self.bishop_pair(pos, Black);
 
-        eval += self.eval_pawns(pos, White) - self.eval_pawns(pos, Black);
+        eval += self.eval_pawns(pos);
+        self.pawn_king_safety(pos, White);
+        self.pawn_king_safety(pos, Black);
+        self.attacked_by[White as usize][Pawn as usize] = self.pawn_attacks[White as usize];
+        self.attacked_by[Black as usize][Pawn as usize] = self.pawn_attacks[Black as usize];
        
-        for s in pos.pieces_by_player(White) {
+        for s in pos.pieces_by_player(White) & !pos.pieces_by_type(Pawn) {
             eval += self.eval_piece(pos, s);
         }
 
-        for s in pos.pieces_by_player(Black) {
+        for s in pos.pieces_by_player(Black) & !pos.pieces_by_type(Pawn) {
             eval -= self.eval_piece(pos, s);
         }
 
+        //terms that need the attacks of all pieces
+        eval += self.passed_pawns(pos, White) - self.passed_pawns(pos, Black);
+        eval += self.threats(pos, White) - self.threats(pos, Black);
+
+        for player in [White, Black] {
+            if self.king_attackers[player as usize] >= 2 {
+                let units = self.king_attack_units[player as usize].clamp(0, self.params.king_danger.len() as i32 - 1);
+                self.king_safety[player as usize] -= self.params.king_danger[units as usize];
+            }
+        }
+
         eval.0 += self.king_safety[White as usize] - self.king_safety[Black as usize];
 
+        //endgames that are harder to win than the material suggests
+        let strong = if eval.1 >= 0 { White } else { Black };
+        eval.1 = eval.1 * endgame::scale_factor(pos, signature, strong, &self.params.material) / SCALE_NORMAL;
+
 
-        let game_phase = self.game_phase(pos);
+        let game_phase = pos.game_phase();
 
         let mut score = ((game_phase as i32 * eval.0) + ((24-game_phase.clamp(0, 24)) as i32 * eval.1)) / 24;
 
@@ -255,24 +474,62 @@ impl Evaluator {
         &self.params
     }
 
-    fn material(&mut self, pos: &mut Position, player: Color) -> P {
+    //material and piece square values from scratch, to check the incremental values of the position or if they are not the defaults
+    fn psqt(&self, pos: &Position) -> (i32, i32) {
+        let mut score = P(0, 0);
+
+        for player in [White, Black] {
+            let sign = if player == White { 1 } else { -1 };
 
-        let material_value = pos.pieces(Pawn, player).count_squares() as i32 * self.params.material[Pawn as usize]
-                            + pos.pieces(Knight, player).count_squares() as i32 * self.params.material[Knight as usize]
-                            + pos.pieces(Bishop, player).count_squares() as i32 * self.params.material[Bishop as usize]
-                            + pos.pieces(Rook, player).count_squares() as i32 * self.params.material[Rook as usize]
-                            + pos.pieces(Queen, player).count_squares() as i32 * self.params.material[Queen as usize];
+            for s in pos.pieces_by_player(player) {
+                let piece = pos.piece_on(s);
+                let material = if piece == King { 0 } else { self.params.material[piece as usize] };
+                let index = if player == Black { s ^ 56 } else { s };
 
-        let mut score = P(material_value, material_value);
+                score += (P(material, material) + self.params.piece_square[piece as usize][index as usize]) * sign;
+            }
+        }
+
+        (score.0, score.1)
+    }
 
+    fn bishop_pair(&self, pos: &Position, player: Color) -> P {
         if pos.pieces(Bishop, player).count_squares() >= 2 {
-            score += self.params.bishop_pair;
+            self.params.bishop_pair
+        } else {
+            P(0, 0)
         }
+    }
+
+    //the pawn structure score from white's point of view. Sets pawn_attacks, outpost_squares and passed_pawns.
+    fn eval_pawns(&mut self, pos: &mut Position) -> P {
+        //unused entries have hash 0 like positions without pawns. Their empty outpost squares are still correct, because outposts need pawn support.
+        let index = pos.pawn_hash() as usize % Self::PAWN_CACHE_SIZE;
+        let entry = self.pawn_cache[index];
+
+        self.pawn_cache_probes += 1;
+        if entry.pawn_hash == pos.pawn_hash() {
+            self.pawn_cache_hits += 1;
+            self.pawn_attacks = entry.pawn_attacks;
+            self.outpost_squares = entry.outpost_squares;
+            self.passed_pawns = entry.passed_pawns;
+            return entry.score;
+        }
+
+        let score = self.pawn_structure(pos, White) - self.pawn_structure(pos, Black);
+
+        self.pawn_cache[index] = PawnEntry {
+            pawn_hash: pos.pawn_hash(),
+            score,
+            pawn_attacks: self.pawn_attacks,
+            outpost_squares: self.outpost_squares,
+            passed_pawns: self.passed_pawns
+        };
 
         score
     }
 
-    fn eval_pawns(&mut self, pos: &mut Position, player: Color) -> P {
+    fn pawn_structure(&mut self, pos: &mut Position, player: Color) -> P {
         let mut score = P(0,0);
 
         let pawn_attacks = match player {
@@ -320,6 +577,36 @@ impl Evaluator {
 
         score += self.params.isolated_pawn * (isolated_pawn_files.count_ones() as i32);
 
+        let doubled_isolated_pawn_files = pawn_files & !(pawn_files << 1) & !(pawn_files >> 1) & stacked_pawn_files;
+
+        score += self.params.doubled_isolated_pawn * (doubled_isolated_pawn_files.count_ones() as i32);
+
+        //connected pawns
+        let pawns = pos.pieces(Pawn, player);
+        let connected_pawns = pawns & (pawn_attacks | pawns.shift(Left) | pawns.shift(Right));
+
+        for s in connected_pawns {
+            score += self.params.connected_pawn[Self::relative_rank(s, player) as usize - 1];
+        }
+
+        //backward pawns: the stop square is attacked by an enemy pawn and no pawn on an adjacent file can defend the pawn anymore
+        let enemy_pawn_attacks = match player {
+            White => pos.pieces(Pawn, Black).shift(DownLeft) | pos.pieces(Pawn, Black).shift(DownRight),
+            Black => pos.pieces(Pawn, White).shift(UpLeft) | pos.pieces(Pawn, White).shift(UpRight)
+        };
+
+        for s in pawns {
+            let adjacent_files = Bitboard::file(s % 8).shift(Left) | Bitboard::file(s % 8).shift(Right);
+            let (not_ahead, stop_square) = match player {
+                White => (Bitboard::from_u64(u64::MAX << (s / 8 * 8)), s - 8),
+                Black => (Bitboard::from_u64(u64::MAX >> ((7 - s / 8) * 8)), s + 8)
+            };
+
+            if !(pawns & adjacent_files).is_empty() && (pawns & adjacent_files & not_ahead).is_empty() && enemy_pawn_attacks.contains(stop_square) {
+                score += self.params.backward_pawn;
+            }
+        }
+
         //enemy passed pawns
         let stoppable = match player {
             Black => in_front_of_pawns | in_front_of_pawns.shift(DownLeft) | in_front_of_pawns.shift(DownRight),
@@ -327,6 +614,7 @@ impl Evaluator {
         };
         
         let enemy_passed_pawns = pos.pieces(Pawn, !player) & !stoppable;
+        self.passed_pawns[!player as usize] = enemy_passed_pawns;
 
         for s in enemy_passed_pawns {
             let rank = match player {
@@ -336,6 +624,87 @@ impl Evaluator {
             score -= self.params.passed_pawn[(rank - 1) as usize];
         }
 
+        //calculate outpost squares
+        self.outpost_squares[!player as usize] = !(in_front_of_pawns.shift(Left) | in_front_of_pawns.shift(Right));
+
+        score
+    }
+
+    //rank 1 to 6 of a pawn, from the point of view of its player
+    fn relative_rank(square: u8, player: Color) -> u8 {
+        match player {
+            White => 7 - square / 8,
+            Black => square / 8
+        }
+    }
+
+    //passed pawn terms that depend on the pieces and can't be cached with the pawn structure
+    fn passed_pawns(&self, pos: &Position, player: Color) -> P {
+        let mut score = P(0, 0);
+
+        let enemy_has_pieces = !(pos.pieces_by_player(!player) & !pos.pieces_by_type(Pawn) & !pos.pieces_by_type(King)).is_empty();
+
+        for s in self.passed_pawns[player as usize] {
+            let rank = Self::relative_rank(s, player) as i32 - 1;
+            let (stop_square, promotion_square) = match player {
+                White => (s - 8, s % 8),
+                Black => (s + 8, 56 + s % 8)
+            };
+            let path = Bitboard::in_between(s, promotion_square) & !Bitboard::from_square(s);
+
+            if (path & pos.occupied()).is_empty() {
+                score += self.params.passed_pawn_free_path[rank as usize];
+            } else if pos.occupied().contains(stop_square) {
+                score += self.params.passed_pawn_blocked[rank as usize];
+            }
+
+            score += self.params.passed_pawn_own_king_distance * (Bitboard::distance(pos.king_square(player), stop_square) as i32 * rank);
+            score += self.params.passed_pawn_enemy_king_distance * (Bitboard::distance(pos.king_square(!player), stop_square) as i32 * rank);
+
+            //rule of the square: the enemy king can't catch the pawn. A pawn on its start square still needs 5 moves.
+            if !enemy_has_pieces && (path & pos.pieces_by_player(player)).is_empty() {
+                let pawn_distance = Bitboard::distance(s, promotion_square).min(5) as i32;
+                let king_distance = Bitboard::distance(pos.king_square(!player), promotion_square) as i32 - (pos.current_player() != player) as i32;
+
+                if king_distance > pawn_distance {
+                    score += self.params.unstoppable_passed_pawn;
+                }
+            }
+        }
+
+        score
+    }
+
+    //bonus for the pieces of the player that attack enemy pieces of higher value or enemy pieces that are not defended
+    fn threats(&self, pos: &Position, player: Color) -> P {
+        let mut score = P(0, 0);
+
+        let attacks = &self.attacked_by[player as usize];
+        let minor_attacks = attacks[Knight as usize] | attacks[Bishop as usize];
+
+        for piece in [Knight, Bishop, Rook, Queen] {
+            let pieces = pos.pieces(piece, !player);
+
+            let by_pawn = pieces & attacks[Pawn as usize];
+            score += self.params.threat_by_pawn[piece as usize] * by_pawn.count_squares() as i32;
+
+            let lower_piece_attacks = match piece {
+                Rook => minor_attacks,
+                Queen => minor_attacks | attacks[Rook as usize],
+                _ => Bitboard::new()
+            };
+            score += self.params.threat_by_lower_piece[piece as usize] * (pieces & lower_piece_attacks & !by_pawn).count_squares() as i32;
+        }
+
+        let attacked = attacks.iter().fold(Bitboard::new(), |b, a| b | *a);
+        let defended = self.attacked_by[!player as usize].iter().fold(Bitboard::new(), |b, a| b | *a);
+        let hanging = pos.pieces_by_player(!player) & !pos.pieces_by_type(Pawn) & !pos.pieces_by_type(King) & attacked & !defended;
+
+        score + self.params.hanging_piece * hanging.count_squares() as i32
+    }
+
+    //king safety terms of the pawns, they depend on the king positions and can't be cached with the pawn structure
+    fn pawn_king_safety(&mut self, pos: &mut Position, player: Color) {
         //king ring attacks
         let king_ring_attack_squares = match player {
             Black => self.king_ring[White as usize].shift(UpLeft) | self.king_ring[White as usize].shift(UpRight),
@@ -351,11 +720,6 @@ impl Evaluator {
         };
 
         self.king_safety[player as usize] += self.params.king_ring_defender[Pawn as usize] * (pos.pieces(Pawn, player) & king_ring_defender_squares).count_squares() as i32;
-
-        //calculate outpost squares
-        self.outpost_squares[!player as usize] = !(in_front_of_pawns.shift(Left) | in_front_of_pawns.shift(Right));
-
-        score
     }
 
     fn eval_piece(&mut self, pos: &mut Position, square: u8) -> P {
@@ -363,15 +727,6 @@ impl Evaluator {
         let piece = pos.piece_on(square);
         let player = pos.square_color(square).unwrap();
 
-        //piece square
-
-        let mut piece_square_value_index = square;
-        if player == Black {
-            piece_square_value_index ^= 56;
-        }
-
-        score += self.params.piece_square[piece as usize][piece_square_value_index as usize];
-
         if piece == King {
             //count the number of squares between the king and the next own piece in diagonal, antidiagonal and vertical direction.
 
@@ -383,7 +738,7 @@ impl Evaluator {
             self.king_safety[player as usize] += self.params.king_attack_ray[antidiagonal_rays.count_squares() as usize];
             self.king_safety[player as usize] += self.params.king_attack_ray[file_rays.count_squares() as usize];
 
-        } else if piece != Pawn {
+        } else {
 
             let attacks = match piece {
                 Knight => Bitboard::knight_attacks(square),
@@ -405,9 +760,13 @@ impl Evaluator {
             };
 
 
+            self.attacked_by[player as usize][piece as usize] |= attacks;
+
             //king ring attacks
             if !(self.king_ring[!player as usize] & attacks).is_empty() {
                 self.king_safety[!player as usize] += self.params.king_ring_attacker[piece as usize];
+                self.king_attack_units[!player as usize] += self.params.king_attack_units[piece as usize];
+                self.king_attackers[!player as usize] += 1;
             }
 
             //king ring defenders
@@ -416,13 +775,11 @@ impl Evaluator {
             }
 
             //outposts
-            if piece == Knight || piece == Bishop {
-                if (self.outpost_squares[player as usize] & self.pawn_attacks[player as usize]).contains(square) {
-                    score += if piece == Knight {
-                        self.params.knight_outpost
-                    } else {
-                        self.params.bishop_outpost
-                    }
+            if (piece == Knight || piece == Bishop) && (self.outpost_squares[player as usize] & self.pawn_attacks[player as usize]).contains(square) {
+                score += if piece == Knight {
+                    self.params.knight_outpost
+                } else {
+                    self.params.bishop_outpost
                 }
             }
 
@@ -440,10 +797,144 @@ impl Evaluator {
         score
     }
 
-    fn game_phase(&mut self, pos: &mut Position) -> u32 {
-          pos.pieces_by_type(Bishop).count_squares()
-        + pos.pieces_by_type(Knight).count_squares()
-        + pos.pieces_by_type(Rook).count_squares() * 2
-        + pos.pieces_by_type(Queen).count_squares() * 4
+    fn game_phase(&self, pos: &Position) -> u32 {
+          pos.pieces_by_type(Bishop).count_squares() * phase_value(Bishop)
+        + pos.pieces_by_type(Knight).count_squares() * phase_value(Knight)
+        + pos.pieces_by_type(Rook).count_squares() * phase_value(Rook)
+        + pos.pieces_by_type(Queen).count_squares() * phase_value(Queen)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    //the passed pawn score of each player with the default parameters and without the given terms
+    fn passed_pawn_terms(fen: &str, disable: impl Fn(&mut EvalParams)) -> ([P; 2], [P; 2]) {
+        let mut pos = Position::from_fen_string(fen).unwrap();
+        let mut evaluator = Evaluator::new(&EvalParams::default());
+        evaluator.eval_pawns(&mut pos);
+
+        let default = [evaluator.passed_pawns(&pos, White), evaluator.passed_pawns(&pos, Black)];
+        disable(&mut evaluator.params);
+        let without = [evaluator.passed_pawns(&pos, White), evaluator.passed_pawns(&pos, Black)];
+
+        (default, without)
+    }
+
+    #[test]
+    fn pawn_cache() {
+        fn compare_rec(pos: &mut Position, cached: &mut Evaluator, depth: u32) {
+            assert_eq!(cached.evaluate(pos), Evaluator::new(&EvalParams::default()).evaluate(pos), "{}", pos.to_fen_string());
+            if depth > 0 {
+                for m in pos.legal_moves() {
+                    pos.make_move(m);
+                    compare_rec(pos, cached, depth - 1);
+                    pos.unmake_move(m);
+                }
+            }
+        }
+
+        let mut evaluator = Evaluator::new(&EvalParams::default());
+        compare_rec(&mut Position::from_fen_string("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap(), &mut evaluator, 2);
+
+        let (probes, hits) = evaluator.pawn_cache_stats();
+        assert!(hits > 0 && hits < probes);
+    }
+
+    //the evaluation does not change if the board is flipped and the colors are swapped
+    #[test]
+    fn eval_symmetry() {
+        fn flip(fen: &str) -> String {
+            let fields = fen.split(' ').collect::<Vec<_>>();
+            let swap_case = |s: &str| s.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect::<String>();
+
+            let board = fields[0].split('/').rev().map(swap_case).collect::<Vec<_>>().join("/");
+            let player = if fields[1] == "w" { "b" } else { "w" };
+            let en_passant = fields[3].replace('3', "6x").replace('6', "3").replace("3x", "6");
+
+            //white's castling rights come first
+            let mut castling = swap_case(fields[2]).chars().collect::<Vec<_>>();
+            castling.sort_by_key(|c| c.is_ascii_lowercase());
+
+            format!("{board} {player} {} {en_passant} {} {}", castling.iter().collect::<String>(), fields[4], fields[5])
+        }
+
+        for fen in [
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+            "6k1/5ppp/8/1P6/8/8/5PPP/6K1 b - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "2r3k1/1q3ppp/p2p4/1p1Pp3/4P1n1/1PN2N1P/1P3PP1/2RQ2K1 w - - 0 1"
+        ] {
+            let mut pos = Position::from_fen_string(fen).unwrap();
+            let mut flipped = Position::from_fen_string(&flip(fen)).unwrap();
+            assert_eq!(Evaluator::new(&EvalParams::default()).evaluate(&mut pos), Evaluator::new(&EvalParams::default()).evaluate(&mut flipped), "{fen}");
+        }
+    }
+
+    #[test]
+    fn passed_pawn_free_path() {
+        let (default, without) = passed_pawn_terms("7k/8/7p/P7/8/8/8/4K3 w - - 0 1", |p| p.passed_pawn_free_path = [P(0, 0); 6]);
+        assert_ne!(default[White as usize], without[White as usize]);
+        assert_ne!(default[Black as usize], without[Black as usize]);
+
+        //a blocked pawn gets the blocked penalty instead
+        let (default, without) = passed_pawn_terms("7k/8/8/n7/P7/8/8/4K3 w - - 0 1", |p| p.passed_pawn_free_path = [P(0, 0); 6]);
+        assert_eq!(default[White as usize], without[White as usize]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn passed_pawn_king_distance() {
+        //the part of the white passed pawn score that the king distance terms contribute
+        let king_distance = |fen: &str| {
+            let (default, without) = passed_pawn_terms(fen, |p| {
+                p.passed_pawn_own_king_distance = P(0, 0);
+                p.passed_pawn_enemy_king_distance = P(0, 0);
+            });
+            (default[White as usize] - without[White as usize]).1
+        };
+
+        let own_king_far = king_distance("k7/8/8/4P3/8/8/8/K7 w - - 0 1");
+        let own_king_near = king_distance("k7/8/3K4/4P3/8/8/8/8 w - - 0 1");
+        let enemy_king_near = king_distance("8/3k4/8/4P3/8/8/8/K7 w - - 0 1");
+        assert!(own_king_near > own_king_far);
+        assert!(enemy_king_near < own_king_far);
+    }
+
+    #[test]
+    fn unstoppable_passed_pawn() {
+        let disable = |p: &mut EvalParams| p.unstoppable_passed_pawn = P(0, 0);
+
+        //the black king is too far away, the white king can catch the h-pawn
+        let (default, without) = passed_pawn_terms("7k/8/7p/P7/8/8/8/4K3 w - - 0 1", disable);
+        assert_ne!(default[White as usize], without[White as usize]);
+        assert_eq!(default[Black as usize], without[Black as usize]);
+
+        //the defending king is on the promotion square and to move
+        let (default, without) = passed_pawn_terms("4k3/p7/8/4P3/8/8/8/4K3 b - - 0 1", disable);
+        assert_eq!(default, without);
+    }
+
+    #[test]
+    fn eval_file() {
+        let path = std::env::temp_dir().join(format!("carlito_eval_{}.txt", std::process::id()));
+        EvalParams::default().save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(saved.contains("\nbishop_pair 10 10\n"));
+
+        let mut position = Position::from_fen_string("rn1qkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+        let default_eval = Evaluator::new(&EvalParams::default()).evaluate(&mut position);
+
+        let params = EvalParams::parse(&saved.replace("\nbishop_pair 10 10\n", "\nbishop_pair 110 110 # strong bishops\n")).unwrap();
+        assert_eq!(Evaluator::new(&params).evaluate(&mut position).s, default_eval.s + 100);
+
+        assert!(matches!(EvalParams::parse("bishop_pairs 10 10"), Err(EvalFileError::UnknownParameter(_))));
+        assert!(matches!(EvalParams::parse("bishop_pair 10"), Err(EvalFileError::WrongValueCount { expected: 2, actual: 1, .. })));
+        assert!(matches!(EvalParams::parse("bishop_pair 10 x"), Err(EvalFileError::InvalidNumber(_))));
+    }
+}
@@ -0,0 +1,304 @@
+use std::sync::OnceLock;
+
+use crate::bitboard::{Bitboard, Direction::*};
+use crate::position::{Color::{self, *}, Piece::*, Position};
+
+/*
+ * bitbase for king and pawn against king, generated by retrograde analysis on first use.
+ *
+ * the pawn is always white and, by mirroring the board, on the files a to d. A position is indexed by
+ * the side to move, both king squares and the pawn square, and the bitbase stores one bit per
+ * position: set if white wins, not set if it is a draw or not a legal position.
+ */
+
+//2 sides to move * 64 black king squares * 64 white king squares * 24 pawn squares
+const SIZE: usize = 2 * 64 * 64 * 24;
+
+static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq)]
+enum Result {
+    Invalid,
+    Unknown,
+    Draw,
+    Win
+}
+
+//generates the bitbase, if that did not happen yet. Probing does this as well, but it takes a moment.
+pub fn init() {
+    BITBASE.get_or_init(generate);
+}
+
+//true if the side with the pawn wins, or None if the position is not king and pawn against king
+pub fn probe_position(pos: &Position) -> Option<bool> {
+    if pos.occupied().count_squares() != 3 || pos.pieces_by_type(Pawn).count_squares() != 1 {
+        return None;
+    }
+
+    let pawn = pos.pieces_by_type(Pawn).into_iter().next().unwrap();
+    let strong = pos.square_color(pawn).unwrap();
+
+    Some(probe(pos.king_square(strong), pawn, pos.king_square(!strong), strong, pos.current_player() == strong))
+}
+
+//true if the side of the strong king and the pawn wins
+pub fn probe(strong_king: u8, pawn: u8, weak_king: u8, strong: Color, strong_to_move: bool) -> bool {
+    //flip the board, so that the pawn moves up, and mirror it, so that the pawn is on the files a to d
+    let flip = if strong == White { 0 } else { 56 };
+    let mirror = if (pawn ^ flip) % 8 > 3 { 7 } else { 0 };
+    let normalize = |s: u8| s ^ flip ^ mirror;
+
+    let index = index(normalize(strong_king), normalize(pawn), normalize(weak_king), strong_to_move);
+    let bitbase = BITBASE.get_or_init(generate);
+
+    bitbase[index / 64] & (1 << (index % 64)) != 0
+}
+
+//the pawn is on the rows 1 to 6 and the files a to d
+fn index(white_king: u8, pawn: u8, black_king: u8, white_to_move: bool) -> usize {
+    let pawn_index = (pawn / 8 - 1) as usize * 4 + (pawn % 8) as usize;
+    white_to_move as usize | (black_king as usize) << 1 | (white_king as usize) << 7 | pawn_index << 13
+}
+
+fn pawn_attacks(pawn: u8) -> Bitboard {
+    Bitboard::from_square(pawn).shift(UpLeft) | Bitboard::from_square(pawn).shift(UpRight)
+}
+
+fn generate() -> Vec<u64> {
+    let mut results = vec![Result::Invalid; SIZE];
+
+    for_each_position(|white_king, pawn, black_king, white_to_move| {
+        results[index(white_king, pawn, black_king, white_to_move)] = initial_result(white_king, pawn, black_king, white_to_move);
+    });
+
+    //retrograde passes until no position changes anymore
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for_each_position(|white_king, pawn, black_king, white_to_move| {
+            let i = index(white_king, pawn, black_king, white_to_move);
+            if results[i] == Result::Unknown {
+                results[i] = classify(&results, white_king, pawn, black_king, white_to_move);
+                changed |= results[i] != Result::Unknown;
+            }
+        });
+    }
+
+    let mut bitbase = vec![0; SIZE / 64];
+    for (i, result) in results.iter().enumerate() {
+        if *result == Result::Win {
+            bitbase[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    bitbase
+}
+
+fn for_each_position(mut f: impl FnMut(u8, u8, u8, bool)) {
+    for pawn in (8..56).filter(|s| s % 8 < 4) {
+        for white_king in 0..64 {
+            for black_king in 0..64 {
+                for white_to_move in [false, true] {
+                    f(white_king, pawn, black_king, white_to_move);
+                }
+            }
+        }
+    }
+}
+
+//results that do not depend on other positions
+fn initial_result(white_king: u8, pawn: u8, black_king: u8, white_to_move: bool) -> Result {
+    if Bitboard::distance(white_king, black_king) <= 1 || white_king == pawn || black_king == pawn
+            || (white_to_move && pawn_attacks(pawn).contains(black_king)) {
+        return Result::Invalid;
+    }
+
+    if white_to_move {
+        //the pawn promotes and the queen can not be captured
+        let promotion_square = pawn - 8;
+        if pawn / 8 == 1 && promotion_square != white_king && promotion_square != black_king
+                && (Bitboard::distance(black_king, promotion_square) > 1 || Bitboard::distance(white_king, promotion_square) == 1) {
+            return Result::Win;
+        }
+    } else {
+        let black_moves = Bitboard::king_attacks(black_king) & !(Bitboard::king_attacks(white_king) | pawn_attacks(pawn));
+
+        //stalemate, or the pawn can be captured
+        if black_moves.is_empty() || black_moves.contains(pawn) {
+            return Result::Draw;
+        }
+    }
+
+    Result::Unknown
+}
+
+/*
+ * white wins if one of its moves wins and draws if all of them draw. Black draws if one of its moves
+ * draws and loses if all of them lose.
+ */
+fn classify(results: &[Result], white_king: u8, pawn: u8, black_king: u8, white_to_move: bool) -> Result {
+    let (good, bad) = if white_to_move { (Result::Win, Result::Draw) } else { (Result::Draw, Result::Win) };
+    let mut all_bad = true;
+
+    let mut check = |result: Result| -> bool {
+        all_bad &= result == bad;
+        result == good
+    };
+
+    if white_to_move {
+        let king_moves = Bitboard::king_attacks(white_king) & !Bitboard::king_attacks(black_king) & !Bitboard::from_square(pawn);
+        for to in king_moves {
+            if check(results[index(to, pawn, black_king, false)]) {
+                return good;
+            }
+        }
+
+        //promotions are handled by the initial results, so only pushes up to the seventh rank are left
+        let push = pawn - 8;
+        if pawn / 8 > 1 && push != white_king && push != black_king {
+            if check(results[index(white_king, push, black_king, false)]) {
+                return good;
+            }
+
+            let double_push = pawn - 16;
+            if pawn / 8 == 6 && double_push != white_king && double_push != black_king
+                    && check(results[index(white_king, double_push, black_king, false)]) {
+                return good;
+            }
+        }
+    } else {
+        let king_moves = Bitboard::king_attacks(black_king) & !(Bitboard::king_attacks(white_king) | pawn_attacks(pawn));
+        for to in king_moves {
+            if check(results[index(white_king, pawn, to, true)]) {
+                return good;
+            }
+        }
+    }
+
+    if all_bad { bad } else { Result::Unknown }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn wins(fen: &str) -> bool {
+        probe_position(&Position::from_fen_string(fen).unwrap()).unwrap()
+    }
+
+    fn fen(white_king: u8, pawn: u8, black_king: u8, white_to_move: bool) -> String {
+        let mut rows = Vec::new();
+        for row in 0..8 {
+            let mut fen_row = String::new();
+            let mut empty = 0;
+
+            for square in 8*row..8*row + 8 {
+                let piece = if square == white_king { 'K' } else if square == black_king { 'k' } else if square == pawn { 'P' } else { ' ' };
+                if piece == ' ' {
+                    empty += 1;
+                } else {
+                    if empty > 0 {
+                        fen_row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen_row.push(piece);
+                }
+            }
+            if empty > 0 {
+                fen_row.push_str(&empty.to_string());
+            }
+            rows.push(fen_row);
+        }
+
+        format!("{} {} - - 0 1", rows.join("/"), if white_to_move { "w" } else { "b" })
+    }
+
+    #[test]
+    fn known_results() {
+        //rule of the square
+        assert!(wins("8/8/8/8/4P3/8/k7/4K3 w - - 0 1"));
+        assert!(!wins("8/8/8/8/4P3/k7/8/7K b - - 0 1"));
+
+        //the king on a key square wins with either side to move
+        assert!(wins("8/8/4k3/8/4K3/8/4P3/8 w - - 0 1"));
+        assert!(wins("8/8/4k3/8/4K3/8/4P3/8 b - - 0 1"));
+        assert!(wins("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"));
+        assert!(wins("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"));
+
+        //opposition
+        assert!(wins("4k3/8/8/4K3/4P3/8/8/8 w - - 0 1"));
+        assert!(!wins("4k3/8/8/4K3/4P3/8/8/8 b - - 0 1"));
+
+        //rook pawns are a draw if the defending king reaches the corner
+        assert!(!wins("k7/8/1K6/P7/8/8/8/8 w - - 0 1"));
+        assert!(!wins("8/8/8/8/8/8/p5k1/K7 w - - 0 1"));
+
+        //stalemate
+        assert!(!wins("k7/P7/1K6/8/8/8/8/8 b - - 0 1"));
+        assert!(!wins("5k2/5P2/5K2/8/8/8/8/8 b - - 0 1"));
+        assert!(!wins("8/8/8/8/8/4k3/4p3/4K3 w - - 0 1"));
+
+        //the pawn is lost
+        assert!(!wins("8/8/8/8/8/4k3/3P4/7K b - - 0 1"));
+
+        //black pawns
+        assert!(wins("8/8/8/8/4k3/8/4p3/1K6 w - - 0 1"));
+
+        assert!(probe_position(&Position::new()).is_none());
+    }
+
+    #[test]
+    fn symmetry() {
+        //mirrored and color flipped positions have the same result
+        for (white, flipped) in [
+            ("8/8/4k3/8/4K3/4P3/8/8 w - - 0 1", "8/8/3p4/3k4/8/3K4/8/8 b - - 0 1"),
+            ("8/8/8/8/4k3/8/1P6/5K2 b - - 0 1", "5k2/6p1/8/3K4/8/8/8/8 w - - 0 1")
+        ] {
+            assert_eq!(wins(white), wins(flipped), "{white} {flipped}");
+        }
+    }
+
+    /*
+     * checks every position against the results of its legal moves, generated by Position. A
+     * promotion wins if the new piece can not be captured.
+     */
+    #[test]
+    fn consistent_with_move_generator() {
+        for_each_position(|white_king, pawn, black_king, white_to_move| {
+            if initial_result(white_king, pawn, black_king, white_to_move) == Result::Invalid {
+                return;
+            }
+
+            let fen = fen(white_king, pawn, black_king, white_to_move);
+            let mut pos = Position::from_fen_string(&fen).unwrap();
+
+            let moves = pos.legal_moves();
+            let results = moves.iter().map(|&m| {
+                pos.make_move(m);
+                let win = match probe_position(&pos) {
+                    Some(win) => win,
+                    None if m.promote_to().is_some() => {
+                        let replies = pos.legal_moves();
+                        if replies.is_empty() {
+                            pos.in_check()
+                        } else {
+                            !replies.iter().any(|r| r.to() == m.to())
+                        }
+                    },
+                    None => false
+                };
+                pos.unmake_move(m);
+                win
+            }).collect::<Vec<_>>();
+
+            let expected = if white_to_move {
+                results.iter().any(|w| *w)
+            } else {
+                !results.is_empty() && results.iter().all(|w| *w)
+            };
+
+            assert_eq!(probe_position(&pos), Some(expected), "{fen}");
+        });
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::chess_move::Move;
+
+use super::score::Score;
+
+//summary of a completed iteration of the iterative deepening loop
+#[derive(Debug, Clone)]
+pub struct IterationReport {
+    pub depth: u16,
+    pub seldepth: u16,
+    pub score: Score,
+    pub nodes: u64,
+    pub tbhits: u64,
+    pub time: Duration,
+    pub hashfull: u16,
+    pub pv: Vec<Move>
+}
+
+//periodic update about a long running iteration
+#[derive(Debug, Clone)]
+pub struct ProgressReport {
+    pub nodes: u64,
+    pub tbhits: u64,
+    pub time: Duration,
+    pub hashfull: u16
+}
+
+/**
+ * receives the results of a search. The methods are called from the search thread, so implementations
+ * should return quickly. best_move is called exactly once at the end of every search. It gets None if
+ * the root position has no legal moves.
+ */
+pub trait SearchObserver: Send + Sync {
+    fn iteration_complete(&self, report: &IterationReport);
+
+    fn best_move(&self, best_move: Option<Move>, ponder_move: Option<Move>);
+
+    //the root move that is searched at the moment. Only reported once an iteration takes a while.
+    fn current_move(&self, _m: Move, _move_number: usize, _depth: u16) { }
+
+    //called about once a second while the search is running
+    fn progress(&self, _report: &ProgressReport) { }
+
+    //diagnostic messages about the search, like the time limits
+    fn debug(&self, _message: &str) { }
+}
+
+//ignores all results, for searches whose result is only needed as the return value of Engine::search_sync
+pub struct SilentObserver;
+
+impl SearchObserver for SilentObserver {
+    fn iteration_complete(&self, _report: &IterationReport) { }
+
+    fn best_move(&self, _best_move: Option<Move>, _ponder_move: Option<Move>) { }
+}
+
+impl IterationReport {
+    pub fn nps(&self) -> Option<u64> {
+        nps(self.nodes, self.time)
+    }
+}
+
+impl ProgressReport {
+    pub fn nps(&self) -> Option<u64> {
+        nps(self.nodes, self.time)
+    }
+}
+
+fn nps(nodes: u64, time: Duration) -> Option<u64> {
+    let ms = time.as_millis() as u64;
+    if ms > 50 {
+        Some(nodes * 1000 / ms)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chess_move::Move;
+
+use super::score::Score;
+
+/*
+ * weakened play for the UCI_LimitStrength, UCI_Elo and Skill Level options.
+ *
+ * a skill level below MAX_SKILL_LEVEL weakens the engine in three ways:
+ *   - the search is limited to a number of nodes that grows exponentially with the level
+ *   - the static evaluation gets a pseudo random offset of up to eval_noise() centipawns
+ *   - the root is searched with several principal variations, and the played move is picked
+ *     randomly among them. Moves that lose more compared to the best one are picked less often.
+ *
+ * the elo mapping was measured with self-play, because there is no match runner yet. Every level
+ * from 0 to 20 in steps of 4 played 40 games against the next one (100 ms per move, alternating
+ * colors, random openings of 4 plies), see the ignored test 'skill_level_self_play':
+ *
+ *   level  4 vs  0: 31.5/40, +228
+ *   level  8 vs  4: 36.5/40, +407
+ *   level 12 vs  8: 39.5/40, +759 (nearly a whitewash, so this step is only a rough estimate)
+ *   level 16 vs 12: 36.0/40, +382
+ *   level 20 vs 16: 36.0/40, +382
+ *
+ * levels in between are interpolated linearly. Self-play only measures differences, the absolute
+ * values are an assumption: the full strength engine is anchored at MAX_ELO. With 40 games per
+ * step the error margin is large, so the mapping should be re-measured once a match runner exists.
+ */
+
+pub const MAX_SKILL_LEVEL: u8 = 20;
+
+//the measured elo of every fourth skill level
+const ELO_BY_LEVEL: [(u8, u32); 6] = [(0, 240), (4, 470), (8, 880), (12, 1640), (16, 2020), (20, 2400)];
+
+pub const MIN_ELO: u32 = ELO_BY_LEVEL[0].1;
+pub const MAX_ELO: u32 = ELO_BY_LEVEL[ELO_BY_LEVEL.len() - 1].1;
+
+const MULTI_PV: usize = 4;
+
+//the highest skill level that does not play stronger than the given elo
+pub fn skill_level_from_elo(elo: u32) -> u8 {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+
+    for w in ELO_BY_LEVEL.windows(2) {
+        let ((level_low, elo_low), (level_high, elo_high)) = (w[0], w[1]);
+        if elo <= elo_high {
+            return level_low + ((elo - elo_low) * (level_high - level_low) as u32 / (elo_high - elo_low)) as u8;
+        }
+    }
+
+    MAX_SKILL_LEVEL
+}
+
+#[derive(Debug, Clone)]
+pub struct Strength {
+    skill_level: u8,
+    rng: Rng
+}
+
+impl Strength {
+    //returns None for full strength
+    pub fn new(skill_level: u8) -> Option<Strength> {
+        if skill_level >= MAX_SKILL_LEVEL {
+            None
+        } else {
+            Some(Strength {
+                skill_level,
+                rng: Rng::from_time()
+            })
+        }
+    }
+
+    //replaces the seed from the clock, so the noise and the picked moves can be reproduced
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn node_limit(&self) -> u64 {
+        //from 400 nodes at level 0 to about 475000 at level 19
+        (400.0 * 1.45_f64.powi(self.skill_level as i32)) as u64
+    }
+
+    pub fn multi_pv(&self) -> usize {
+        MULTI_PV
+    }
+
+    pub fn eval_noise(&self) -> i32 {
+        8 * (MAX_SKILL_LEVEL - self.skill_level) as i32
+    }
+
+    pub fn noise_seed(&self) -> u64 {
+        self.rng.state
+    }
+
+    //the pseudo random evaluation offset in centipawns. It only depends on the position, so transpositions get the same offset.
+    pub fn noise(seed: u64, hash: u64, amplitude: i32) -> i32 {
+        if amplitude == 0 {
+            return 0;
+        }
+
+        let r = Rng::mix(hash ^ seed);
+        (r % (2 * amplitude as u64 + 1)) as i32 - amplitude
+    }
+
+    /**
+     * picks one of the best moves. candidates are sorted by score, the best move first. The lower
+     * the skill level, the more likely weaker moves get picked.
+     */
+    pub fn pick_move(&mut self, candidates: &[(Move, Score)]) -> Option<Move> {
+        let (_, best_score) = *candidates.first()?;
+
+        //mate scores are not randomized, the engine should not throw away a forced mate
+        if best_score.mate().is_some() {
+            return Some(candidates[0].0);
+        }
+
+        let weakness = 120 - 2 * self.skill_level as i32;
+        let worst_score = candidates.last().unwrap().1.centi_pawns().unwrap_or(i16::MIN) as i32;
+        let delta = (best_score.s as i32 - worst_score).min(100);
+
+        let mut best = (candidates[0].0, i32::MIN);
+
+        for &(m, score) in candidates {
+            let score = match score.centi_pawns() {
+                Some(s) => s as i32,
+                None => continue
+            };
+
+            //a bonus that is random and larger for moves that are worse
+            let push = (weakness * (best_score.s as i32 - score) + delta * (self.rng.next() % weakness as u64) as i32) / 128;
+
+            if score + push > best.1 {
+                best = (m, score + push);
+            }
+        }
+
+        Some(best.0)
+    }
+}
+
+//xorshift random number generator
+#[derive(Debug, Clone)]
+pub(super) struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub(super) fn new(seed: u64) -> Rng {
+        Rng {
+            state: Self::mix(seed) | 1
+        }
+    }
+
+    pub(super) fn from_time() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    pub(super) fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    //splitmix64 finalizer
+    fn mix(mut x: u64) -> u64 {
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^ (x >> 31)
+    }
+}
@@ -0,0 +1,773 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::bitboard::Bitboard;
+use crate::position::{Color::{self, *}, Piece::{self, *}, Position};
+
+use super::endgame::MaterialSignature;
+
+/*
+ * endgame tablebases with the distance to mate, generated by retrograde analysis for up to 4 pieces.
+ *
+ * a table covers one material distribution, named like "KQKR": the pieces up to the second king are
+ * white. The same table is used with the colors swapped. Positions are indexed by the side to move
+ * and the squares of the pieces, the white king first. The board is mirrored, flipped and transposed,
+ * so that the white king is in the triangle a8-a5-d5. Tables with pawns are only mirrored, so that the
+ * white king is on the files a to d.
+ *
+ * generation:
+ *   - every position is set up and its moves are generated. Mates and stalemates are final. Captures
+ *     and promotions lead to smaller tables, which are generated first.
+ *   - pass n finds the positions with a distance to mate of n plies. Un-moves from the positions that
+ *     are lost in n - 1 plies lead to positions that are won in n plies. Un-moves from the positions
+ *     that are won in n - 1 plies lead to candidates for a loss, which are verified with their moves.
+ *   - positions that are not resolved when the passes find no new positions are draws.
+ *
+ * castling is not possible in the tables, en passant captures and the 50 move rule are ignored.
+ */
+
+pub const MAX_PIECES: usize = 4;
+
+const FILE_MAGIC: [u8; 8] = *b"CRLEGTB\0";
+const FILE_VERSION: u32 = 1;
+const FILE_EXTENSION: &str = "crtb";
+
+//the white king squares of tables without pawns: the triangle a8-a5-d5
+const TRIANGLE: [u8; 10] = [0, 8, 9, 16, 17, 18, 24, 25, 26, 27];
+
+//the result for the side to move, the distance to mate is in plies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dtm {
+    Draw,
+    Win(u8),
+    Loss(u8)
+}
+
+impl Dtm {
+    //the result of the previous position, if the current one was reached by a move
+    fn before_move(self) -> Dtm {
+        match self {
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Win(d) => Dtm::Loss(d + 1),
+            Dtm::Loss(d) => Dtm::Win(d + 1)
+        }
+    }
+
+    //higher is better for the side to move
+    fn rating(self) -> i32 {
+        match self {
+            Dtm::Win(d) => 1000 - d as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(d) => d as i32 - 1000
+        }
+    }
+
+    //0 for draws and invalid positions, the distance + 1 otherwise. Won positions have an odd distance.
+    fn to_byte(self) -> u8 {
+        match self {
+            Dtm::Draw => 0,
+            Dtm::Win(d) | Dtm::Loss(d) => d + 1
+        }
+    }
+
+    fn from_byte(b: u8) -> Dtm {
+        match b {
+            0 => Dtm::Draw,
+            b if (b - 1) % 2 == 1 => Dtm::Win(b - 1),
+            b => Dtm::Loss(b - 1)
+        }
+    }
+}
+
+//the state of a position during generation
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Invalid,
+    Unknown,
+    Final(Dtm)
+}
+
+#[derive(Clone, Copy)]
+struct Symmetry {
+    mirror: u8,
+    flip: u8,
+    transpose: bool
+}
+
+impl Symmetry {
+    fn apply(&self, square: u8) -> u8 {
+        let s = square ^ self.mirror ^ self.flip;
+        if self.transpose { (s % 8) * 8 + s / 8 } else { s }
+    }
+}
+
+pub struct Table {
+    name: String,
+    signature: MaterialSignature,
+    //the white king, the black king, then the other white and black pieces
+    pieces: Vec<(Piece, Color)>,
+    has_pawns: bool,
+    values: Vec<u8>
+}
+
+impl Table {
+    fn new(name: &str) -> Result<Table, TablebaseError> {
+        let invalid = || TablebaseError::InvalidName(name.to_string());
+
+        if name.len() < 3 || name.len() > MAX_PIECES || !name.starts_with('K') || name.matches('K').count() != 2 {
+            return Err(invalid());
+        }
+
+        let mut pieces = vec![(King, White), (King, Black)];
+        let mut player = White;
+        for c in name.chars().skip(1) {
+            let piece = match c {
+                'K' => {
+                    player = Black;
+                    continue;
+                },
+                'P' => Pawn,
+                'N' => Knight,
+                'B' => Bishop,
+                'R' => Rook,
+                'Q' => Queen,
+                _ => return Err(invalid())
+            };
+            pieces.push((piece, player));
+        }
+
+        Ok(Table {
+            name: name.to_string(),
+            signature: MaterialSignature::from_pieces(name),
+            has_pawns: pieces.iter().any(|(p, _)| *p == Pawn),
+            pieces,
+            values: Vec::new()
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //the longest forced mate in moves
+    pub fn longest_mate(&self) -> u8 {
+        self.values.iter().filter_map(|&b| match Dtm::from_byte(b) {
+            Dtm::Win(d) => Some(d.div_ceil(2)),
+            _ => None
+        }).max().unwrap_or(0)
+    }
+
+    fn king_squares(&self) -> usize {
+        if self.has_pawns { 32 } else { TRIANGLE.len() }
+    }
+
+    fn len(&self) -> usize {
+        2 * self.king_squares() * 64_usize.pow(self.pieces.len() as u32 - 1)
+    }
+
+    /*
+     * the symmetry that moves the white king to the squares covered by the index. If the white king
+     * is on the diagonal, the first piece off the diagonal decides about the transposition, so that
+     * every position has only one index.
+     */
+    fn symmetry(&self, squares: &[u8]) -> Symmetry {
+        let white_king = squares[0];
+        let mirror = if white_king % 8 > 3 { 7 } else { 0 };
+        if self.has_pawns {
+            return Symmetry { mirror, flip: 0, transpose: false };
+        }
+
+        let flip = if white_king / 8 > 3 { 56 } else { 0 };
+        let transpose = squares.iter()
+            .map(|&s| s ^ mirror ^ flip)
+            .find(|s| s % 8 != s / 8)
+            .is_some_and(|s| s % 8 > s / 8);
+
+        Symmetry { mirror, flip, transpose }
+    }
+
+    fn index(&self, squares: &[u8], white_to_move: bool) -> usize {
+        let symmetry = self.symmetry(squares);
+
+        let king = symmetry.apply(squares[0]);
+        let king_index = if self.has_pawns {
+            (king / 8 * 4 + king % 8) as usize
+        } else {
+            TRIANGLE.iter().position(|&s| s == king).unwrap()
+        };
+
+        let mut index = white_to_move as usize * self.king_squares() + king_index;
+        for &s in &squares[1..self.pieces.len()] {
+            index = index * 64 + symmetry.apply(s) as usize;
+        }
+
+        index
+    }
+
+    fn squares(&self, mut index: usize) -> ([u8; MAX_PIECES], bool) {
+        let mut squares = [0; MAX_PIECES];
+        for i in (1..self.pieces.len()).rev() {
+            squares[i] = (index % 64) as u8;
+            index /= 64;
+        }
+
+        let king_index = index % self.king_squares();
+        squares[0] = if self.has_pawns { (king_index / 4 * 8 + king_index % 4) as u8 } else { TRIANGLE[king_index] };
+
+        (squares, index / self.king_squares() == 1)
+    }
+
+    //None if the index does not belong to a legal position, or the position has another index on the diagonal
+    fn position(&self, index: usize) -> Option<Position> {
+        let (squares, white_to_move) = self.squares(index);
+        let squares = &squares[..self.pieces.len()];
+
+        if squares.iter().enumerate().any(|(i, s)| squares[..i].contains(s)) || self.index(squares, white_to_move) != index {
+            return None;
+        }
+
+        let pieces = self.pieces.iter().zip(squares).map(|(&(piece, player), &s)| (piece, player, s)).collect::<Vec<_>>();
+        Position::from_pieces(&pieces, if white_to_move { White } else { Black }).ok()
+    }
+
+    //the index of a position with the material of the table. With flipped colors, white's pieces are looked up as black's pieces and the board is flipped.
+    fn position_index(&self, pos: &Position, flipped: bool) -> usize {
+        let mut squares = [0; MAX_PIECES];
+        let mut used = Bitboard::new();
+
+        for (i, &(piece, player)) in self.pieces.iter().enumerate() {
+            let player = if flipped { !player } else { player };
+            let square = (pos.pieces(piece, player) & !used).into_iter().next().expect("the position has the material of the table");
+            used |= Bitboard::from_square(square);
+            squares[i] = if flipped { square ^ 56 } else { square };
+        }
+
+        self.index(&squares, (pos.current_player() == White) != flipped)
+    }
+
+    //positions of the table from which the last move led to the given one, if they are legal
+    fn predecessors(&self, index: usize, states: &[State]) -> Vec<usize> {
+        let (squares, white_to_move) = self.squares(index);
+        let mover = if white_to_move { Black } else { White };
+
+        let occupied = squares[..self.pieces.len()].iter().fold(Bitboard::new(), |b, &s| b | Bitboard::from_square(s));
+        let mut predecessors = Vec::new();
+
+        for (i, &(piece, player)) in self.pieces.iter().enumerate() {
+            if player != mover {
+                continue;
+            }
+
+            let to = squares[i];
+            let from_squares = match piece {
+                King => Bitboard::king_attacks(to),
+                Knight => Bitboard::knight_attacks(to),
+                Bishop => Bitboard::bishop_attacks(to, occupied),
+                Rook => Bitboard::rook_attacks(to, occupied),
+                Queen => Bitboard::bishop_attacks(to, occupied) | Bitboard::rook_attacks(to, occupied),
+                Pawn => Self::pawn_un_moves(to, player, occupied),
+                NoPiece => unreachable!()
+            } & !occupied;
+
+            for from in from_squares {
+                let mut previous = squares;
+                previous[i] = from;
+
+                let p = self.index(&previous[..self.pieces.len()], mover == White);
+                if states[p] != State::Invalid {
+                    predecessors.push(p);
+                }
+            }
+        }
+
+        predecessors
+    }
+
+    //squares a pawn came from with a push. Pawns do not come from the first rank.
+    fn pawn_un_moves(to: u8, player: Color, occupied: Bitboard) -> Bitboard {
+        let row = to / 8;
+        let (back, start_row, double_push_row) = if player == White { (8_i8, 6, 4) } else { (-8, 1, 3) };
+
+        let from = (to as i8 + back) as u8;
+        if from / 8 == 0 || from / 8 == 7 || occupied.contains(from) {
+            return Bitboard::new();
+        }
+
+        let mut squares = Bitboard::from_square(from);
+        let double_push_from = (from as i8 + back) as u8;
+        if row == double_push_row && double_push_from / 8 == start_row && !occupied.contains(double_push_from) {
+            squares |= Bitboard::from_square(double_push_from);
+        }
+
+        squares
+    }
+
+    //the best result over all moves, None if a move leads to a position in this table that is not resolved yet
+    fn best_move_result(&self, pos: &mut Position, states: &[State], tablebases: &Tablebases) -> Option<Dtm> {
+        let pieces = pos.occupied().count_squares();
+        let mut best: Option<Dtm> = None;
+
+        for m in pos.legal_moves() {
+            pos.make_move(m);
+
+            let result = if pos.occupied().count_squares() != pieces || m.promote_to().is_some() {
+                tablebases.probe_exit(pos)
+            } else {
+                match states[self.position_index(pos, false)] {
+                    State::Final(dtm) => Some(dtm),
+                    _ => None
+                }
+            };
+
+            pos.unmake_move(m);
+
+            let result = result?.before_move();
+            if best.is_none_or(|b| result.rating() > b.rating()) {
+                best = Some(result);
+            }
+        }
+
+        best
+    }
+
+    fn generate(name: &str, tablebases: &Tablebases) -> Result<Table, TablebaseError> {
+        let mut table = Table::new(name)?;
+        let mut states = vec![State::Invalid; table.len()];
+
+        //positions that are resolved at a later pass because of a capture or promotion
+        let mut scheduled: Vec<Vec<usize>> = Vec::new();
+        let schedule = |scheduled: &mut Vec<Vec<usize>>, distance: u8, index: usize| {
+            if scheduled.len() <= distance as usize {
+                scheduled.resize(distance as usize + 1, Vec::new());
+            }
+            scheduled[distance as usize].push(index);
+        };
+
+        let mut frontier = Vec::new();
+
+        for (index, state) in states.iter_mut().enumerate() {
+            let mut pos = match table.position(index) {
+                Some(p) => p,
+                None => continue
+            };
+            *state = State::Unknown;
+
+            let moves = pos.legal_moves();
+            if moves.is_empty() {
+                *state = State::Final(if pos.in_check() { Dtm::Loss(0) } else { Dtm::Draw });
+                if pos.in_check() {
+                    frontier.push(index);
+                }
+                continue;
+            }
+
+            let pieces = pos.occupied().count_squares();
+            let mut best_exit: Option<Dtm> = None;
+            let mut moves_in_table = 0;
+
+            for m in moves {
+                pos.make_move(m);
+                if pos.occupied().count_squares() != pieces || m.promote_to().is_some() {
+                    let result = tablebases.probe_exit(&pos).expect("smaller tables are generated first").before_move();
+                    if best_exit.is_none_or(|b| result.rating() > b.rating()) {
+                        best_exit = Some(result);
+                    }
+                } else {
+                    moves_in_table += 1;
+                }
+                pos.unmake_move(m);
+            }
+
+            match best_exit {
+                Some(Dtm::Win(d)) => schedule(&mut scheduled, d, index),
+                Some(Dtm::Draw) if moves_in_table == 0 => *state = State::Final(Dtm::Draw),
+                Some(Dtm::Loss(d)) if moves_in_table == 0 => schedule(&mut scheduled, d, index),
+                _ => ()
+            }
+        }
+
+        let mut distance: u8 = 0;
+        while !frontier.is_empty() || scheduled.len() > distance as usize + 1 {
+            distance = distance.checked_add(1).filter(|d| *d < u8::MAX).expect("distance to mate too long for the table format");
+            let mut next = Vec::new();
+
+            for &q in &frontier {
+                let lost = matches!(states[q], State::Final(Dtm::Loss(_)));
+
+                for p in table.predecessors(q, &states) {
+                    if states[p] != State::Unknown {
+                        continue;
+                    }
+
+                    if lost {
+                        states[p] = State::Final(Dtm::Win(distance));
+                        next.push(p);
+                    } else if let Some(Dtm::Loss(d)) = table.best_move_result(&mut table.position(p).unwrap(), &states, tablebases) {
+                        if d == distance {
+                            states[p] = State::Final(Dtm::Loss(d));
+                            next.push(p);
+                        } else {
+                            schedule(&mut scheduled, d, p);
+                        }
+                    }
+                }
+            }
+
+            for &p in scheduled.get(distance as usize).unwrap_or(&Vec::new()) {
+                if states[p] == State::Unknown {
+                    states[p] = State::Final(if distance % 2 == 1 { Dtm::Win(distance) } else { Dtm::Loss(distance) });
+                    next.push(p);
+                }
+            }
+
+            frontier = next;
+        }
+
+        table.values = states.iter().map(|s| match s {
+            State::Final(dtm) => dtm.to_byte(),
+            _ => 0
+        }).collect();
+
+        Ok(table)
+    }
+
+    /*
+     * file format:
+     * magic (8 bytes), version (u32), name (8 bytes, padded with zeros), number of positions (u64)
+     * values as runs: length (u8), value (u8)
+     * all numbers are little endian.
+     */
+
+    fn save(&self, path: &Path) -> Result<(), TablebaseError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut name = [0; 8];
+        name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+
+        writer.write_all(&FILE_MAGIC)?;
+        writer.write_all(&FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&name)?;
+        writer.write_all(&(self.values.len() as u64).to_le_bytes())?;
+
+        let mut i = 0;
+        while i < self.values.len() {
+            let value = self.values[i];
+            let run = self.values[i..].iter().take(u8::MAX as usize).take_while(|v| **v == value).count();
+            writer.write_all(&[run as u8, value])?;
+            i += run;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn load(path: &Path) -> Result<Table, TablebaseError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut header = [0; 28];
+        reader.read_exact(&mut header)?;
+        if header[0..8] != FILE_MAGIC {
+            return Err(TablebaseError::NotATablebaseFile);
+        }
+
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != FILE_VERSION {
+            return Err(TablebaseError::UnsupportedVersion(version));
+        }
+
+        let name = String::from_utf8_lossy(&header[12..20]).trim_end_matches('\0').to_string();
+        let mut table = Table::new(&name)?;
+
+        let positions = u64::from_le_bytes(header[20..28].try_into().unwrap());
+        if positions != table.len() as u64 {
+            return Err(TablebaseError::InvalidData);
+        }
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        table.values = Vec::with_capacity(table.len());
+        for run in data.chunks(2) {
+            match *run {
+                [length, value] if length > 0 && table.values.len() + length as usize <= table.len() => {
+                    table.values.extend(std::iter::repeat_n(value, length as usize));
+                },
+                _ => return Err(TablebaseError::CorruptRuns)
+            }
+        }
+
+        if table.values.len() != table.len() {
+            return Err(TablebaseError::CorruptRuns);
+        }
+
+        Ok(table)
+    }
+}
+
+#[derive(Default)]
+pub struct Tablebases {
+    tables: Vec<Table>
+}
+
+impl Tablebases {
+    pub fn new() -> Tablebases {
+        Tablebases { tables: Vec::new() }
+    }
+
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    //the table of the material and whether the colors are flipped
+    fn find(&self, signature: MaterialSignature) -> Option<(&Table, bool)> {
+        self.tables.iter().find_map(|t| {
+            if t.signature == signature {
+                Some((t, false))
+            } else if t.signature.mirrored() == signature {
+                Some((t, true))
+            } else {
+                None
+            }
+        })
+    }
+
+    //the result for the side to move, None if there is no table for the position
+    pub fn probe(&self, pos: &Position) -> Option<Dtm> {
+        if pos.occupied().count_squares() > MAX_PIECES as u32 || pos.has_castling_rights() {
+            return None;
+        }
+
+        let (table, flipped) = self.find(MaterialSignature::from_position(pos))?;
+        Some(Dtm::from_byte(table.values[table.position_index(pos, flipped)]))
+    }
+
+    //like probe, but two bare kings are a draw
+    fn probe_exit(&self, pos: &Position) -> Option<Dtm> {
+        if pos.occupied().count_squares() == 2 {
+            Some(Dtm::Draw)
+        } else {
+            self.probe(pos)
+        }
+    }
+
+    //generates the table and the smaller tables it depends on, unless they are available already
+    pub fn generate(&mut self, name: &str) -> Result<(), TablebaseError> {
+        let table = Table::new(name)?;
+        if self.find(table.signature).is_some() {
+            return Ok(());
+        }
+
+        for sub_table in Self::sub_tables(&table.pieces) {
+            self.generate(&sub_table)?;
+        }
+
+        let table = Table::generate(name, self)?;
+        self.tables.push(table);
+        Ok(())
+    }
+
+    //the tables that captures and promotions lead to
+    fn sub_tables(pieces: &[(Piece, Color)]) -> Vec<String> {
+        let mut materials = Vec::new();
+
+        for i in 2..pieces.len() {
+            let mut captured = pieces.to_vec();
+            captured.remove(i);
+            materials.push(captured);
+
+            if pieces[i].0 == Pawn {
+                for promotion in [Queen, Rook, Bishop, Knight] {
+                    let mut promoted = pieces.to_vec();
+                    promoted[i].0 = promotion;
+                    materials.push(promoted.clone());
+
+                    for j in (2..pieces.len()).filter(|&j| pieces[j].1 != pieces[i].1) {
+                        let mut captured = promoted.clone();
+                        captured.remove(j);
+                        materials.push(captured);
+                    }
+                }
+            }
+        }
+
+        materials.iter().filter(|m| m.len() > 2).map(|m| Self::name(m)).collect()
+    }
+
+    fn name(pieces: &[(Piece, Color)]) -> String {
+        let mut name = String::new();
+        for player in [White, Black] {
+            name.push('K');
+            for piece in [Queen, Rook, Bishop, Knight, Pawn] {
+                for _ in pieces.iter().filter(|&&p| p == (piece, player)) {
+                    name.push(['P', 'N', 'B', 'R', 'Q'][piece as usize]);
+                }
+            }
+        }
+        name
+    }
+
+    //saves every table to a file in the directory
+    pub fn save(&self, dir: &Path) -> Result<(), TablebaseError> {
+        fs::create_dir_all(dir)?;
+        for table in &self.tables {
+            table.save(&dir.join(format!("{}.{FILE_EXTENSION}", table.name)))?;
+        }
+        Ok(())
+    }
+
+    //loads every table file in the directory
+    pub fn load(dir: &Path) -> Result<Tablebases, TablebaseError> {
+        let mut tablebases = Tablebases::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == FILE_EXTENSION) {
+                let table = Table::load(&path)?;
+                if tablebases.find(table.signature).is_none() {
+                    tablebases.tables.push(table);
+                }
+            }
+        }
+
+        Ok(tablebases)
+    }
+}
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(io::Error),
+    InvalidName(String),
+    NotATablebaseFile,
+    UnsupportedVersion(u32),
+    InvalidData,
+    //the runs are truncated, empty or cover more positions than the table has
+    CorruptRuns
+}
+
+impl From<io::Error> for TablebaseError {
+    fn from(e: io::Error) -> Self {
+        TablebaseError::Io(e)
+    }
+}
+
+impl std::fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TablebaseError::Io(e) => write!(f, "{e}"),
+            TablebaseError::InvalidName(n) => write!(f, "invalid table name '{n}', expected something like KQKR with at most {MAX_PIECES} pieces"),
+            TablebaseError::NotATablebaseFile => write!(f, "not a tablebase file"),
+            TablebaseError::UnsupportedVersion(v) => write!(f, "unsupported tablebase file version {v}, expected {FILE_VERSION}"),
+            TablebaseError::InvalidData => write!(f, "the number of positions does not match the table"),
+            TablebaseError::CorruptRuns => write!(f, "the run length encoded values are corrupt")
+        }
+    }
+}
+
+impl std::error::Error for TablebaseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::engine::kpk;
+
+    fn probe(tablebases: &Tablebases, fen: &str) -> Option<Dtm> {
+        tablebases.probe(&Position::from_fen_string(fen).unwrap())
+    }
+
+    #[test]
+    fn longest_mates() {
+        let mut tablebases = Tablebases::new();
+        tablebases.generate("KQK").unwrap();
+        tablebases.generate("KRK").unwrap();
+
+        assert_eq!(tablebases.tables()[0].longest_mate(), 10);
+        assert_eq!(tablebases.tables()[1].longest_mate(), 16);
+
+        assert_eq!(probe(&tablebases, "k7/8/1K6/8/8/8/8/6Q1 w - - 0 1"), Some(Dtm::Win(1)));
+        assert_eq!(probe(&tablebases, "k7/8/1K6/8/8/8/8/6Q1 b - - 0 1"), Some(Dtm::Loss(2)));
+        assert_eq!(probe(&tablebases, "6q1/8/8/8/8/1k6/8/K7 w - - 0 1"), Some(Dtm::Loss(2)), "colors swapped");
+        assert_eq!(probe(&tablebases, "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw), "stalemate");
+        assert_eq!(probe(&tablebases, "kQ6/8/2K5/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw), "the queen can be captured");
+        assert_eq!(probe(&tablebases, "k7/8/1K6/8/8/8/8/5BQ1 w - - 0 1"), None);
+    }
+
+    //every position has the best result over its moves
+    #[test]
+    fn consistent_with_moves() {
+        let mut tablebases = Tablebases::new();
+        tablebases.generate("KRK").unwrap();
+
+        let table = &tablebases.tables()[0];
+        let states = table.values.iter().map(|&b| State::Final(Dtm::from_byte(b))).collect::<Vec<_>>();
+
+        for index in 0..table.len() {
+            if let Some(mut pos) = table.position(index) {
+                let expected = match table.best_move_result(&mut pos, &states, &tablebases) {
+                    Some(dtm) => dtm,
+                    None if pos.in_check() => Dtm::Loss(0),
+                    None => Dtm::Draw
+                };
+                assert_eq!(tablebases.probe(&pos), Some(expected), "{}", pos.to_fen_string());
+            }
+        }
+    }
+
+    #[test]
+    fn king_and_pawn_agrees_with_bitbase() {
+        let mut tablebases = Tablebases::new();
+        tablebases.generate("KPK").unwrap();
+
+        let table = tablebases.tables().iter().find(|t| t.name() == "KPK").unwrap();
+        for index in 0..table.len() {
+            if let Some(pos) = table.position(index) {
+                let white_wins = match tablebases.probe(&pos).unwrap() {
+                    Dtm::Win(_) => pos.current_player() == White,
+                    Dtm::Loss(_) => pos.current_player() == Black,
+                    Dtm::Draw => false
+                };
+                assert_eq!(white_wins, kpk::probe_position(&pos).unwrap(), "{}", pos.to_fen_string());
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut tablebases = Tablebases::new();
+        tablebases.generate("KRK").unwrap();
+
+        let dir = std::env::temp_dir().join(format!("carlito_tablebase_test_{}", std::process::id()));
+        tablebases.save(&dir).unwrap();
+        let loaded = Tablebases::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.tables().len(), 1);
+        assert_eq!(loaded.tables()[0].values, tablebases.tables()[0].values);
+
+        //a wrong number of positions in the header and broken runs are told apart
+        let path = std::env::temp_dir().join(format!("carlito_tablebase_test_{}.bin", std::process::id()));
+        let table = &tablebases.tables()[0];
+        table.save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut wrong_count = bytes.clone();
+        wrong_count[20] ^= 1;
+        let mut truncated = bytes.clone();
+        truncated.pop();
+        let mut too_long = bytes.clone();
+        too_long.extend([1, 0]);
+        let mut empty_run = bytes.clone();
+        empty_run.splice(28..28, [0, 0]);
+
+        fs::write(&path, &wrong_count).unwrap();
+        assert!(matches!(Table::load(&path), Err(TablebaseError::InvalidData)));
+        for corrupt in [truncated, too_long, empty_run, bytes[..30].to_vec()] {
+            fs::write(&path, &corrupt).unwrap();
+            assert!(matches!(Table::load(&path), Err(TablebaseError::CorruptRuns)));
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(Table::new("KQRKRN"), Err(TablebaseError::InvalidName(_))));
+        assert!(matches!(Table::new("QKK"), Err(TablebaseError::InvalidName(_))));
+    }
+}
@@ -3,9 +3,11 @@ use std::sync::{atomic, Arc, mpsc};
 use std::time::Duration;
 use std::thread;
 
+use crate::chess_move::Move;
 use crate::position::Color;
 
 use super::ThreadData;
+use super::score::Score;
 
 pub struct Timer {
     timer_thread: Option<thread::JoinHandle<()>>,
@@ -15,20 +17,27 @@ pub struct Timer {
 
 impl Timer {
     
+    //the limits are set while pondering as well, but the timer only starts on the ponder hit, when the clock runs
     pub fn new(thread_data: Arc<ThreadData>) -> Option<Timer> {
 
-        if thread_data.options.infinite || thread_data.ponder.load(atomic::Ordering::Acquire) {
+        if thread_data.options.infinite {
             None 
         } else {
             let (min_ms, max_ms) = Self::calculate_min_max_time(&thread_data)?;
 
-            *(thread_data.min_time.lock().unwrap()) = Some(Duration::from_millis(min_ms as u64));
-            *(thread_data.max_time.lock().unwrap()) = Some(Duration::from_millis(max_ms as u64));
+            *(thread_data.min_time.lock().unwrap()) = Some(Duration::from_millis(min_ms));
+            *(thread_data.max_time.lock().unwrap()) = Some(Duration::from_millis(max_ms));
+
+            if thread_data.ponder.load(atomic::Ordering::Acquire) {
+                return None;
+            }
 
             let (sender, reciever) = mpsc::channel::<()>();
 
             let t = thread::spawn(move || {
-                match reciever.recv_timeout(thread_data.max_time.lock().unwrap().unwrap()) {
+                //don't keep max_time locked while waiting
+                let max_time = thread_data.max_time.lock().unwrap().unwrap();
+                match reciever.recv_timeout(max_time) {
                     Ok(()) => (),
                     Err(RecvTimeoutError::Disconnected) => panic!("timing abort sender dropped while timer thread still active"),
                     Err(RecvTimeoutError::Timeout) => thread_data.stop.store(true, atomic::Ordering::Release) //time is up, set stop flag
@@ -43,7 +52,10 @@ impl Timer {
     }
 
     fn calculate_min_max_time(thread_data: &ThreadData) -> Option<(u64, u64)> {
+        let overhead = thread_data.options.move_overhead;
+
         if let Some(move_time) = thread_data.options.move_time {
+            let move_time = move_time.saturating_sub(overhead).max(1);
             Some((move_time, move_time))
         } else {
             let moves_to_go = thread_data.options.moves_to_go.unwrap_or(50);
@@ -53,12 +65,21 @@ impl Timer {
                 Color::Black => thread_data.options.btime,
             }?; //return None if no clock time given.
 
+            //the time the gui or the network needs to transmit the moves is lost for every remaining move
+            let clock_time = clock_time.saturating_sub(overhead * moves_to_go.min(10)).max(1);
+
             let increment = match thread_data.position.current_player() {
                 Color::White => thread_data.options.winc,
                 Color::Black => thread_data.options.binc,
             }.unwrap_or(0);
 
-            let min_time = (clock_time / moves_to_go + increment) * 3 / 4; //TODO tune factor
+            let mut min_time = (clock_time / moves_to_go + increment) * 3 / 4; //TODO tune factor
+
+            //pondering saves time on the opponent's moves
+            if thread_data.options.pondering_enabled {
+                min_time += min_time / 4;
+            }
+
             let mut max_time = min_time * 3;
 
             max_time = if clock_time <= 1 {
@@ -81,4 +102,71 @@ impl Drop for Timer {
         if self.abort.send(()).is_err() { }; //the timer thread may have already finished
         self.timer_thread.take().unwrap().join().unwrap();
     }
-}
\ No newline at end of file
+}
+
+/**
+ * scales the soft time limit (min_time) after every iteration. The engine thinks longer if the best
+ * move keeps changing or the score drops, and shorter if the best move is stable or there are only a
+ * few legal moves.
+ */
+pub struct TimeScaling {
+    root_moves: usize,
+    best_move: Option<Move>,
+    stable_iterations: u32,
+    score_drop: i32,
+    previous_score: Option<Score>
+}
+
+impl TimeScaling {
+    pub fn new(root_moves: usize) -> TimeScaling {
+        TimeScaling {
+            root_moves,
+            best_move: None,
+            stable_iterations: 0,
+            score_drop: 0,
+            previous_score: None
+        }
+    }
+
+    pub fn iteration_complete(&mut self, best_move: Option<Move>, score: Score) {
+        if best_move == self.best_move {
+            self.stable_iterations += 1;
+        } else {
+            self.stable_iterations = 0;
+            self.best_move = best_move;
+        }
+
+        //mate scores are left out, the search ends on mates anyway
+        self.score_drop = match (self.previous_score.and_then(|s| s.centi_pawns()), score.centi_pawns()) {
+            (Some(previous), Some(current)) => (previous as i32 - current as i32).max(0),
+            _ => 0
+        };
+        self.previous_score = Some(score);
+    }
+
+    //with a single legal move there is nothing to think about
+    pub fn single_move(&self) -> bool {
+        self.root_moves == 1
+    }
+
+    pub fn factor(&self) -> f64 {
+        let stability = match self.stable_iterations {
+            0 => 1.6,
+            1 => 1.2,
+            2 => 1.0,
+            3 => 0.85,
+            _ => 0.7
+        };
+
+        //up to twice as long if the score dropped by a pawn or more
+        let score_drop = 1.0 + (self.score_drop as f64 / 100.0).min(1.0);
+
+        let root_moves = if self.root_moves <= 3 { 0.7 } else { 1.0 };
+
+        stability * score_drop * root_moves
+    }
+
+    pub fn soft_limit(&self, min_time: Duration, max_time: Duration) -> Duration {
+        min_time.mul_f64(self.factor()).min(max_time)
+    }
+}
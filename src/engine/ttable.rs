@@ -1,4 +1,7 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::mem::size_of;
+use std::path::Path;
 
 use crate::chess_move::Move;
 
@@ -59,19 +62,21 @@ impl TTable {
     }
 
     pub fn mb_size(&self) -> usize {
-        self.table.len() / (1<<20) * size_of::<Bucket>()
+        self.table.len() * size_of::<Bucket>() / (1<<20)
+    }
+
+    //permille of used entries, estimated from the first 1000 entries
+    pub fn hashfull(&self) -> u16 {
+        let sample = self.table.iter().take(250).flat_map(|b| b.entries.iter());
+        let (used, total) = sample.fold((0, 0), |(used, total), e| (used + (e.hash != 0) as u32, total + 1));
+
+        (used * 1000).checked_div(total).unwrap_or(0) as u16
     }
 
     pub fn lookup(&self, hash: u64) -> Option<&TTableEntry> {
         let index = hash as usize % self.table.len();
 
-        for entry in self.table[index].entries.iter() {
-            if entry.hash == hash {
-                return Some(entry);
-            } 
-        }
-
-        None
+        self.table[index].entries.iter().find(|entry| entry.hash == hash)
     }
 
     pub fn insert(&mut self, hash: u64, entry_type: EntryType, score: Score, best_move: Move, depth: u16) {
@@ -107,4 +112,127 @@ impl TTable {
             *b = Bucket::new();
         }
     }
-}
\ No newline at end of file
+
+    /*
+     * table files
+     *
+     * header: magic bytes, format version (u32), number of buckets (u64), entries per bucket (u32)
+     * entries: hash (u64), entry type (u8), score (i16), best move (u16), depth (u16)
+     * all numbers are little endian.
+     */
+
+    const FILE_MAGIC: [u8; 8] = *b"CRLTTBL\0";
+    const FILE_VERSION: u32 = 1;
+    const ENTRIES_PER_BUCKET: u32 = 4;
+    const ENTRY_FILE_SIZE: usize = 15;
+
+    pub fn save(&self, path: &Path) -> Result<(), TableFileError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(&Self::FILE_MAGIC)?;
+        writer.write_all(&Self::FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.table.len() as u64).to_le_bytes())?;
+        writer.write_all(&Self::ENTRIES_PER_BUCKET.to_le_bytes())?;
+
+        for entry in self.table.iter().flat_map(|b| b.entries.iter()) {
+            let mut bytes = [0; Self::ENTRY_FILE_SIZE];
+            bytes[0..8].copy_from_slice(&entry.hash.to_le_bytes());
+            bytes[8] = entry.entry_type as u8;
+            bytes[9..11].copy_from_slice(&entry.score.s.to_le_bytes());
+            bytes[11..13].copy_from_slice(&entry.best_move.to_bits().to_le_bytes());
+            bytes[13..15].copy_from_slice(&entry.depth.to_le_bytes());
+            writer.write_all(&bytes)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    //loads a table saved with save(). The table has the size stored in the file, which can't be larger than max_mb_size.
+    pub fn load(path: &Path, max_mb_size: usize) -> Result<TTable, TableFileError> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != Self::FILE_MAGIC {
+            return Err(TableFileError::NotATableFile);
+        }
+
+        let version = u32::from_le_bytes(Self::read_bytes(&mut reader)?);
+        if version != Self::FILE_VERSION {
+            return Err(TableFileError::UnsupportedVersion(version));
+        }
+
+        let buckets = u64::from_le_bytes(Self::read_bytes(&mut reader)?);
+        let entries_per_bucket = u32::from_le_bytes(Self::read_bytes(&mut reader)?);
+
+        let max_buckets = (max_mb_size * (1<<20) / size_of::<Bucket>()) as u64;
+        if entries_per_bucket != Self::ENTRIES_PER_BUCKET || buckets == 0 || buckets > max_buckets {
+            return Err(TableFileError::InvalidHeader);
+        }
+
+        let header_size = 8 + 4 + 8 + 4;
+        let expected_size = header_size + buckets * Self::ENTRIES_PER_BUCKET as u64 * Self::ENTRY_FILE_SIZE as u64;
+        if file_size != expected_size {
+            return Err(TableFileError::WrongFileSize { expected: expected_size, actual: file_size });
+        }
+
+        let mut table = vec![Bucket::new(); buckets as usize];
+
+        for entry in table.iter_mut().flat_map(|b| b.entries.iter_mut()) {
+            let bytes: [u8; Self::ENTRY_FILE_SIZE] = Self::read_bytes(&mut reader)?;
+
+            entry.hash = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+            entry.entry_type = match bytes[8] {
+                0 => EntryType::Exact,
+                1 => EntryType::Upper,
+                2 => EntryType::Lower,
+                _ => return Err(TableFileError::InvalidEntry)
+            };
+            entry.score = Score { s: i16::from_le_bytes(bytes[9..11].try_into().unwrap()) };
+            entry.best_move = Move::from_bits(u16::from_le_bytes(bytes[11..13].try_into().unwrap()));
+            entry.depth = u16::from_le_bytes(bytes[13..15].try_into().unwrap());
+        }
+
+        Ok(TTable { table })
+    }
+
+    fn read_bytes<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[derive(Debug)]
+pub enum TableFileError {
+    Io(io::Error),
+    NotATableFile,
+    UnsupportedVersion(u32),
+    InvalidHeader,
+    WrongFileSize { expected: u64, actual: u64 },
+    InvalidEntry
+}
+
+impl From<io::Error> for TableFileError {
+    fn from(e: io::Error) -> Self {
+        TableFileError::Io(e)
+    }
+}
+
+impl std::fmt::Display for TableFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableFileError::Io(e) => write!(f, "{e}"),
+            TableFileError::NotATableFile => write!(f, "not a hash table file"),
+            TableFileError::UnsupportedVersion(v) => write!(f, "unsupported hash table file version {v}, expected {}", TTable::FILE_VERSION),
+            TableFileError::InvalidHeader => write!(f, "invalid hash table size in the file header"),
+            TableFileError::WrongFileSize { expected, actual } => write!(f, "expected a file size of {expected} bytes, got {actual} bytes"),
+            TableFileError::InvalidEntry => write!(f, "invalid hash table entry")
+        }
+    }
+}
+
+impl std::error::Error for TableFileError {}
\ No newline at end of file
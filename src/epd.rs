@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::position::*;
+use crate::chess_move::*;
+use crate::engine::*;
+
+/*
+ * test suites in the extended position description format. Every line is a position, given by the
+ * first four fields of a fen, followed by operations like 'bm Nf3 Qd4;' (best moves), 'am Qxb7;'
+ * (moves to avoid) and 'id "name";'. Other operations are ignored, except for the move counters
+ * 'hmvc' and 'fmvn'. A position is solved if the engine plays a best move and no move to avoid.
+ */
+
+#[derive(Debug, Clone)]
+pub struct EpdEntry {
+    pub position: Position,
+    pub id: Option<String>,
+    pub best_moves: Vec<Move>,
+    pub avoid_moves: Vec<Move>
+}
+
+impl EpdEntry {
+    pub fn parse(line: &str) -> Result<EpdEntry, EpdError> {
+        let fields: Vec<&str> = line.splitn(5, char::is_whitespace).collect();
+        if fields.len() < 4 {
+            return Err(EpdError::MissingFields);
+        }
+
+        let mut id = None;
+        let mut moves = (Vec::new(), Vec::new());
+        let mut counters = ("0", "1");
+
+        let operations = fields.get(4).copied().unwrap_or_default();
+        for operation in operations.split(';').map(str::trim).filter(|op| !op.is_empty()) {
+            let (opcode, operands) = operation.split_once(char::is_whitespace).unwrap_or((operation, ""));
+            match opcode {
+                "id" => id = Some(operands.trim().trim_matches('"').to_string()),
+                "bm" => moves.0 = operands.split_whitespace().collect(),
+                "am" => moves.1 = operands.split_whitespace().collect(),
+                "hmvc" => counters.0 = operands.trim(),
+                "fmvn" => counters.1 = operands.trim(),
+                _ => ()
+            }
+        }
+
+        let fen = format!("{} {} {}", fields[..4].join(" "), counters.0, counters.1);
+        let mut position = Position::from_fen_string(&fen).map_err(EpdError::InvalidPosition)?;
+
+        let mut parse_moves = |moves: Vec<&str>| moves.into_iter()
+            .map(|m| Move::from_san(m, &mut position).or_else(|_| Move::from_string(m, &mut position)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(EpdError::InvalidMove);
+
+        let best_moves = parse_moves(moves.0)?;
+        let avoid_moves = parse_moves(moves.1)?;
+
+        Ok(EpdEntry { position, id, best_moves, avoid_moves })
+    }
+
+    pub fn is_solved_by(&self, m: Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&m)) && !self.avoid_moves.contains(&m)
+    }
+}
+
+//reads all positions of a suite, empty lines are skipped
+pub fn load(path: &Path) -> Result<Vec<EpdEntry>, EpdError> {
+    fs::read_to_string(path)?.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| EpdEntry::parse(line.trim()).map_err(|e| EpdError::Line(i + 1, Box::new(e))))
+        .collect()
+}
+
+//searches every position of the suite with the same options, prints the results and returns the number of solved positions
+pub fn run_suite(engine: &mut Engine, entries: &[EpdEntry], options: &EngineOptions) -> usize {
+    let mut solved = 0;
+
+    for (i, entry) in entries.iter().enumerate() {
+        //every position is searched from scratch, so the results don't depend on the order
+        engine.clear_table();
+        let result = engine.search_sync(entry.position.clone(), options.clone());
+
+        let mut position = entry.position.clone();
+        let san = |moves: &[Move], position: &mut Position| moves.iter().map(|m| m.to_san(position)).collect::<Vec<_>>().join(" ");
+
+        let played = result.best_move.map_or("none".to_string(), |m| san(&[m], &mut position));
+        let ok = result.best_move.is_some_and(|m| entry.is_solved_by(m));
+        if ok {
+            solved += 1;
+        }
+
+        let mut line = format!("{}/{} {}: {played}", i + 1, entries.len(), entry.id.as_deref().unwrap_or("-"));
+        if !entry.best_moves.is_empty() {
+            line += &format!(", bm {}", san(&entry.best_moves, &mut position));
+        }
+        if !entry.avoid_moves.is_empty() {
+            line += &format!(", am {}", san(&entry.avoid_moves, &mut position));
+        }
+        println!("{line}, {}", if ok { "solved" } else { "failed" });
+    }
+
+    println!("solved {solved} of {}", entries.len());
+    solved
+}
+
+#[derive(Debug)]
+pub enum EpdError {
+    Io(io::Error),
+    MissingFields,
+    InvalidPosition(FenError),
+    InvalidMove(MoveError),
+    Line(usize, Box<EpdError>)
+}
+
+impl From<io::Error> for EpdError {
+    fn from(e: io::Error) -> Self {
+        EpdError::Io(e)
+    }
+}
+
+impl std::fmt::Display for EpdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpdError::Io(e) => write!(f, "{e}"),
+            EpdError::MissingFields => write!(f, "expected pieces, side to move, castling rights and en passant square"),
+            EpdError::InvalidPosition(e) => write!(f, "invalid position: {e}"),
+            EpdError::InvalidMove(e) => write!(f, "{e}"),
+            EpdError::Line(line, e) => write!(f, "line {line}: {e}")
+        }
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let entry = EpdEntry::parse("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - bm Ra6; id \"mate in 3\"; c0 \"a comment\";").unwrap();
+        assert_eq!(entry.id.as_deref(), Some("mate in 3"));
+        assert_eq!(entry.best_moves.len(), 1);
+
+        let mut position = entry.position.clone();
+        assert!(entry.is_solved_by(Move::from_string("f6a6", &mut position).unwrap()));
+        assert!(!entry.is_solved_by(Move::from_string("f6f7", &mut position).unwrap()));
+
+        let entry = EpdEntry::parse("4k3/8/8/8/8/8/8/R3K3 w Q - am O-O-O; hmvc 12; fmvn 40;").unwrap();
+        assert_eq!(entry.position.to_fen_string(), "4k3/8/8/8/8/8/8/R3K3 w Q - 12 40");
+        assert_eq!(entry.avoid_moves.len(), 1);
+
+        assert!(matches!(EpdEntry::parse("4k3/8/8/8/8/8/8/R3K3 w"), Err(EpdError::MissingFields)));
+        assert!(matches!(EpdEntry::parse("4k3/8/8/8/8/8/8/R3K3 w - - bm Rb8"), Err(EpdError::InvalidMove(_))));
+    }
+}
@@ -0,0 +1,17 @@
+pub mod bitboard;
+pub mod position;
+pub mod chess_move;
+pub mod uci;
+pub mod xboard;
+pub mod play;
+pub mod engine;
+pub mod bench;
+pub mod perft;
+pub mod config;
+pub mod epd;
+pub mod log;
+pub mod replay;
+
+pub use position::Position;
+pub use chess_move::Move;
+pub use engine::{Engine, EngineOptions, IterationReport, Score, SearchObserver, SearchResult, SilentObserver};
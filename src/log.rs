@@ -0,0 +1,71 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/*
+ * log of the protocol traffic, for debugging sessions with a gui. Every line that is read or sent is
+ * appended to the log file with the milliseconds since the log was opened, '>>' for input and '<<'
+ * for output. '--' marks notes that are only in the log, like the nodes a search needed for its best
+ * move. Lines are written right away, so the log is complete even if the engine crashes.
+ */
+
+struct Log {
+    writer: LineWriter<File>,
+    start: Instant
+}
+
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+
+//starts logging to the file. An existing log is appended to.
+pub fn open(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = LineWriter::new(file);
+
+    let unix_time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    writeln!(writer, "# log started at unix time {unix_time}")?;
+
+    *LOG.lock().unwrap() = Some(Log { writer, start: Instant::now() });
+    Ok(())
+}
+
+pub fn close() {
+    *LOG.lock().unwrap() = None;
+}
+
+//a line read from the gui
+pub fn input(line: &str) {
+    write(LOG.lock().unwrap().as_mut(), ">>", line);
+}
+
+//prints a line for the gui and logs it. The log is locked while printing, so it has the same order as the output.
+pub fn send_line(line: &str) {
+    let mut log = LOG.lock().unwrap();
+    println!("{line}");
+    write(log.as_mut(), "<<", line);
+}
+
+//like send_line, with a note for the log right after the line
+pub fn send_line_with_note(line: &str, note: &str) {
+    let mut log = LOG.lock().unwrap();
+    println!("{line}");
+    write(log.as_mut(), "<<", line);
+    write(log.as_mut(), "--", note);
+}
+
+fn write(log: Option<&mut Log>, direction: &str, line: &str) {
+    if let Some(log) = log {
+        //the engine keeps running if the log can't be written, e.g. on a full disk
+        let _ = writeln!(log.writer, "{} {direction} {line}", log.start.elapsed().as_millis());
+    }
+}
+
+//println! for protocol output, which is logged as well
+macro_rules! send {
+    ($($arg:tt)*) => {
+        $crate::log::send_line(&format!($($arg)*))
+    };
+}
+
+pub(crate) use send;
@@ -1,10 +1,258 @@
-pub mod bitboard;
-pub mod position;
-pub mod chess_move;
-pub mod uci;
-pub mod engine;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Instant;
 
+use carlito::{uci, xboard, play, bench, epd, log, perft};
+use carlito::{EngineOptions, SilentObserver};
+use carlito::config::{Config, ConfigError, RunMode, USAGE};
+use carlito::bitboard::SliderAttacks;
+use carlito::perft::PerftTable;
+use carlito::replay::Session;
+use carlito::position::{Color, Position};
+use carlito::engine::strength::MAX_SKILL_LEVEL;
+use carlito::engine::tablebase::Tablebases;
 
 fn main() {
-    uci::input_loop()
-}
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return;
+    }
+
+    let (config, mode_args) = Config::from_args(&args).unwrap_or_else(|e| exit_with_usage(&e.to_string(), USAGE));
+
+    //a replay must not append to the log it reads
+    if let Some(path) = config.log_file.as_ref().filter(|_| config.mode != RunMode::Replay) {
+        if let Err(e) = log::open(path) {
+            exit_with_error(&format!("could not open log file '{}': {e}", path.display()));
+        }
+    }
+
+    //perft and the tablebase generator don't create an engine
+    SliderAttacks::init();
+
+    let result = match config.mode {
+        RunMode::Auto => auto_mode(&config),
+        RunMode::Uci => uci::input_loop(&config),
+        RunMode::Xboard => xboard::input_loop(&config),
+        RunMode::Bench => { bench_mode(&mode_args); Ok(()) },
+        RunMode::Perft => { perft_mode(&config, &mode_args); Ok(()) },
+        RunMode::Play => play_mode(&config, &mode_args),
+        RunMode::Epd => epd_mode(&config, &mode_args),
+        RunMode::Tablebase => { tablebase_mode(&mode_args); Ok(()) },
+        RunMode::Replay => replay_mode(&config, &mode_args)
+    };
+
+    if let Err(e) = result {
+        exit_with_error(&e.to_string());
+    }
+}
+
+//the first command selects the protocol. Stdin must not stay locked, the protocol handlers read it again
+fn auto_mode(config: &Config) -> Result<(), ConfigError> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if io::stdin().read_line(&mut line).expect("error reading stdin") == 0 {
+            return Ok(());
+        }
+        log::input(line.trim_end());
+
+        match line.trim() {
+            "uci" => return uci::input_loop(config),
+            "xboard" => return xboard::input_loop(config),
+            _ => ()
+        }
+    }
+}
+
+const BENCH_USAGE: &str = "usage: carlito bench [DEPTH]";
+
+//carlito bench [DEPTH]
+fn bench_mode(args: &[String]) {
+    let depth = match args {
+        [] => 4,
+        [depth] => depth.parse().unwrap_or_else(|_| exit_with_usage(&format!("invalid depth: {depth}"), BENCH_USAGE)),
+        _ => exit_with_usage("too many arguments", BENCH_USAGE)
+    };
+
+    bench::slider_attacks_bench(depth);
+}
+
+const PERFT_USAGE: &str = "usage: carlito perft DEPTH [FEN]";
+
+//carlito perft DEPTH [FEN]
+fn perft_mode(config: &Config, args: &[String]) {
+    let (depth, fen) = match args.split_first() {
+        Some((depth, fen)) => (depth.parse::<u32>().unwrap_or_else(|_| exit_with_usage(&format!("invalid depth: {depth}"), PERFT_USAGE)), fen.join(" ")),
+        None => exit_with_usage("missing depth", PERFT_USAGE)
+    };
+
+    let mut position = if fen.is_empty() {
+        Position::new()
+    } else {
+        Position::from_fen_string(&fen).unwrap_or_else(|e| exit_with_usage(&format!("invalid fen: {e}"), PERFT_USAGE))
+    };
+
+    let start = Instant::now();
+    let nodes = if depth == 0 {
+        1
+    } else {
+        let table = PerftTable::new(config.hash);
+        let results = perft::split_root_moves(&mut position, config.threads, |pos| perft::perft(pos, depth - 1, &table));
+        for (m, nodes) in results.iter() {
+            println!("{m}: {nodes}");
+        }
+        results.iter().map(|(_, n)| n).sum::<u64>()
+    };
+
+    let ms = start.elapsed().as_millis() as u64;
+    print!("\nnodes {nodes} time {ms}");
+    if let Some(nps) = (nodes * 1000).checked_div(ms) {
+        print!(" nps {nps}");
+    }
+    println!();
+}
+
+const PLAY_USAGE: &str = "usage: carlito play [--color white|black] [--fen FEN] [--time MS] [--depth N] [--nodes N] [--skill N]";
+
+//carlito play [--color white|black] [--fen FEN] [--time MS] [--depth N] [--nodes N] [--skill N]
+fn play_mode(config: &Config, args: &[String]) -> Result<(), ConfigError> {
+    let mut settings = play::PlaySettings::default();
+    let mut position = Position::new();
+    let mut user_color = Color::White;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = match iter.next() {
+            Some(v) => v,
+            None => exit_with_usage(&format!("missing value for {arg}"), PLAY_USAGE)
+        };
+
+        let parse_number = |v: &str| v.parse::<u64>().unwrap_or_else(|_| exit_with_usage(&format!("invalid value for {arg}: {v}"), PLAY_USAGE));
+
+        match arg.as_str() {
+            "--color" => user_color = match value.as_str() {
+                "white" => Color::White,
+                "black" => Color::Black,
+                v => exit_with_usage(&format!("invalid color: {v}"), PLAY_USAGE)
+            },
+            "--fen" => position = Position::from_fen_string(value).unwrap_or_else(|e| exit_with_usage(&format!("invalid fen: {e}"), PLAY_USAGE)),
+            "--time" => settings.move_time = parse_number(value),
+            "--depth" => settings.depth = Some(parse_number(value)),
+            "--nodes" => settings.nodes = Some(parse_number(value)),
+            "--skill" => settings.skill_level = parse_number(value).min(MAX_SKILL_LEVEL as u64) as u8,
+            _ => exit_with_usage(&format!("unknown argument: {arg}"), PLAY_USAGE)
+        }
+    }
+
+    play::input_loop(config, position, Some(!user_color), settings)
+}
+
+const EPD_USAGE: &str = "usage: carlito epd FILE [--time MS] [--depth N] [--nodes N]";
+
+//carlito epd FILE [--time MS] [--depth N] [--nodes N]
+fn epd_mode(config: &Config, args: &[String]) -> Result<(), ConfigError> {
+    let (path, args) = match args.split_first() {
+        Some((path, args)) => (Path::new(path), args),
+        None => exit_with_usage("missing epd file", EPD_USAGE)
+    };
+
+    //one second per position, unless there is another limit
+    let mut options = EngineOptions { move_time: Some(play::DEFAULT_MOVE_TIME), ..Default::default() };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = iter.next().unwrap_or_else(|| exit_with_usage(&format!("missing value for {arg}"), EPD_USAGE));
+        let number = value.parse::<u64>().unwrap_or_else(|_| exit_with_usage(&format!("invalid value for {arg}: {value}"), EPD_USAGE));
+
+        match arg.as_str() {
+            "--time" => options.move_time = Some(number),
+            "--depth" => (options.depth, options.move_time) = (Some(number), None),
+            "--nodes" => (options.nodes, options.move_time) = (Some(number), None),
+            _ => exit_with_usage(&format!("unknown argument: {arg}"), EPD_USAGE)
+        }
+    }
+
+    let entries = epd::load(path).unwrap_or_else(|e| exit_with_error(&format!("could not load '{}': {e}", path.display())));
+
+    //the epd mode only prints the results
+    let mut engine = config.create_engine(Arc::new(SilentObserver))?;
+    epd::run_suite(&mut engine, &entries, &options);
+    Ok(())
+}
+
+const REPLAY_USAGE: &str = "usage: carlito replay LOG_FILE [--nodes N]";
+
+//carlito replay LOG_FILE [--nodes N]
+fn replay_mode(config: &Config, args: &[String]) -> Result<(), ConfigError> {
+    let (path, nodes) = match args {
+        [path] => (Path::new(path), None),
+        [path, option, n] if option == "--nodes" => {
+            let nodes = n.parse::<u64>().unwrap_or_else(|_| exit_with_usage(&format!("invalid number of nodes: {n}"), REPLAY_USAGE));
+            (Path::new(path), Some(nodes))
+        },
+        [] => exit_with_usage("missing log file", REPLAY_USAGE),
+        _ => exit_with_usage("invalid arguments", REPLAY_USAGE)
+    };
+
+    let session = Session::load(path).unwrap_or_else(|e| exit_with_error(&format!("could not read '{}': {e}", path.display())));
+    let best_move = uci::replay(config, &session, nodes)?;
+
+    let replayed = best_move.map_or("0000".to_string(), |m| m.to_string());
+    let logged = session.best_move.as_deref().and_then(|line| line.split_whitespace().nth(1));
+    match logged {
+        Some(m) if m == replayed => println!("\nreplayed bestmove {replayed}, same as in the log"),
+        Some(m) => println!("\nreplayed bestmove {replayed}, the log has bestmove {m}"),
+        None => println!("\nreplayed bestmove {replayed}, the log has no bestmove")
+    }
+
+    Ok(())
+}
+
+const TABLEBASE_USAGE: &str = "usage: carlito tablebase DIR ENDGAME... (for example: carlito tablebase tables KQK KRKP)";
+
+//carlito tablebase DIR ENDGAME...
+fn tablebase_mode(args: &[String]) {
+    let (dir, endgames) = match args.split_first() {
+        Some((dir, endgames)) if !endgames.is_empty() => (Path::new(dir), endgames),
+        _ => exit_with_usage("missing arguments", TABLEBASE_USAGE)
+    };
+
+    //tables that were generated before are not generated again
+    let mut tablebases = if dir.is_dir() {
+        Tablebases::load(dir).unwrap_or_else(|e| exit_with_error(&format!("could not load tablebases from '{}': {e}", dir.display())))
+    } else {
+        Tablebases::new()
+    };
+
+    for endgame in endgames {
+        println!("generating {endgame}");
+        if let Err(e) = tablebases.generate(&endgame.to_ascii_uppercase()) {
+            exit_with_usage(&e.to_string(), TABLEBASE_USAGE);
+        }
+    }
+
+    if let Err(e) = tablebases.save(dir) {
+        exit_with_error(&format!("could not save tablebases to '{}': {e}", dir.display()));
+    }
+
+    for table in tablebases.tables() {
+        println!("{}: longest mate in {}", table.name(), table.longest_mate());
+    }
+}
+
+fn exit_with_usage(message: &str, usage: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!("{usage}");
+    process::exit(1);
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1);
+}
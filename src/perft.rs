@@ -0,0 +1,278 @@
+use std::mem::size_of;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+use crate::bitboard::Bitboard;
+use crate::chess_move::*;
+use crate::position::*;
+
+/**
+ * fixed size hash table for perft results, shared between threads. Entries store the hash xor'ed
+ * with the data, so torn writes from different threads are detected on look up.
+ */
+pub struct PerftTable {
+    table: Vec<[AtomicU64; 2]>
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub en_passant: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub discovered_checks: u64,
+    pub double_checks: u64,
+    pub checkmates: u64
+}
+
+impl PerftTable {
+    pub const DEFAULT_MB_SIZE: usize = 64;
+
+    pub fn new(mb_size: usize) -> PerftTable {
+        let len = (mb_size * (1 << 20) / size_of::<[AtomicU64; 2]>()).max(1);
+
+        PerftTable {
+            table: (0..len).map(|_| [AtomicU64::new(0), AtomicU64::new(0)]).collect()
+        }
+    }
+
+    //data layout: the lower 8 bits store the depth, the upper 56 bits the node count
+    fn lookup(&self, hash: u64, depth: u32) -> Option<u64> {
+        let entry = &self.table[Self::index(hash, depth, self.table.len())];
+
+        let data = entry[1].load(Ordering::Relaxed);
+        if entry[0].load(Ordering::Relaxed) ^ data == hash && data & 0xff == depth as u64 {
+            Some(data >> 8)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&self, hash: u64, depth: u32, nodes: u64) {
+        if depth > 0xff || nodes >= 1 << 56 {
+            return;
+        }
+
+        let entry = &self.table[Self::index(hash, depth, self.table.len())];
+
+        let data = nodes << 8 | depth as u64;
+        entry[0].store(hash ^ data, Ordering::Relaxed);
+        entry[1].store(data, Ordering::Relaxed);
+    }
+
+    fn index(hash: u64, depth: u32, len: usize) -> usize {
+        (hash ^ (depth as u64).wrapping_mul(0x9e3779b97f4a7c15)) as usize % len
+    }
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.captures += rhs.captures;
+        self.en_passant += rhs.en_passant;
+        self.castles += rhs.castles;
+        self.promotions += rhs.promotions;
+        self.checks += rhs.checks;
+        self.discovered_checks += rhs.discovered_checks;
+        self.double_checks += rhs.double_checks;
+        self.checkmates += rhs.checkmates;
+    }
+}
+
+impl std::fmt::Display for PerftStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "nodes {} captures {} ep {} castles {} promotions {} checks {} discovered_checks {} double_checks {} checkmates {}",
+            self.nodes, self.captures, self.en_passant, self.castles, self.promotions, self.checks, self.discovered_checks, self.double_checks, self.checkmates)
+    }
+}
+
+/*
+ * perft with bulk counting at the last ply and a transposition table
+ */
+
+pub fn perft(pos: &mut Position, depth: u32, table: &PerftTable) -> u64 {
+    if depth == 0 {
+        1
+    } else if depth == 1 {
+        pos.legal_moves().len() as u64
+    } else {
+        if let Some(res) = table.lookup(pos.hash(), depth) {
+            return res;
+        }
+
+        let mut result = 0;
+        for m in pos.legal_moves() {
+            pos.make_move(m);
+            result += perft(pos, depth-1, table);
+            pos.unmake_move(m);
+        }
+
+        table.insert(pos.hash(), depth, result);
+
+        result
+    }
+}
+
+/*
+ * perft that classifies the moves of the last ply like the tables on the chessprogramming wiki.
+ * No bulk counting or hashing is possible here, because every leaf has to be visited.
+ */
+
+pub fn perft_stats(pos: &mut Position, depth: u32) -> PerftStats {
+    let mut stats = PerftStats::default();
+
+    if depth == 0 {
+        stats.nodes = 1;
+        return stats;
+    }
+
+    for m in pos.legal_moves() {
+        if depth == 1 {
+            classify_move(pos, m, &mut stats);
+        } else {
+            pos.make_move(m);
+            stats += perft_stats(pos, depth-1);
+            pos.unmake_move(m);
+        }
+    }
+
+    stats
+}
+
+fn classify_move(pos: &mut Position, m: Move, stats: &mut PerftStats) {
+    stats.nodes += 1;
+
+    if pos.is_capture(m) {
+        stats.captures += 1;
+    }
+    if m.is_en_passant() {
+        stats.en_passant += 1;
+    }
+    if m.castling_type().is_some() {
+        stats.castles += 1;
+    }
+    if m.promote_to().is_some() {
+        stats.promotions += 1;
+    }
+
+    pos.make_move(m);
+
+    let checkers = pos.checkers();
+
+    if !checkers.is_empty() {
+        stats.checks += 1;
+
+        /*
+         * a single check is discovered if it is given by a piece other than the one that moved. For castling the
+         * rook is the moving piece. Double checks are counted separately, like in the chessprogramming wiki tables.
+         */
+        let moved_piece_square = match m.castling_type() {
+            Some(c) => Position::CASTLE_ROOK_TO[c as usize],
+            None => m.to()
+        };
+
+        if checkers.count_squares() >= 2 {
+            stats.double_checks += 1;
+        } else if !(checkers & !Bitboard::from_square(moved_piece_square)).is_empty() {
+            stats.discovered_checks += 1;
+        }
+        if pos.legal_moves().is_empty() {
+            stats.checkmates += 1;
+        }
+    }
+
+    pos.unmake_move(m);
+}
+
+/**
+ * runs f for every legal move of the position on up to 'threads' threads. Every thread works on its
+ * own copy of the position, with the root move already made. The results are returned in move generation order.
+ */
+pub fn split_root_moves<T, F>(pos: &mut Position, threads: usize, f: F) -> Vec<(Move, T)> 
+    where T: Send, F: Fn(&mut Position) -> T + Sync {
+
+    let moves = pos.legal_moves();
+    let next_move = AtomicUsize::new(0);
+
+    let mut results: Vec<(usize, Move, T)> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.clamp(1, moves.len().max(1))).map(|_| {
+            let mut pos = pos.clone();
+            let (moves, next_move, f) = (&moves, &next_move, &f);
+
+            s.spawn(move || {
+                let mut results = Vec::new();
+                loop {
+                    let i = next_move.fetch_add(1, Ordering::Relaxed);
+                    if i >= moves.len() {
+                        break;
+                    }
+
+                    pos.make_move(moves[i]);
+                    results.push((i, moves[i], f(&mut pos)));
+                    pos.unmake_move(moves[i]);
+                }
+                results
+            })
+        }).collect();
+
+        handles.into_iter().flat_map(|h| h.join().expect("perft thread panicked")).collect()
+    });
+
+    results.sort_by_key(|(i, _, _)| *i);
+    results.into_iter().map(|(_, m, res)| (m, res)).collect()
+}
+
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //nodes, captures, en passant, castles, promotions, checks, discovered checks, double checks, checkmates
+    fn stats(s: [u64; 9]) -> PerftStats {
+        PerftStats { 
+            nodes: s[0], captures: s[1], en_passant: s[2], castles: s[3], promotions: s[4], 
+            checks: s[5], discovered_checks: s[6], double_checks: s[7], checkmates: s[8] 
+        }
+    }
+
+    #[test]
+    fn perft_stats_small() {
+        let start_pos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let position_3 = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+
+        assert_eq!(perft_stats(&mut Position::from_fen_string(start_pos).unwrap(), 4), stats([197_281, 1_576, 0, 0, 0, 469, 0, 0, 8]));
+        assert_eq!(perft_stats(&mut Position::from_fen_string(kiwipete).unwrap(), 3), stats([97_862, 17_102, 45, 3_162, 0, 993, 0, 0, 1]));
+        assert_eq!(perft_stats(&mut Position::from_fen_string(position_3).unwrap(), 4), stats([43_238, 3_348, 123, 0, 0, 1_680, 106, 0, 17]));
+    }
+
+    #[test]
+    #[ignore]
+    fn perft_stats_full() {
+        let start_pos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let position_3 = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+
+        assert_eq!(perft_stats(&mut Position::from_fen_string(start_pos).unwrap(), 5), stats([4_865_609, 82_719, 258, 0, 0, 27_351, 6, 0, 347]));
+        assert_eq!(perft_stats(&mut Position::from_fen_string(kiwipete).unwrap(), 4), stats([4_085_603, 757_163, 1_929, 128_013, 15_172, 25_523, 42, 6, 43]));
+        assert_eq!(perft_stats(&mut Position::from_fen_string(position_3).unwrap(), 5), stats([674_624, 52_051, 1_165, 0, 0, 52_950, 1_292, 3, 0]));
+    }
+
+    #[test]
+    fn parallel_perft() {
+        let mut pos = Position::from_fen_string("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let table = PerftTable::new(1);
+
+        let results = split_root_moves(&mut pos, 4, |pos| perft(pos, 2, &table));
+
+        assert_eq!(results.len(), 48);
+        assert_eq!(results.iter().map(|(_, n)| n).sum::<u64>(), 97_862);
+    }
+}
@@ -0,0 +1,445 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::position::*;
+use crate::chess_move::*;
+use crate::engine::*;
+use crate::engine::strength::MAX_SKILL_LEVEL;
+use crate::uci::NAME;
+use crate::config::{Config, ConfigError};
+
+/*
+ * interactive play mode for the terminal: the user plays against the engine
+ */
+
+const HELP: &str = "\
+commands:
+  <move>         play a move in SAN (Nf3, exd5, O-O, e8=Q) or UCI notation (g1f3, e7e8q)
+  go             let the engine play the side to move
+  undo           take back the last move of each side
+  hint           suggest a move for the side to move
+  flip           flip the board
+  fen [FEN]      show the current fen or set up a new position
+  new            start a new game
+  time <ms>      engine time per move
+  depth <n>      engine search depth limit, 'depth off' removes the limit
+  nodes <n>      engine node limit, 'nodes off' removes the limit
+  skill <n>      engine skill level from 0 to 20, 20 is full strength
+  help           show this help
+  quit           exit the program";
+
+pub const DEFAULT_MOVE_TIME: u64 = 1000;
+
+//how strong the engine plays and how long it thinks about a move
+#[derive(Debug, Clone)]
+pub struct PlaySettings {
+    pub move_time: u64,
+    pub depth: Option<u64>,
+    pub nodes: Option<u64>,
+    pub skill_level: u8
+}
+
+impl Default for PlaySettings {
+    fn default() -> Self {
+        PlaySettings {
+            move_time: DEFAULT_MOVE_TIME,
+            depth: None,
+            nodes: None,
+            skill_level: MAX_SKILL_LEVEL
+        }
+    }
+}
+
+pub fn input_loop(config: &Config, position: Position, engine_color: Option<Color>, settings: PlaySettings) -> Result<(), ConfigError> {
+    //the game is shown in the terminal, there is nothing to report during the search
+    let mut game = Game::new(config.create_engine(Arc::new(SilentObserver))?, position, settings);
+    game.flipped = engine_color == Some(Color::White);
+
+    println!("{NAME}. Type 'help' for a list of commands.");
+    game.print_board();
+
+    if engine_color == Some(game.position.current_player()) {
+        game.engine_move();
+    }
+
+    game.input_loop();
+    Ok(())
+}
+
+struct Game {
+    position: Position,
+    moves: Vec<Move>,
+    engine: Engine,
+    settings: PlaySettings,
+    engine_color: Option<Color>,
+    flipped: bool
+}
+
+impl Game {
+    fn new(engine: Engine, position: Position, settings: PlaySettings) -> Game {
+        Game {
+            position,
+            moves: Vec::new(),
+            engine,
+            settings,
+            engine_color: None,
+            flipped: false
+        }
+    }
+
+    fn input_loop(&mut self) {
+        loop {
+            print!("> ");
+            io::stdout().flush().expect("error writing to stdout");
+
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line).expect("error reading stdin") == 0 {
+                println!();
+                return;
+            }
+
+            if !self.parse_command(line.trim()) {
+                return;
+            }
+        }
+    }
+
+    //returns false if the program should exit
+    fn parse_command(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+
+        let command = match tokens.next() {
+            Some(c) => c,
+            None => return true
+        };
+
+        match command {
+            "quit" | "exit" => return false,
+            "help" => println!("{HELP}"),
+            "go" => {
+                if self.position.outcome().is_some() {
+                    println!("the game is over");
+                } else {
+                    self.engine_move();
+                }
+            },
+            "undo" => self.undo(),
+            "hint" => self.hint(),
+            "flip" => {
+                self.flipped = !self.flipped;
+                self.print_board();
+            },
+            "fen" => {
+                let fen = tokens.collect::<Vec<_>>().join(" ");
+                if fen.is_empty() {
+                    println!("{}", self.position.to_fen_string());
+                } else {
+                    match Position::from_fen_string(&fen) {
+                        Ok(p) => {
+                            self.position = p;
+                            self.moves.clear();
+                            self.engine_color = None;
+                            self.print_board();
+                        },
+                        Err(e) => println!("invalid fen: {e}")
+                    }
+                }
+            },
+            "new" => {
+                self.position = Position::new();
+                self.moves.clear();
+                self.engine_color = None;
+                self.engine.clear_table();
+                self.print_board();
+            },
+            "time" => match tokens.next().and_then(|t| t.parse::<u64>().ok()) {
+                Some(t) => self.settings.move_time = t,
+                None => println!("usage: time <milliseconds>")
+            },
+            "depth" => match Self::parse_limit(tokens.next()) {
+                Some(d) => self.settings.depth = d,
+                None => println!("usage: depth <n>|off")
+            },
+            "nodes" => match Self::parse_limit(tokens.next()) {
+                Some(n) => self.settings.nodes = n,
+                None => println!("usage: nodes <n>|off")
+            },
+            "skill" => match tokens.next().and_then(|t| t.parse::<u8>().ok()).filter(|s| *s <= MAX_SKILL_LEVEL) {
+                Some(s) => self.settings.skill_level = s,
+                None => println!("usage: skill <0-{MAX_SKILL_LEVEL}>")
+            },
+            m => self.user_move(m)
+        }
+
+        true
+    }
+
+    //Some(None) for 'off'
+    fn parse_limit(token: Option<&str>) -> Option<Option<u64>> {
+        match token {
+            Some("off") => Some(None),
+            Some(t) => t.parse::<u64>().ok().filter(|n| *n > 0).map(Some),
+            None => None
+        }
+    }
+
+    fn user_move(&mut self, move_str: &str) {
+        if self.position.outcome().is_some() {
+            println!("the game is over. Use 'undo', 'new' or 'fen' to continue.");
+            return;
+        }
+
+        let m = match Move::from_string(move_str, &mut self.position).or_else(|_| Move::from_san(move_str, &mut self.position)) {
+            Ok(m) => m,
+            Err(_) => {
+                println!("unknown command or illegal move '{move_str}'. Type 'help' for a list of commands.");
+                return;
+            }
+        };
+
+        //the engine answers with the other color from now on
+        self.engine_color = Some(!self.position.current_player());
+
+        self.play(m);
+        if !self.game_over() {
+            self.engine_move();
+        }
+    }
+
+    fn engine_move(&mut self) {
+        self.engine_color = Some(self.position.current_player());
+
+        println!("thinking...");
+        let result = self.search(self.settings.skill_level);
+
+        let m = match result.best_move {
+            Some(m) => m,
+            None => return
+        };
+
+        let san = m.to_san(&mut self.position);
+        match result.score {
+            Some(score) => println!("{NAME} plays {san} (score {}, depth {})", Self::format_score(score, self.position.current_player()), result.depth),
+            None => println!("{NAME} plays {san}")
+        }
+
+        self.play(m);
+        self.game_over();
+    }
+
+    fn hint(&mut self) {
+        if self.position.outcome().is_some() {
+            println!("the game is over");
+            return;
+        }
+
+        //hints are always given at full strength
+        let result = self.search(MAX_SKILL_LEVEL);
+        if let Some(m) = result.best_move {
+            println!("hint: {}", m.to_san(&mut self.position));
+        }
+    }
+
+    fn search(&mut self, skill_level: u8) -> SearchResult {
+        let options = EngineOptions {
+            move_time: Some(self.settings.move_time),
+            depth: self.settings.depth,
+            nodes: self.settings.nodes,
+            skill_level: Some(skill_level),
+            ..Default::default()
+        };
+
+        self.engine.search_sync(self.position.clone(), options)
+    }
+
+    fn play(&mut self, m: Move) {
+        self.position.make_move(m);
+        self.moves.push(m);
+        self.print_board();
+    }
+
+    //takes back the last move of the user and the engine's answer to it
+    fn undo(&mut self) {
+        let plies = match self.engine_color {
+            Some(c) if c != self.position.current_player() && self.moves.len() >= 2 => 2,
+            _ => 1
+        };
+
+        if self.moves.is_empty() {
+            println!("no moves to take back");
+            return;
+        }
+
+        for _ in 0..plies {
+            let m = self.moves.pop().unwrap();
+            self.position.unmake_move(m);
+        }
+
+        self.print_board();
+    }
+
+    //announces the result if the game ended. Returns true if it did.
+    fn game_over(&mut self) -> bool {
+        let message = match self.position.outcome() {
+            None => return false,
+            Some(GameOutcome::Checkmate { winner: Color::White }) => "1-0, white wins by checkmate",
+            Some(GameOutcome::Checkmate { winner: Color::Black }) => "0-1, black wins by checkmate",
+            Some(GameOutcome::Stalemate) => "1/2-1/2, draw by stalemate",
+            Some(GameOutcome::InsufficientMaterial) => "1/2-1/2, draw by insufficient material",
+            Some(GameOutcome::FiftyMoveRule) => "1/2-1/2, draw by the 50 move rule",
+            Some(GameOutcome::Repetition) => "1/2-1/2, draw by threefold repetition"
+        };
+
+        println!("game over: {message}");
+        true
+    }
+
+    //scores are shown from white's point of view
+    fn format_score(score: Score, player: Color) -> String {
+        let sign = if player == Color::White { 1 } else { -1 };
+
+        match score.mate() {
+            Some(m) => format!("#{}", m as i32 * sign),
+            None => format!("{:+.2}", (score.centi_pawns().unwrap() as i32 * sign) as f64 / 100.0)
+        }
+    }
+
+    fn print_board(&self) {
+        let rows: Vec<u8> = if self.flipped { (0..8).rev().collect() } else { (0..8).collect() };
+        let columns: Vec<u8> = if self.flipped { (0..8).rev().collect() } else { (0..8).collect() };
+
+        println!();
+        for &row in rows.iter() {
+            print!(" {} ", 8 - row);
+            for &column in columns.iter() {
+                let square = 8*row + column;
+                let c = match self.position.piece_on(square) {
+                    Piece::Pawn => 'p',
+                    Piece::Knight => 'n',
+                    Piece::Bishop => 'b',
+                    Piece::Rook => 'r',
+                    Piece::Queen => 'q',
+                    Piece::King => 'k',
+                    Piece::NoPiece => '.'
+                };
+
+                if self.position.square_color(square) == Some(Color::White) {
+                    print!(" {}", c.to_ascii_uppercase());
+                } else {
+                    print!(" {c}");
+                }
+            }
+            println!();
+        }
+
+        print!("   ");
+        for &column in columns.iter() {
+            print!(" {}", (b'a' + column) as char);
+        }
+        println!();
+        println!();
+
+        match self.position.current_player() {
+            Color::White => print!("white to move"),
+            Color::Black => print!("black to move")
+        }
+        if self.position.in_check() {
+            print!(", check");
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game() -> Game {
+        let engine = Config { hash: 1, ..Config::default() }.create_engine(Arc::new(SilentObserver)).unwrap();
+        let settings = PlaySettings { depth: Some(2), ..PlaySettings::default() };
+        Game::new(engine, Position::new(), settings)
+    }
+
+    #[test]
+    fn moves_and_undo() {
+        let mut game = game();
+
+        //the engine answers every user move, in SAN or UCI notation
+        game.parse_command("e4");
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.engine_color, Some(Color::Black));
+        game.parse_command("g1f3");
+        assert_eq!(game.moves.len(), 4);
+
+        //illegal moves and unknown commands change nothing
+        let fen = game.position.to_fen_string();
+        for command in ["e4", "Ke3", "foo"] {
+            game.parse_command(command);
+        }
+        assert_eq!(game.position.to_fen_string(), fen);
+
+        //undo takes back the engine's answer together with the user's move
+        game.parse_command("undo");
+        assert_eq!(game.moves.len(), 2);
+        assert_eq!(game.position.current_player(), Color::White);
+        game.parse_command("undo");
+        game.parse_command("undo");
+        assert!(game.moves.is_empty());
+        assert_eq!(game.position.to_fen_string(), Position::new().to_fen_string());
+
+        //go lets the engine play the side to move
+        game.parse_command("go");
+        assert_eq!(game.engine_color, Some(Color::White));
+        assert_eq!(game.position.current_player(), Color::Black);
+
+        assert!(!game.parse_command("quit"));
+    }
+
+    #[test]
+    fn game_over() {
+        let mut game = game();
+        game.parse_command("fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        assert!(game.moves.is_empty());
+
+        //the engine doesn't answer a mate, and no moves can be played after it
+        game.parse_command("Ra8#");
+        assert_eq!(game.moves.len(), 1);
+        assert_eq!(game.position.outcome(), Some(GameOutcome::Checkmate { winner: Color::White }));
+        game.parse_command("Kf8");
+        game.parse_command("go");
+        assert_eq!(game.moves.len(), 1);
+
+        game.parse_command("new");
+        assert!(game.moves.is_empty());
+        assert_eq!(game.engine_color, None);
+    }
+
+    #[test]
+    fn settings() {
+        let mut game = game();
+        for command in ["time 200", "depth off", "nodes 5000", "skill 5"] {
+            game.parse_command(command);
+        }
+        assert_eq!(game.settings.move_time, 200);
+        assert_eq!(game.settings.depth, None);
+        assert_eq!(game.settings.nodes, Some(5000));
+        assert_eq!(game.settings.skill_level, 5);
+
+        //invalid values keep the old settings
+        for command in ["time x", "depth 0", "nodes", "skill 21"] {
+            game.parse_command(command);
+        }
+        assert_eq!(game.settings.move_time, 200);
+        assert_eq!(game.settings.depth, None);
+        assert_eq!(game.settings.nodes, Some(5000));
+        assert_eq!(game.settings.skill_level, 5);
+    }
+
+    #[test]
+    fn score_format() {
+        assert_eq!(Game::format_score(Score::from_centi_pawns(50), Color::White), "+0.50");
+        assert_eq!(Game::format_score(Score::from_centi_pawns(50), Color::Black), "-0.50");
+        assert_eq!(Game::format_score(Score::from_mate_distance(3), Color::White), "#3");
+        assert_eq!(Game::format_score(Score::from_mate_distance(3), Color::Black), "#-3");
+    }
+}
@@ -1,8 +1,12 @@
-use std::collections::HashMap;
-use std::num::IntErrorKind;
+use std::num::{IntErrorKind, ParseIntError};
+
+mod cuckoo;
+pub(crate) mod psqt;
 
 use crate::bitboard::*;
 use crate::chess_move::*;
+use crate::perft::{self, PerftTable};
+use psqt::{piece_value, phase_value};
 
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -37,6 +41,116 @@ impl std::ops::Not for Color {
 
 use Piece::*;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOutcome {
+    Checkmate { winner: Color },
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoveRule,
+    Repetition
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FenField {
+    PiecePlacement,
+    SideToMove,
+    Castling,
+    EnPassant,
+    HalfMoveClock,
+    FullMoveNumber
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FenError {
+    MissingField(FenField),
+    TrailingCharacters(String),
+    WrongNumberOfRanks(usize),
+    WrongRankLength(u8),
+    InvalidPieceChar(char),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    CastlingWithoutKingOrRook(char),
+    InvalidEnPassantSquare(String),
+    EnPassantInconsistent(String),
+    InvalidNumber(FenField, String),
+    MissingKing(Color),
+    TooManyKings(Color),
+    PawnOnBackRank(u8),
+    SideNotToMoveInCheck
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SquareError {
+    WrongLength(String),
+    InvalidFile(char),
+    InvalidRank(char)
+}
+
+impl FenError {
+    pub fn field(&self) -> FenField {
+        match self {
+            FenError::MissingField(f) | FenError::InvalidNumber(f, _) => *f,
+            FenError::TrailingCharacters(_) => FenField::FullMoveNumber,
+            FenError::WrongNumberOfRanks(_) | FenError::WrongRankLength(_) | FenError::InvalidPieceChar(_) 
+                | FenError::MissingKing(_) | FenError::TooManyKings(_) | FenError::PawnOnBackRank(_) => FenField::PiecePlacement,
+            FenError::InvalidSideToMove(_) | FenError::SideNotToMoveInCheck => FenField::SideToMove,
+            FenError::InvalidCastling(_) | FenError::CastlingWithoutKingOrRook(_) => FenField::Castling,
+            FenError::InvalidEnPassantSquare(_) | FenError::EnPassantInconsistent(_) => FenField::EnPassant
+        }
+    }
+}
+
+impl std::fmt::Display for FenField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            FenField::PiecePlacement => "piece placement",
+            FenField::SideToMove => "side to move",
+            FenField::Castling => "castling rights",
+            FenField::EnPassant => "en passant square",
+            FenField::HalfMoveClock => "halfmove clock",
+            FenField::FullMoveNumber => "fullmove number"
+        })
+    }
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.field())?;
+
+        match self {
+            FenError::MissingField(_) => write!(f, "missing"),
+            FenError::TrailingCharacters(s) => write!(f, "unexpected characters after the last field: '{s}'"),
+            FenError::WrongNumberOfRanks(n) => write!(f, "expected 8 ranks, got {n}"),
+            FenError::WrongRankLength(r) => write!(f, "rank {r} does not have exactly 8 squares"),
+            FenError::InvalidPieceChar(c) => write!(f, "invalid piece character '{c}'"),
+            FenError::InvalidSideToMove(s) => write!(f, "expected 'w' or 'b', got '{s}'"),
+            FenError::InvalidCastling(s) => write!(f, "expected '-' or a subset of 'KQkq' in this order, got '{s}'"),
+            FenError::CastlingWithoutKingOrRook(c) => write!(f, "castling right '{c}' without king and rook on their initial squares"),
+            FenError::InvalidEnPassantSquare(s) => write!(f, "invalid square '{s}'"),
+            FenError::EnPassantInconsistent(s) => write!(f, "'{s}' is not the target square of a pawn double move"),
+            FenError::InvalidNumber(_, s) => write!(f, "expected a non negative integer, got '{s}'"),
+            FenError::MissingKing(c) => write!(f, "{c:?} has no king"),
+            FenError::TooManyKings(c) => write!(f, "{c:?} has more than one king"),
+            FenError::PawnOnBackRank(s) => write!(f, "pawn on the back rank ({})", Position::square_to_string(*s)),
+            FenError::SideNotToMoveInCheck => write!(f, "the side not to move is in check")
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+impl std::fmt::Display for SquareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SquareError::WrongLength(s) => write!(f, "expected a square like 'e4', got '{s}'"),
+            SquareError::InvalidFile(c) => write!(f, "invalid file '{c}'"),
+            SquareError::InvalidRank(c) => write!(f, "invalid rank '{c}'")
+        }
+    }
+}
+
+impl std::error::Error for SquareError {}
+
 const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
 #[derive(Debug, Clone)]
@@ -51,7 +165,15 @@ struct StackFrame {
 
     pinned: Bitboard,
 
-    hash: u64
+    hash: u64,
+
+    //zobrist hash of the pawns only, used by the pawn structure cache of the evaluator
+    pawn_hash: u64,
+
+    //material and piece square values (middle game, end game) from white's point of view, and the game phase, for the evaluator
+    psqt: (i32, i32),
+
+    game_phase: u32
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +187,9 @@ pub struct Position {
     current_player: Color,
 
     stack: Vec<StackFrame>,
+
+    //full move number of the fen string the position was created from
+    initial_full_move_number: u32
 }
 
 impl Default for Position {
@@ -119,6 +244,22 @@ impl Position {
         self.stack_frame().hash
     }
 
+    pub fn pawn_hash(&self) -> u64 {
+        self.stack_frame().pawn_hash
+    }
+
+    pub fn psqt(&self) -> (i32, i32) {
+        self.stack_frame().psqt
+    }
+
+    pub fn game_phase(&self) -> u32 {
+        self.stack_frame().game_phase
+    }
+
+    pub fn has_castling_rights(&self) -> bool {
+        self.stack_frame().castling_rights != 0
+    }
+
     pub fn half_move_clock(&self) -> u32 {
         self.stack_frame().half_move_clock
     }
@@ -147,12 +288,77 @@ impl Position {
         self.is_attacked(self.king_square(self.current_player()), self.current_player())
     }
 
+    //pieces of the opponent that give check to the current player
+    pub fn checkers(&self) -> Bitboard {
+        let king_square = self.king_square(self.current_player);
+        let player = self.current_player;
+        let occupied = self.occupied();
+
+        let pawn_attack_squares = if player == White {
+            Bitboard::from_square(king_square).shift(Direction::UpLeft) | Bitboard::from_square(king_square).shift(Direction::UpRight)
+        } else {
+            Bitboard::from_square(king_square).shift(Direction::DownLeft) | Bitboard::from_square(king_square).shift(Direction::DownRight)
+        };
+
+        (Bitboard::knight_attacks(king_square) & self.pieces(Knight, !player))
+            | (pawn_attack_squares & self.pieces(Pawn, !player))
+            | (Bitboard::bishop_attacks(king_square, occupied) & (self.pieces(Bishop, !player) | self.pieces(Queen, !player)))
+            | (Bitboard::rook_attacks(king_square, occupied) & (self.pieces(Rook, !player) | self.pieces(Queen, !player)))
+    }
+
     /*
      * fen parsing
      */
 
-    pub fn from_fen_string(fen: &str) -> Result<Position, ()> {
-        let mut p = Position {
+    pub fn from_fen_string(fen: &str) -> Result<Position, FenError> {
+        let mut p = Self::empty();
+
+        let mut sections = fen.split(' ');
+
+        p.parse_board(sections.next().ok_or(FenError::MissingField(FenField::PiecePlacement))?)?;
+        p.parse_player(sections.next().ok_or(FenError::MissingField(FenField::SideToMove))?)?;
+        p.validate_board()?;
+        p.parse_castling(sections.next().ok_or(FenError::MissingField(FenField::Castling))?)?;
+        p.parse_en_passant(sections.next().ok_or(FenError::MissingField(FenField::EnPassant))?)?;
+
+        let half_move_clock = sections.next().ok_or(FenError::MissingField(FenField::HalfMoveClock))?;
+        p.mut_stack_frame().half_move_clock = Self::parse_int(half_move_clock)
+            .map_err(|_| FenError::InvalidNumber(FenField::HalfMoveClock, half_move_clock.to_string()))?
+            .clamp(0, (u32::MAX/2) as u64) as u32;
+
+        let full_move_number = sections.next().ok_or(FenError::MissingField(FenField::FullMoveNumber))?;
+        p.initial_full_move_number = Self::parse_int(full_move_number)
+            .map_err(|_| FenError::InvalidNumber(FenField::FullMoveNumber, full_move_number.to_string()))?
+            .clamp(1, (u32::MAX/2) as u64) as u32;
+
+        if let Some(s) = sections.next() {
+            return Err(FenError::TrailingCharacters(s.to_string()));
+        }
+
+        p.init_stack_frame();
+
+        Ok(p)
+    }
+
+    //a position with the given pieces, without castling rights and en passant square, e.g. for endgame tablebases. The squares have to be different.
+    pub fn from_pieces(pieces: &[(Piece, Color, u8)], current_player: Color) -> Result<Position, FenError> {
+        let mut p = Self::empty();
+        p.current_player = current_player;
+
+        for &(piece, player, square) in pieces {
+            debug_assert!(p.squares[square as usize] == NoPiece, "two pieces on square {square}");
+            p.add_piece::<false>(piece, player, square);
+        }
+
+        p.validate_board()?;
+        p.init_stack_frame();
+
+        Ok(p)
+    }
+
+    //an empty board, which is not a valid position
+    fn empty() -> Position {
+        Position {
             squares: [NoPiece; 64],
             piece_bb: [Bitboard::from_u64(0); 6],
             color_bb: [Bitboard::from_u64(0); 2],
@@ -163,119 +369,129 @@ impl Position {
                 half_move_clock: 0,
                 captured_piece: NoPiece,
                 pinned: Bitboard::new(),
-                hash: 0
-            }]
-        };
-
-        let mut sections = fen.split(' ');
-
-
-        p.parse_board(sections.next().ok_or(())?)?;
-        p.parse_player(sections.next().ok_or(())?)?;
-        p.parse_castling(sections.next().ok_or(())?)?;
-        p.parse_en_passant(sections.next().ok_or(())?)?;
-
-        p.mut_stack_frame().half_move_clock = Self::parse_int(sections.next().ok_or(())?)?.clamp(0, (u32::MAX/2) as u64) as u32;
-        Self::parse_int(sections.next().ok_or(())?)?.clamp(0, (u32::MAX/2) as u64) as u32;
-
-        if sections.next().is_some() {
-            return Err(());
+                hash: 0,
+                pawn_hash: 0,
+                psqt: (0, 0),
+                game_phase: 0
+            }],
+            initial_full_move_number: 1
         }
+    }
 
-        p.mut_stack_frame().pinned = p.pinned_pieces();
-        p.mut_stack_frame().hash = p.calculate_hash();
-
-        Ok(p)
+    //values of the stack frame that are updated incrementally by make_move
+    fn init_stack_frame(&mut self) {
+        self.mut_stack_frame().pinned = self.pinned_pieces();
+        self.mut_stack_frame().hash = self.calculate_hash();
+        self.mut_stack_frame().pawn_hash = self.calculate_pawn_hash();
+        (self.mut_stack_frame().psqt, self.mut_stack_frame().game_phase) = self.calculate_psqt();
     }
 
-    pub fn parse_int(s: &str) -> Result<u64, ()> {
-        match u64::from_str_radix(s, 10) {
+    //parses a non negative integer. Values that are too large are clamped to u64::MAX.
+    pub fn parse_int(s: &str) -> Result<u64, ParseIntError> {
+        match s.parse::<u64>() {
             Ok(res) => Ok(res),
             Err(e) => match e.kind() {
                 IntErrorKind::PosOverflow => Ok(u64::MAX),
-                _ => Err(())
+                _ => Err(e)
             }
         }
     }
 
-    fn parse_board(&mut self, s: &str) -> Result<(), ()> {
-        let mut row = 0;
-        let mut column = 0;
+    fn parse_board(&mut self, s: &str) -> Result<(), FenError> {
+        let ranks = s.split('/').count();
+        if ranks != 8 {
+            return Err(FenError::WrongNumberOfRanks(ranks));
+        }
 
-        for c in s.chars() {
-            if column == 8 { //expecting '/'.
-                if c != '/' {
-                    return Err(());
-                }
-                if row >= 7{
-                    return Err(());
-                }
+        for (row, rank_str) in s.split('/').enumerate() {
+            let row = row as u8;
+            let mut column = 0;
 
-                row += 1;
-                column = 0;
+            for c in rank_str.chars() {
+                if let Some(d) = c.to_digit(9) {
+                    if c == '0' {
+                        return Err(FenError::WrongRankLength(8 - row));
+                    }
 
-            } else if let Some(d) = c.to_digit(9) {
-                if c == '0' {
-                    return Err(());
-                }
-                
-                column += d as u8;
-
-                if column > 8 {
-                    return Err(());
-                }
-            } else {
-                self.squares[(column+8*row) as usize] = match c.to_ascii_uppercase() {
-                    'P' => Pawn,
-                    'N' => Knight,
-                    'B' => Bishop,
-                    'R' => Rook,
-                    'Q' => Queen,
-                    'K' => King,
-                    _ => return Err(())
-                };
+                    column += d as u8;
 
-                let piece_color = if c.is_ascii_uppercase() {
-                    White
+                    if column > 8 {
+                        return Err(FenError::WrongRankLength(8 - row));
+                    }
                 } else {
-                    Black
-                };
-                self.color_bb[piece_color as usize] |= Bitboard::from_coords(column, row);
-                self.piece_bb[self.squares[(column+8*row) as usize] as usize] |= Bitboard::from_coords(column, row);
-                
-                column += 1;
+                    if column >= 8 {
+                        return Err(FenError::WrongRankLength(8 - row));
+                    }
+
+                    self.squares[(column+8*row) as usize] = match c.to_ascii_uppercase() {
+                        'P' => Pawn,
+                        'N' => Knight,
+                        'B' => Bishop,
+                        'R' => Rook,
+                        'Q' => Queen,
+                        'K' => King,
+                        _ => return Err(FenError::InvalidPieceChar(c))
+                    };
+
+                    let piece_color = if c.is_ascii_uppercase() {
+                        White
+                    } else {
+                        Black
+                    };
+                    self.color_bb[piece_color as usize] |= Bitboard::from_coords(column, row);
+                    self.piece_bb[self.squares[(column+8*row) as usize] as usize] |= Bitboard::from_coords(column, row);
+
+                    column += 1;
+                }
             }
-        }
 
-        if row != 7 || column != 8 {
-            return Err(());
+            if column != 8 {
+                return Err(FenError::WrongRankLength(8 - row));
+            }
         }
 
         Ok(())
     } 
 
-    fn parse_player(&mut self, s: &str) -> Result<(), ()> {
-        if s.len() != 1 {
-            return Err(());
+    fn parse_player(&mut self, s: &str) -> Result<(), FenError> {
+        self.current_player = match s {
+            "w" => White,
+            "b" => Black,
+            _ => return Err(FenError::InvalidSideToMove(s.to_string()))
+        };
+
+        Ok(())
+    }
+
+    //checks the piece placement for positions that can't be handled by the move generator
+    fn validate_board(&self) -> Result<(), FenError> {
+        for player in [White, Black] {
+            match self.pieces(King, player).count_squares() {
+                0 => return Err(FenError::MissingKing(player)),
+                1 => (),
+                _ => return Err(FenError::TooManyKings(player))
+            }
         }
 
-        self.current_player = match s.chars().next().unwrap() {
-            'w' => White,
-            'b' => Black,
-            _ => return Err(())
-        };
+        if let Some(square) = (self.pieces_by_type(Pawn) & (Bitboard::rank(0) | Bitboard::rank(7))).into_iter().next() {
+            return Err(FenError::PawnOnBackRank(square));
+        }
+
+        if self.is_attacked(self.king_square(!self.current_player), !self.current_player) {
+            return Err(FenError::SideNotToMoveInCheck);
+        }
 
         Ok(())
     }
 
-    fn parse_castling(&mut self, s: &str) -> Result<(), ()> {
+    fn parse_castling(&mut self, s: &str) -> Result<(), FenError> {
         if s == "-" {
             self.mut_stack_frame().castling_rights = 0;
             return Ok(());
         }
 
         if s.is_empty() {
-            return Err(());
+            return Err(FenError::InvalidCastling(s.to_string()));
         }
         
         let mut iter = s.chars().peekable();
@@ -286,44 +502,53 @@ impl Position {
             }
 
             if iter.peek().unwrap() == c {
+                //the king and the rook have to be on their initial squares
+                let (player, king_square) = if i < 2 { (White, 60) } else { (Black, 4) };
+                let rook_square = Self::CASTLE_ROOK_FROM[i];
+
+                if !self.pieces(King, player).contains(king_square) || !self.pieces(Rook, player).contains(rook_square) {
+                    return Err(FenError::CastlingWithoutKingOrRook(*c));
+                }
+
                 self.mut_stack_frame().castling_rights |= 1 << i;
                 iter.next();
             }
         }
 
         if iter.next().is_some() {
-            return Err(());
+            return Err(FenError::InvalidCastling(s.to_string()));
         }
 
         Ok(())
     }
 
-    fn parse_en_passant(&mut self, s: &str) -> Result<(), ()> {
+    fn parse_en_passant(&mut self, s: &str) -> Result<(), FenError> {
 
         if s == "-" {
             self.mut_stack_frame().en_passant_file = None;
             return Ok(());
         }
 
-        let square = Position::parse_square(s)?;
+        let square = Position::parse_square(s).map_err(|_| FenError::InvalidEnPassantSquare(s.to_string()))?;
 
         let rank = square / 8;
         let file = square % 8;
 
         if (self.current_player == White && rank != 2) || (self.current_player == Black && rank != 5) {
-            return Err(());
+            return Err(FenError::EnPassantInconsistent(s.to_string()));
         }
 
-        //only save en passant square if there is a pseudo legal en passant move
-
         let from_rank = if self.current_player == White { rank + 1 } else { rank - 1 };
+        let origin_rank = if self.current_player == White { rank - 1 } else { rank + 1 };
 
-        //check if there is an enemy pawn above/below the en passant target square
-        if !((self.squares[(from_rank*8+file) as usize] == Pawn) && (self.square_color(from_rank*8+file) != Some(self.current_player))) {
-            self.mut_stack_frame().en_passant_file = None;
-            return Ok(());
+        //there has to be an enemy pawn above/below the en passant target square, that just moved two squares
+        if !self.pieces(Pawn, !self.current_player).contains(from_rank*8+file) 
+                || self.piece_on(square) != NoPiece || self.piece_on(origin_rank*8+file) != NoPiece {
+            return Err(FenError::EnPassantInconsistent(s.to_string()));
         }
 
+        //only save en passant square if there is a pseudo legal en passant move
+
         //check if there are own pawns next to the enemy pawn
         let mut from_squares = [-1,1].into_iter().map(|offset| offset + file as i32).filter(|x| *x <= 7 && *x >= 0).map(|x| x as u8 + 8*from_rank);
         let pawn_on_from_square = from_squares.any(|x| self.squares[x as usize] == Pawn && Some(self.current_player) == self.square_color(x));
@@ -337,23 +562,21 @@ impl Position {
         Ok(())
     }
 
-    pub fn parse_square(s: &str) -> Result<u8, ()> {
+    pub fn parse_square(s: &str) -> Result<u8, SquareError> {
 
         let mut str_iter = s.chars();
 
-        let first_char = str_iter.next().ok_or(())?;
-        let second_char = str_iter.next().ok_or(())?;
-        
-        if str_iter.next().is_some() {
-            return Err(());
-        }
+        let (first_char, second_char) = match (str_iter.next(), str_iter.next(), str_iter.next()) {
+            (Some(f), Some(r), None) => (f, r),
+            _ => return Err(SquareError::WrongLength(s.to_string()))
+        };
 
         if !"abcdefgh".contains(first_char) {
-            return Err(());
+            return Err(SquareError::InvalidFile(first_char));
         }
 
         if !"12345678".contains(second_char) {
-            return Err(());
+            return Err(SquareError::InvalidRank(second_char));
         }     
 
         let file = first_char.to_digit(18).unwrap()-10;
@@ -362,6 +585,81 @@ impl Position {
         Ok((8*rank+file) as u8)
     }
 
+    pub fn to_fen_string(&self) -> String {
+        let mut fen = String::new();
+
+        for row in 0..8 {
+            let mut empty_squares = 0;
+
+            for column in 0..8 {
+                let square = 8*row + column;
+                let piece_char = match self.piece_on(square) {
+                    Pawn => 'p',
+                    Knight => 'n',
+                    Bishop => 'b',
+                    Rook => 'r',
+                    Queen => 'q',
+                    King => 'k',
+                    NoPiece => {
+                        empty_squares += 1;
+                        continue;
+                    }
+                };
+
+                if empty_squares > 0 {
+                    fen.push_str(&empty_squares.to_string());
+                    empty_squares = 0;
+                }
+
+                if self.square_color(square) == Some(White) {
+                    fen.push(piece_char.to_ascii_uppercase());
+                } else {
+                    fen.push(piece_char);
+                }
+            }
+
+            if empty_squares > 0 {
+                fen.push_str(&empty_squares.to_string());
+            }
+            if row < 7 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.current_player == White { " w " } else { " b " });
+
+        let castling_rights = self.stack_frame().castling_rights;
+        if castling_rights == 0 {
+            fen.push('-');
+        }
+        for (i, c) in ['K', 'Q', 'k', 'q'].into_iter().enumerate() {
+            if castling_rights & (1 << i) != 0 {
+                fen.push(c);
+            }
+        }
+
+        //the en passant square is only stored if an en passant capture is pseudo legal
+        match self.stack_frame().en_passant_file {
+            Some(file) => {
+                let rank = if self.current_player == White { 2 } else { 5 };
+                fen.push(' ');
+                fen.push_str(&Self::square_to_string(8*rank + file));
+            },
+            None => fen.push_str(" -")
+        }
+
+        fen.push_str(&format!(" {} {}", self.half_move_clock(), self.full_move_number()));
+
+        fen
+    }
+
+    pub fn full_move_number(&self) -> u32 {
+        let plies = (self.stack.len() - 1) as u32;
+        //the full move number increases after each move of black
+        let initial_player_is_black = (self.current_player == Black) == plies.is_multiple_of(2);
+        self.initial_full_move_number + (plies + initial_player_is_black as u32) / 2
+    }
+
     pub fn square_to_string(square: u8) -> String {
         let mut res = String::from(char::from_digit((square%8+10) as u32, 18).unwrap());
         res.push(char::from_digit((7-square/8+1) as u32, 9).unwrap());
@@ -373,7 +671,7 @@ impl Position {
      */
 
     const CASTLE_ROOK_FROM: [u8; 4] = [63, 56, 7, 0];
-    const CASTLE_ROOK_TO: [u8; 4] = [61 , 59, 5, 3];
+    pub const CASTLE_ROOK_TO: [u8; 4] = [61 , 59, 5, 3];
 
     fn remove_piece<const UPDATE_HASH: bool>(&mut self, piece: Piece, player: Color, square: u8) {
         self.squares[square as usize] = NoPiece;
@@ -381,6 +679,15 @@ impl Position {
         self.color_bb[player as usize] &= !Bitboard::from_square(square);
         if UPDATE_HASH {
             self.mut_stack_frame().hash ^= Self::ZOBRIST_PIECES[player as usize][piece as usize][square as usize];
+            if piece == Pawn {
+                self.mut_stack_frame().pawn_hash ^= Self::ZOBRIST_PIECES[player as usize][Pawn as usize][square as usize];
+            }
+
+            let value = piece_value(piece, player, square);
+            let frame = self.mut_stack_frame();
+            frame.psqt.0 -= value.0;
+            frame.psqt.1 -= value.1;
+            frame.game_phase -= phase_value(piece);
         }
     }
 
@@ -390,6 +697,15 @@ impl Position {
         self.color_bb[player as usize] |= Bitboard::from_square(square);
         if UPDATE_HASH {
             self.mut_stack_frame().hash ^= Self::ZOBRIST_PIECES[player as usize][piece as usize][square as usize];
+            if piece == Pawn {
+                self.mut_stack_frame().pawn_hash ^= Self::ZOBRIST_PIECES[player as usize][Pawn as usize][square as usize];
+            }
+
+            let value = piece_value(piece, player, square);
+            let frame = self.mut_stack_frame();
+            frame.psqt.0 += value.0;
+            frame.psqt.1 += value.1;
+            frame.game_phase += phase_value(piece);
         }
     }
 
@@ -404,7 +720,10 @@ impl Position {
             half_move_clock: self.stack_frame().half_move_clock + 1,
             captured_piece,
             pinned: Bitboard::new(),
-            hash: self.stack_frame().hash
+            hash: self.stack_frame().hash,
+            pawn_hash: self.stack_frame().pawn_hash,
+            psqt: self.stack_frame().psqt,
+            game_phase: self.stack_frame().game_phase
         });
 
         if captured_piece != NoPiece {
@@ -537,12 +856,21 @@ impl Position {
      * move generation
      */
 
-    pub fn legal_moves(&mut self) -> Vec<Move> {
-        self.pseudo_legal_moves().into_iter().filter(|m| self.is_legal(*m)).collect()
+    pub fn legal_moves(&mut self) -> MoveList {
+        let mut moves = self.pseudo_legal_moves();
+        moves.retain(|m| self.is_legal(m));
+        moves
+    }
+
+    /**
+     * convenience wrapper around legal_moves for callers that want an owned, growable vector.
+     */
+    pub fn legal_moves_vec(&mut self) -> Vec<Move> {
+        self.legal_moves().to_vec()
     }
 
-    fn pseudo_legal_moves(&self) -> Vec<Move> {
-        let mut moves = Vec::with_capacity(50);
+    fn pseudo_legal_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
 
         let target_squares = self.check_blocking_squares(self.current_player);
 
@@ -786,7 +1114,7 @@ impl Position {
         blocking_squares
     }
 
-    fn generate_pawn_moves(moves: &mut Vec<Move>, targets: Bitboard, offset: i8) {
+    fn generate_pawn_moves(moves: &mut MoveList, targets: Bitboard, offset: i8) {
         for to in targets & !(Bitboard::rank(0) | Bitboard::rank(7)) {
             moves.push(Move::new((to as i8 - offset) as u8, to));
         }
@@ -802,9 +1130,13 @@ impl Position {
     }
     
     /*
-     * draw detection
+     * draw detection. The stack holds the positions of the whole game, so repetitions of positions
+     * before the search root are found as well. The protocol handlers make the moves of the game on
+     * the position, e.g. the moves of 'position ... moves', and the search works on a clone of it.
+     * Positions before the last capture or pawn move can't repeat, so no separate game history is kept.
      */
 
+    //a position that occurred before inside the search (within ply plies), or twice before in the game, is a draw
     pub fn has_repetition(&self, ply: u16) -> bool {
         
         let mut repetitions = 0;
@@ -829,6 +1161,60 @@ impl Position {
         false
     }
 
+    /**
+     * true if the side to move can repeat a position with a single reversible move. Like for
+     * has_repetition, the repeated position has to be within the last ply plies, or it has to have
+     * occurred twice in the game already. The search can score such positions as a draw before the
+     * repetition is on the board.
+     */
+    pub fn has_upcoming_repetition(&self, ply: u16) -> bool {
+        let end = (self.half_move_clock() as usize).min(self.stack.len() - 1);
+        if end < 3 {
+            return false;
+        }
+
+        let hash = |i: usize| self.stack[self.stack.len() - 1 - i].hash;
+        let side = Self::ZOBRIST_CURRENT_PLAYER[White as usize] ^ Self::ZOBRIST_CURRENT_PLAYER[Black as usize];
+
+        //the hash difference of the moves since the position i plies ago, which is 0 if all of them were undone
+        let mut other = hash(0) ^ hash(1) ^ side;
+
+        for i in (3..=end).step_by(2) {
+            other ^= hash(i - 1) ^ hash(i) ^ side;
+            if other != 0 {
+                continue;
+            }
+
+            let Some((s1, s2)) = cuckoo::lookup(hash(0) ^ hash(i)) else {
+                continue;
+            };
+
+            let path = Bitboard::in_between(s1, s2) & !Bitboard::from_square(s1) & !Bitboard::from_square(s2);
+            if !(path & self.occupied()).is_empty() {
+                continue;
+            }
+
+            if i < ply as usize {
+                return true;
+            }
+
+            //both directions of the move have the same key. Only the one of the side to move goes back to the earlier position.
+            let from = if self.piece_on(s1) == NoPiece { s2 } else { s1 };
+            if self.square_color(from) == Some(self.current_player) && self.repeats_earlier(self.stack.len() - 1 - i) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    //true if the position at the given index of the stack occurred before in the game
+    fn repeats_earlier(&self, index: usize) -> bool {
+        let frame = &self.stack[index];
+        (2..=frame.half_move_clock as usize).step_by(2)
+            .take_while(|i| *i <= index)
+            .any(|i| self.stack[index - i].hash == frame.hash)
+    }
      
     pub fn insufficient_material(&self) -> bool {
         let major_pieces = (self.pieces_by_type(Rook) | self.pieces_by_type(Queen)).count_squares();
@@ -851,6 +1237,25 @@ impl Position {
         false
     }
 
+    //returns how the game ended, if the current position ends the game under the FIDE rules (threefold repetition and 50 moves are treated as claimed draws)
+    pub fn outcome(&mut self) -> Option<GameOutcome> {
+        if self.legal_moves().is_empty() {
+            if self.in_check() {
+                Some(GameOutcome::Checkmate { winner: !self.current_player })
+            } else {
+                Some(GameOutcome::Stalemate)
+            }
+        } else if self.insufficient_material() {
+            Some(GameOutcome::InsufficientMaterial)
+        } else if self.half_move_clock() >= 100 {
+            Some(GameOutcome::FiftyMoveRule)
+        } else if self.has_repetition(0) {
+            Some(GameOutcome::Repetition)
+        } else {
+            None
+        }
+    }
+
 
     /*
      * zobrist hash
@@ -908,6 +1313,33 @@ impl Position {
     const ZOBRIST_EN_PASSANT: [u64; 8] = Self::random_array(64*6*2+16);
     const ZOBRIST_CURRENT_PLAYER: [u64; 2] = Self::random_array(64*6*2+16+8);
 
+    fn calculate_psqt(&self) -> ((i32, i32), u32) {
+        let mut psqt = (0, 0);
+        let mut game_phase = 0;
+
+        for s in self.occupied() {
+            let piece = self.piece_on(s);
+            let value = piece_value(piece, self.square_color(s).unwrap(), s);
+            psqt.0 += value.0;
+            psqt.1 += value.1;
+            game_phase += phase_value(piece);
+        }
+
+        (psqt, game_phase)
+    }
+
+    fn calculate_pawn_hash(&self) -> u64 {
+        let mut hash = 0;
+
+        for p in [Black, White] {
+            for s in self.pieces(Pawn, p) {
+                hash ^= Self::ZOBRIST_PIECES[p as usize][Pawn as usize][s as usize];
+            }
+        }
+
+        hash
+    }
+
     fn calculate_hash(&self) -> u64 {
 
         let mut hash = 0;
@@ -934,31 +1366,9 @@ impl Position {
      * perft function
      */
 
-    pub fn perft(&mut self, depth: u32) -> u64 {
-        self.perft_with_hash_map(depth, &mut HashMap::new())
-    }
-
-    pub fn perft_with_hash_map(&mut self, depth: u32, hash_map: &mut HashMap<(u64, u32), u64>) -> u64 {
-        if depth == 0 {
-            1
-        } else if depth == 1 {
-            self.legal_moves().len() as u64
-        } else {
-            if let Some(res) = hash_map.get(&(self.hash(), depth)) {
-                return *res;
-            }
-
-            let mut result = 0;
-            for m in self.legal_moves().into_iter() {
-                self.make_move(m);
-                result += self.perft_with_hash_map(depth-1,hash_map);
-                self.unmake_move(m);
-            }
-
-            hash_map.insert((self.hash(), depth), result);
-    
-            result
-        }
+    //the table can be reused for several positions, its entries are keyed by the hash
+    pub fn perft(&mut self, depth: u32, table: &PerftTable) -> u64 {
+        perft::perft(self, depth, table)
     }
 }
 
@@ -986,9 +1396,10 @@ mod test {
 
     #[test]
     fn perft_small() {
+        let table = PerftTable::new(1);
         for (i, &fen) in PERFT_POSITIONS.iter().enumerate() {
             for depth in 1..5 {
-                assert_eq!(PERFT_RESULTS[i][(depth-1) as usize], Position::from_fen_string(fen).unwrap().perft(depth));
+                assert_eq!(PERFT_RESULTS[i][(depth-1) as usize], Position::from_fen_string(fen).unwrap().perft(depth, &table));
             }
         }
     }
@@ -996,15 +1407,98 @@ mod test {
     #[test]
     #[ignore]
     fn perft_full() {
+        let table = PerftTable::new(PerftTable::DEFAULT_MB_SIZE);
         for (i, &fen) in PERFT_POSITIONS.iter().enumerate() {
             for depth in 1..PERFT_RESULTS[i].len()+1 {
-                assert_eq!(PERFT_RESULTS[i][depth-1], Position::from_fen_string(fen).unwrap().perft(depth as u32));
+                assert_eq!(PERFT_RESULTS[i][depth-1], Position::from_fen_string(fen).unwrap().perft(depth as u32, &table));
+            }
+        }
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in PERFT_POSITIONS.iter().chain(["8/8/8/2k5/2pP4/8/B7/4K3 b - d3 0 3", "4k3/8/8/8/8/8/8/4K2R w K - 99 120"].iter()) {
+            assert_eq!(*fen, Position::from_fen_string(fen).unwrap().to_fen_string());
+        }
+
+        let mut pos = Position::from_fen_string(PERFT_POSITIONS[4]).unwrap();
+        for m in ["c5c4", "b3c4", "b5c4"] {
+            let m = Move::from_string(m, &mut pos).unwrap();
+            pos.make_move(m);
+        }
+        assert_eq!(pos.to_fen_string(), "r2q1rk1/pP1p2pp/Q4n2/b3p3/Npb5/5NBn/pPPP1PPP/R3K2R w KQ - 0 3");
+    }
+
+    #[test]
+    fn fen_errors() {
+        let cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1", FenError::WrongNumberOfRanks(7)),
+            ("rnbqkbnr/pppppppp/4p4/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::WrongRankLength(6)),
+            ("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::InvalidPieceChar('x')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1", FenError::InvalidSideToMove("x".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQqk - 0 1", FenError::InvalidCastling("KQqk".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1", FenError::CastlingWithoutKingOrRook('K')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1", FenError::EnPassantInconsistent("e3".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e4 0 1", FenError::EnPassantInconsistent("e4".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq z9 0 1", FenError::InvalidEnPassantSquare("z9".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1", FenError::InvalidNumber(FenField::HalfMoveClock, "x".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0", FenError::MissingField(FenField::FullMoveNumber)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 x", FenError::TrailingCharacters("x".to_string())),
+            ("rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1", FenError::MissingKing(Black)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBKKBNR w kq - 0 1", FenError::TooManyKings(White)),
+            ("rnbqkbnP/pppppppp/8/8/8/8/PPPPPPP1/RNBQKBNR w KQq - 0 1", FenError::PawnOnBackRank(7)),
+            ("4k2R/8/8/8/8/8/8/4K3 w - - 0 1", FenError::SideNotToMoveInCheck),
+        ];
+
+        for (fen, error) in cases {
+            assert_eq!(Position::from_fen_string(fen).unwrap_err(), error, "{fen}");
+        }
+
+        assert!(Position::from_fen_string("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").is_ok());
+    }
+
+    #[test]
+    fn repetitions() {
+        let play = |moves: &str| {
+            let mut pos = Position::new();
+            for m in moves.split_whitespace() {
+                let m = Move::from_string(m, &mut pos).unwrap();
+                pos.make_move(m);
             }
+            pos
+        };
+
+        //black can go back to the start position, which is inside the search only if it is more than 3 plies deep
+        let pos = play("g1f3 g8f6 f3g1");
+        assert!(pos.has_upcoming_repetition(4));
+        assert!(!pos.has_upcoming_repetition(3));
+        assert!(!pos.has_repetition(4));
+
+        //the start position occurred twice, so going back to it is a draw anywhere
+        let pos = play("g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1");
+        assert!(pos.has_upcoming_repetition(0));
+        let pos = play("g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8");
+        assert!(pos.has_repetition(0));
+
+        //white moved two different knights, one move can't undo that
+        let pos = play("g1f3 g8f6 b1c3");
+        assert!(!pos.has_upcoming_repetition(10));
+
+        //the rook went around the pawn and can't go back to a1 directly
+        for (fen, expected) in [("4k3/8/8/8/8/8/8/R3K3 b - - 0 1", true), ("4k3/8/8/8/8/P7/8/R3K3 b - - 0 1", false)] {
+            let mut pos = Position::from_fen_string(fen).unwrap();
+            for m in ["e8d8", "a1b1", "d8c8", "b1b5", "c8d8", "b5a5", "d8e8"] {
+                let m = Move::from_string(m, &mut pos).unwrap();
+                pos.make_move(m);
+            }
+            assert_eq!(pos.has_upcoming_repetition(8), expected, "{fen}");
         }
     }
 
     fn hash_test_rec(pos: &mut Position, depth: u32) {
         assert_eq!(pos.hash(), pos.calculate_hash(), "\n{:#?}", pos);
+        assert_eq!(pos.pawn_hash(), pos.calculate_pawn_hash(), "\n{:#?}", pos);
+        assert_eq!((pos.psqt(), pos.game_phase()), pos.calculate_psqt(), "\n{:#?}", pos);
         if depth > 0 {
             for m in pos.legal_moves() {
                 pos.make_move(m);
@@ -1028,4 +1522,4 @@ mod test {
             hash_test_rec(&mut Position::from_fen_string(fen).unwrap(), 5);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use crate::bitboard::Bitboard;
+
+use super::{Position, Color::*, Piece::{self, *}};
+
+/*
+ * cuckoo tables of all reversible moves on an empty board, indexed by the hash difference between the
+ * positions before and after the move. They detect whether a move leads back to an earlier position,
+ * see Position::has_upcoming_repetition. The idea is from Marcel van Kervinck and is used by Stockfish.
+ */
+
+const SIZE: usize = 8192;
+
+struct CuckooTables {
+    keys: Vec<u64>,
+    moves: Vec<(u8, u8)>
+}
+
+static TABLES: OnceLock<CuckooTables> = OnceLock::new();
+
+const fn h1(key: u64) -> usize {
+    (key & (SIZE as u64 - 1)) as usize
+}
+
+const fn h2(key: u64) -> usize {
+    ((key >> 16) & (SIZE as u64 - 1)) as usize
+}
+
+fn attacks(piece: Piece, square: u8) -> Bitboard {
+    match piece {
+        Knight => Bitboard::knight_attacks(square),
+        Bishop => Bitboard::bishop_attacks(square, Bitboard::new()),
+        Rook => Bitboard::rook_attacks(square, Bitboard::new()),
+        Queen => Bitboard::bishop_attacks(square, Bitboard::new()) | Bitboard::rook_attacks(square, Bitboard::new()),
+        King => Bitboard::king_attacks(square),
+        _ => Bitboard::new()
+    }
+}
+
+fn generate() -> CuckooTables {
+    let mut tables = CuckooTables {
+        keys: vec![0; SIZE],
+        moves: vec![(0, 0); SIZE]
+    };
+
+    let side = Position::ZOBRIST_CURRENT_PLAYER[White as usize] ^ Position::ZOBRIST_CURRENT_PLAYER[Black as usize];
+
+    for player in [White, Black] {
+        for piece in [Knight, Bishop, Rook, Queen, King] {
+            let zobrist = &Position::ZOBRIST_PIECES[player as usize][piece as usize];
+
+            for s1 in 0..64 {
+                for s2 in attacks(piece, s1).into_iter().filter(|s2| *s2 > s1) {
+                    let mut key = zobrist[s1 as usize] ^ zobrist[s2 as usize] ^ side;
+                    let mut m = (s1, s2);
+
+                    //insert the move, and move the entries it replaces to their other slot until a free one is found
+                    let mut i = h1(key);
+                    loop {
+                        std::mem::swap(&mut tables.keys[i], &mut key);
+                        std::mem::swap(&mut tables.moves[i], &mut m);
+                        if key == 0 {
+                            break;
+                        }
+                        i = if i == h1(key) { h2(key) } else { h1(key) };
+                    }
+                }
+            }
+        }
+    }
+
+    tables
+}
+
+//the squares of the reversible move that changes the hash by key, if there is one. Both directions of a move have the same key.
+pub fn lookup(key: u64) -> Option<(u8, u8)> {
+    let tables = TABLES.get_or_init(generate);
+
+    [h1(key), h2(key)].into_iter()
+        .find(|i| tables.keys[*i] == key)
+        .map(|i| tables.moves[i])
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn all_moves_inserted() {
+        let tables = generate();
+        assert_eq!(tables.keys.iter().filter(|k| **k != 0).count(), 3668);
+
+        let side = Position::ZOBRIST_CURRENT_PLAYER[White as usize] ^ Position::ZOBRIST_CURRENT_PLAYER[Black as usize];
+        let knight = &Position::ZOBRIST_PIECES[Black as usize][Knight as usize];
+        assert_eq!(lookup(knight[1] ^ knight[18] ^ side), Some((1, 18)));
+        assert_eq!(lookup(knight[1] ^ knight[2] ^ side), None);
+    }
+}
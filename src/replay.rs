@@ -0,0 +1,163 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/*
+ * sessions recorded in a log file, see log.rs. A replay feeds the input of a session back into the
+ * uci handler, see uci::replay. The log is appended to, so only the last session in the file is
+ * replayed. A session that was logged from the 'Debug Log File' option starts with that command, so
+ * options that were set before are missing.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub line: String,
+    //the nodes a go command searched in the recorded session, from the note after its best move. Logs
+    //without the note have the nodes of the last info before the best move, which can be a few less.
+    pub nodes: Option<u64>
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub commands: Vec<Command>,
+    //the last best move that was sent, like 'bestmove e2e4 ponder e7e5'
+    pub best_move: Option<String>
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Session, ReplayError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Session, ReplayError> {
+        let mut session = Session::default();
+
+        //the go command that waits for its best move, and the nodes it searched so far
+        let mut search: Option<usize> = None;
+        let mut nodes = None;
+        //the go command whose best move was the last line, the note with its nodes follows
+        let mut finished: Option<usize> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            if line.starts_with('#') {
+                session = Session::default();
+                (search, finished) = (None, None);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut fields = line.splitn(3, ' ');
+            let (Some(time), Some(direction)) = (fields.next(), fields.next()) else {
+                return Err(ReplayError::Syntax(i + 1));
+            };
+            if time.parse::<u64>().is_err() {
+                return Err(ReplayError::Syntax(i + 1));
+            }
+            let text = fields.next().unwrap_or_default();
+
+            let best_move = finished.take();
+            match direction {
+                ">>" => {
+                    let mut tokens = text.split_whitespace();
+                    if tokens.next() == Some("go") && tokens.next() != Some("perft") {
+                        search = Some(session.commands.len());
+                        nodes = None;
+                    }
+                    session.commands.push(Command { line: text.to_string(), nodes: None });
+                },
+                "<<" => {
+                    let mut tokens = text.split_whitespace();
+                    match tokens.next() {
+                        Some("info") => {
+                            if let Some(n) = tokens.skip_while(|t| *t != "nodes").nth(1).and_then(|n| n.parse().ok()) {
+                                nodes = Some(n);
+                            }
+                        },
+                        Some("bestmove") => {
+                            if let Some(index) = search.take() {
+                                session.commands[index].nodes = nodes;
+                                finished = Some(index);
+                            }
+                            session.best_move = Some(text.to_string());
+                        },
+                        _ => ()
+                    }
+                },
+                "--" => {
+                    let mut tokens = text.split_whitespace();
+                    if let (Some(index), Some("nodes"), Some(n)) = (best_move, tokens.next(), tokens.next().and_then(|n| n.parse().ok())) {
+                        session.commands[index].nodes = Some(n);
+                    }
+                },
+                _ => return Err(ReplayError::Syntax(i + 1))
+            }
+        }
+
+        Ok(session)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Syntax(usize)
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{e}"),
+            ReplayError::Syntax(line) => write!(f, "line {line}: expected time, '>>', '<<' or '--' and the logged line")
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_log() {
+        let log = "\
+# log started at unix time 1000
+0 >> uci
+5 << uciok
+9 >> go movetime 100
+110 << bestmove d2d4
+# log started at unix time 2000
+0 >> position startpos moves e2e4
+1 >> go wtime 1000 btime 1000
+20 << info depth 1 seldepth 1 score cp 20 nodes 30 time 1 pv e7e5
+500 << info nodes 12345 nps 24000 hashfull 3 time 500
+510 << bestmove e7e5 ponder g1f3
+510 -- nodes 12399
+511 >> go perft 2
+512 << e2e4: 20
+513 >> go depth 1
+514 << info depth 1 seldepth 1 score cp 20 nodes 21 time 1 pv e7e5
+514 << bestmove e7e5
+515 -- nodes 80
+";
+        let session = Session::parse(log).unwrap();
+        assert_eq!(session.commands.iter().map(|c| c.line.as_str()).collect::<Vec<_>>(),
+            ["position startpos moves e2e4", "go wtime 1000 btime 1000", "go perft 2", "go depth 1"]);
+        assert_eq!(session.commands.iter().map(|c| c.nodes).collect::<Vec<_>>(), [None, Some(12399), None, Some(80)]);
+
+        //logs without the notes have the nodes of the last info
+        let session = Session::parse(&log.replace("-- nodes", "-- other")).unwrap();
+        assert_eq!(session.commands.iter().map(|c| c.nodes).collect::<Vec<_>>(), [None, Some(12345), None, Some(21)]);
+        assert_eq!(session.best_move.as_deref(), Some("bestmove e7e5"));
+
+        assert!(matches!(Session::parse("0 >> uci\nuciok\n"), Err(ReplayError::Syntax(2))));
+    }
+}
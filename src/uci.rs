@@ -1,82 +1,421 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::io;
 use std::num::IntErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
 use crate::position::*;
 use crate::chess_move::*;
 use crate::engine::*;
+use crate::engine::strength::{self, MAX_SKILL_LEVEL, MIN_ELO, MAX_ELO};
+use crate::engine::tablebase::Tablebases;
+use crate::engine::book::Book;
+use crate::bench;
+use crate::config::{Config, ConfigError};
+use crate::log::{self, send};
+use crate::perft::{self, PerftTable};
+use crate::replay::Session;
+
+pub const NAME: &str = "Carlito Chess Engine";
+pub const AUTHOR: &str = "Lovis Hagemeyer";
+
+pub const DEFAULT_TTABLE_SIZE: usize = 64;
+pub const MIN_TTABLE_SIZE: usize = 1;
+pub const MAX_TTABLE_SIZE: usize = 4096;
+
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 10;
+pub const MAX_MOVE_OVERHEAD: u64 = 5000;
+
+pub const MIN_CONTEMPT: i16 = -100;
+pub const MAX_CONTEMPT: i16 = 100;
+
+/*
+ * front end for the uci protocol. Input is read on its own thread and handled on the main thread together
+ * with the best moves of the search, so commands are answered while the engine searches. Commands that
+ * change the position, the options or start a search are only accepted while the engine is idle. Slow
+ * commands like 'go perft' run as tasks on another thread, and 'isready' is answered in the meantime.
+ */
+
+pub fn input_loop(config: &Config) -> Result<(), ConfigError> {
+    UciHandler::new(config)?.input_loop();
+    Ok(())
+}
+
+//searches of a replay without a logged node count and without a limit of their own
+pub const DEFAULT_REPLAY_NODES: u64 = 1_000_000;
+
+//the seed of the book and of weakened play in a replay
+const REPLAY_SEED: u64 = 0x5eed;
+
+/**
+ * feeds the input of a recorded session back into a new handler. Searches run to the end before the next
+ * command, limited to the nodes they searched in the session or to the given nodes instead of the clock,
+ * so the replay doesn't depend on timing. The book and weakened play use a fixed seed for the same reason.
+ * 'stop' and 'ponderhit' have nothing left to do. Returns the last best move.
+ */
+pub fn replay(config: &Config, session: &Session, nodes: Option<u64>) -> Result<Option<Move>, ConfigError> {
+    let mut handler = UciHandler::new(config)?;
+    handler.engine.set_random_seed(Some(REPLAY_SEED));
+    let mut best_move = None;
+
+    for command in session.commands.iter() {
+        println!(">> {}", command.line);
+
+        let mut tokens = command.line.split_whitespace();
+        match tokens.next() {
+            Some("go") => {
+                let Some(mut opt) = handler.parse_go_options(&mut tokens) else {
+                    continue;
+                };
+
+                let has_limit = opt.depth.is_some() || opt.nodes.is_some() || opt.mate_in.is_some();
+                opt.nodes = nodes.or(command.nodes).or(opt.nodes).or(Some(DEFAULT_REPLAY_NODES).filter(|_| !has_limit));
+                (opt.ponder, opt.infinite, opt.move_time) = (false, false, None);
+                (opt.wtime, opt.btime, opt.winc, opt.binc, opt.moves_to_go) = (None, None, None, None, None);
+
+                best_move = handler.engine.search_sync(handler.position.clone(), opt).best_move;
+                handler.finish_search();
+            },
+            Some("stop") | Some("ponderhit") => (),
+            //the replay is not logged again
+            Some("setoption") if tokens.clone().map(str::to_ascii_lowercase).take(4).eq(["name", "debug", "log", "file"]) => (),
+            _ => if !handler.execute(&command.line) {
+                break;
+            }
+        }
 
-const NAME: &str = "Carlito Chess Engine";
-const AUTHOR: &str = "Lovis Hagemeyer";
+        handler.wait_for_task();
+    }
 
-const DEFAULT_TTABLE_SIZE: usize = 64;
-const MIN_TTABLE_SIZE: usize = 1;
-const MAX_TTABLE_SIZE: usize = 4096;
+    Ok(best_move)
+}
 
-pub fn input_loop() {
-    UciHandler::new().input_loop();
+enum Event {
+    Line(String),
+    EndOfInput,
+    BestMove(Option<Move>, Option<Move>),
+    //the end of a task, with the transposition table it allocated or loaded
+    TaskFinished(Option<TTable>)
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum State {
+    Idle,
+    Searching,
+    Pondering
+}
 
 struct UciHandler {
     position: Position,
-    engine: Engine
+    engine: Engine,
+    observer: Arc<UciObserver>,
+
+    state: State,
+    sender: mpsc::Sender<Event>,
+    events: mpsc::Receiver<Event>,
+    //input that waits for a task, or for the best move after 'stop'
+    deferred: VecDeque<Event>,
+    //a command that runs on another thread, because it takes a while: allocating or loading the transposition table, 'go perft' and 'bench'
+    task_running: bool,
+
+    skill_level: u8,
+    limit_strength: bool,
+    elo: u32,
+
+    contempt: i16,
+    analyse_mode: bool,
+    move_overhead: u64,
+    ponder: bool,
+
+    //defaults of the options from the configuration
+    hash: usize,
+    threads: usize,
+    tablebase_path: Option<PathBuf>,
+    book_path: Option<PathBuf>,
+    log_path: Option<PathBuf>
+}
+
+//prints the search results in uci format
+struct UciObserver {
+    sender: mpsc::Sender<Event>,
+    //'debug on' turns on 'info string' diagnostics
+    debug: AtomicBool
+}
+
+impl SearchObserver for UciObserver {
+    fn iteration_complete(&self, report: &IterationReport) {
+        let mut line = format!("info depth {} seldepth {}", report.depth, report.seldepth);
+        if let Some(s) = report.score.centi_pawns() {
+            line += &format!(" score cp {s}");
+        } else {
+            line += &format!(" score mate {}", report.score.mate().unwrap());
+        }
+
+        line += &format!(" nodes {}", report.nodes);
+        line += &format!(" time {}", report.time.as_millis());
+        if let Some(nps) = report.nps() {
+            line += &format!(" nps {nps}");
+        }
+        line += &format!(" hashfull {} tbhits {}", report.hashfull, report.tbhits);
+
+        line += " pv";
+        for m in report.pv.iter() {
+            line += &format!(" {m}");
+        }
+
+        send!("{line}");
+    }
+
+    //the handler sends the best move, when it knows that the search is over
+    fn best_move(&self, best_move: Option<Move>, ponder_move: Option<Move>) {
+        //the receiver only disappears when the program is shutting down
+        let _ = self.sender.send(Event::BestMove(best_move, ponder_move));
+    }
+
+    fn current_move(&self, m: Move, move_number: usize, depth: u16) {
+        send!("info depth {depth} currmove {m} currmovenumber {move_number}");
+    }
+
+    fn progress(&self, report: &ProgressReport) {
+        let mut line = format!("info nodes {}", report.nodes);
+        if let Some(nps) = report.nps() {
+            line += &format!(" nps {nps}");
+        }
+        send!("{line} hashfull {} tbhits {} time {}", report.hashfull, report.tbhits, report.time.as_millis());
+    }
+
+    fn debug(&self, message: &str) {
+        if self.debug.load(Ordering::Relaxed) {
+            send!("info string {message}");
+        }
+    }
 }
 
 impl UciHandler {
-    pub fn new() -> UciHandler {
-        UciHandler {
+    pub fn new(config: &Config) -> Result<UciHandler, ConfigError> {
+        let (sender, events) = mpsc::channel();
+
+        let observer = Arc::new(UciObserver {
+            sender: sender.clone(),
+            debug: AtomicBool::new(false)
+        });
+
+        Ok(UciHandler {
             position: Position::new(),
-            engine: Engine::new(DEFAULT_TTABLE_SIZE)
-        }
+            engine: config.create_engine(observer.clone())?,
+            observer,
+            state: State::Idle,
+            sender,
+            events,
+            deferred: VecDeque::new(),
+            task_running: false,
+            skill_level: MAX_SKILL_LEVEL,
+            limit_strength: false,
+            elo: MAX_ELO,
+            contempt: 0,
+            analyse_mode: false,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            ponder: true,
+            hash: config.hash,
+            threads: config.threads,
+            tablebase_path: config.tablebases.clone(),
+            book_path: config.book.clone(),
+            log_path: config.log_file.clone()
+        })
     }
 
     pub fn input_loop(&mut self) {
         self.setup();
 
-        for line in io::stdin().lines().map(|r| r.expect("error reading stdin")) {
-            let mut tokens = line.split_whitespace();
-
-            match tokens.next() {
-                Some("setoption") => self.parse_set_option(&mut tokens),
-                Some("isready") => println!("readyok"),
-                Some("position") => self.parse_position(&mut tokens),
-                Some("go") => self.parse_go(&mut tokens),
-                Some("stop") => {
-                    if tokens.next().is_some() {
-                        eprintln!("invalid arguments for stop command");
+        let input_sender = self.sender.clone();
+        thread::spawn(move || {
+            for line in io::stdin().lines().map(|r| r.expect("error reading stdin")) {
+                log::input(&line);
+                if input_sender.send(Event::Line(line)).is_err() {
+                    return;
+                }
+            }
+            let _ = input_sender.send(Event::EndOfInput);
+        });
+
+        while let Some(event) = self.next_event() {
+            match event {
+                Event::Line(line) => {
+                    //while a task runs, only 'isready' is answered right away. The other commands wait in order.
+                    let command = line.split_whitespace().next();
+                    if self.task_running && !matches!(command, Some("isready") | Some("quit")) {
+                        self.deferred.push_back(Event::Line(line));
+                    } else if !self.execute(&line) {
+                        break;
                     }
-                    self.engine.stop();
                 },
-                Some("ponderhit") => {
-                    if tokens.next().is_some() {
-                        eprintln!("invalid arguments for stop command");
+                Event::BestMove(m, ponder_move) => self.on_best_move(m, ponder_move),
+                Event::TaskFinished(table) => self.on_task_finished(table),
+                Event::EndOfInput => {
+                    //like 'stop', a running search still sends its best move
+                    if self.state != State::Idle {
+                        self.engine.stop();
+                        self.finish_search();
                     }
-                    self.engine.ponderhit();
+                    break;
+                }
+            }
+        }
+
+        self.engine.stop();
+    }
+
+    fn next_event(&mut self) -> Option<Event> {
+        if !self.task_running {
+            if let Some(event) = self.deferred.pop_front() {
+                return Some(event);
+            }
+        }
+        self.events.recv().ok()
+    }
+
+    fn on_best_move(&mut self, best_move: Option<Move>, ponder_move: Option<Move>) {
+        //the search has finished, this only joins the search thread
+        let result = self.engine.stop();
+        self.state = State::Idle;
+
+        let mut line = match best_move {
+            Some(m) => format!("bestmove {m}"),
+            None => "bestmove 0000".to_string()
+        };
+        if let Some(m) = ponder_move {
+            line += &format!(" ponder {m}");
+        }
+
+        //a replay searches exactly as many nodes, see replay.rs
+        match result {
+            Some(result) => log::send_line_with_note(&line, &format!("nodes {}", result.nodes)),
+            None => send!("{line}")
+        }
+    }
+
+    fn on_task_finished(&mut self, table: Option<TTable>) {
+        if let Some(table) = table {
+            self.engine.set_table(table);
+        }
+        self.task_running = false;
+    }
+
+    //runs the task on another thread. The task sends its own output.
+    fn start_task<F: FnOnce() -> Option<TTable> + Send + 'static>(&mut self, task: F) {
+        self.task_running = true;
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let _ = sender.send(Event::TaskFinished(task()));
+        });
+    }
+
+    //sends the best move of a search that was stopped or has finished. Input that arrives in the meantime is handled afterwards.
+    fn finish_search(&mut self) {
+        while let Ok(event) = self.events.recv() {
+            match event {
+                Event::BestMove(m, ponder_move) => {
+                    self.on_best_move(m, ponder_move);
+                    return;
                 },
-                Some("ucinewgame") => { self.engine.clear_table() }
-                Some("quit") => return,
-                Some("uci") => (),
-                Some(s) => eprintln!("unknown command: {s}"),
-                None => ()
+                Event::TaskFinished(table) => self.on_task_finished(table),
+                event => self.deferred.push_back(event)
             }
         }
     }
 
-    fn setup(&mut self) {
-        for line in io::stdin().lines().map(|r| r.expect("error reading stdin")) {
-            if line == "uci" {
-                break;
-            }   
+    fn wait_for_task(&mut self) {
+        while self.task_running {
+            match self.events.recv() {
+                Ok(Event::TaskFinished(table)) => self.on_task_finished(table),
+                Ok(event) => self.deferred.push_back(event),
+                Err(_) => return
+            }
         }
+    }
 
-        println!("id name {NAME}");
-        println!("id author {AUTHOR}");
+    //handles a line of input, returns false on 'quit'
+    pub(crate) fn execute(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
 
-        println!("option name Hash type spin default {DEFAULT_TTABLE_SIZE} min {MIN_TTABLE_SIZE} max {MAX_TTABLE_SIZE}");
-        println!("option name Ponder type check default true");
+        match tokens.next() {
+            //commands that change the position, the options or the table are rejected during a search
+            Some(command @ ("setoption" | "position" | "go" | "ucinewgame" | "hash" | "bench")) if self.state != State::Idle => {
+                send!("info string error: '{command}' during a search, send 'stop' first");
+            },
+            Some("setoption") => self.parse_set_option(&mut tokens),
+            Some("isready") => send!("readyok"),
+            Some("position") => self.parse_position(&mut tokens),
+            Some("go") => self.parse_go(&mut tokens),
+            Some("stop") => {
+                if tokens.next().is_some() {
+                    eprintln!("invalid arguments for stop command");
+                }
+                if self.state == State::Idle {
+                    send!("info string error: 'stop' without a search");
+                } else {
+                    self.engine.stop();
+                    self.finish_search();
+                }
+            },
+            Some("ponderhit") => {
+                if tokens.next().is_some() {
+                    eprintln!("invalid arguments for ponderhit command");
+                }
+                if self.state == State::Pondering {
+                    self.engine.ponderhit();
+                    self.state = State::Searching;
+                } else {
+                    send!("info string error: 'ponderhit' without 'go ponder'");
+                }
+            },
+            Some("ucinewgame") => self.engine.clear_table(),
+            Some("hash") => self.parse_hash(&mut tokens),
+            Some("debug") => {
+                match tokens.next() {
+                    Some("on") => self.observer.debug.store(true, Ordering::Relaxed),
+                    Some("off") => self.observer.debug.store(false, Ordering::Relaxed),
+                    _ => eprintln!("expected 'on' or 'off' after 'debug'")
+                }
+            },
+            Some("bench") => {
+                let depth = if tokens.clone().next().is_some() { Self::parse_int_arg(&mut tokens, "bench") } else { Some(4) };
+                if let Some(depth) = depth {
+                    self.start_task(move || {
+                        bench::slider_attacks_bench(depth.clamp(0, u32::MAX as u64) as u32);
+                        None
+                    });
+                }
+            },
+            Some("quit") => return false,
+            Some("uci") => (),
+            Some(s) => eprintln!("unknown command: {s}"),
+            None => ()
+        }
 
-        println!("uciok");
+        true
+    }
+
+    //the 'uci' command has already been read when the protocol was selected
+    fn setup(&mut self) {
+        send!("id name {NAME}");
+        send!("id author {AUTHOR}");
+
+        send!("option name Hash type spin default {} min {MIN_TTABLE_SIZE} max {MAX_TTABLE_SIZE}", self.hash);
+        send!("option name Ponder type check default true");
+        send!("option name Skill Level type spin default {MAX_SKILL_LEVEL} min 0 max {MAX_SKILL_LEVEL}");
+        send!("option name UCI_LimitStrength type check default false");
+        send!("option name UCI_Elo type spin default {MAX_ELO} min {MIN_ELO} max {MAX_ELO}");
+        send!("option name Contempt type spin default 0 min {MIN_CONTEMPT} max {MAX_CONTEMPT}");
+        send!("option name UCI_AnalyseMode type check default false");
+        send!("option name Move Overhead type spin default {DEFAULT_MOVE_OVERHEAD} min 0 max {MAX_MOVE_OVERHEAD}");
+        send!("option name TablebasePath type string default {}", Self::path_option(&self.tablebase_path));
+        send!("option name BookFile type string default {}", Self::path_option(&self.book_path));
+        send!("option name Debug Log File type string default {}", Self::path_option(&self.log_path));
+
+        send!("uciok");
     }
 
     fn parse_set_option<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
@@ -85,28 +424,119 @@ impl UciHandler {
             return;
         }
 
-        match tokens.next().map(|s| s.to_ascii_lowercase()).as_deref() {
-            None => { eprintln!("no arguments for setoption command"); }
-            Some("hash") => { 
-                if tokens.next() != Some("value") {
-                    eprintln!("expected 'value' after 'setoption hash'");
-                    return;
-                }
+        //option names can contain spaces
+        let mut name = Vec::new();
+        for t in tokens.by_ref() {
+            if t == "value" {
+                break;
+            }
+            name.push(t.to_ascii_lowercase());
+        }
 
+        match name.join(" ").as_str() {
+            "" => { eprintln!("no arguments for setoption command"); }
+            "hash" => {
                 if let Some(n) = Self::parse_int_arg(tokens, "value") {
                     if n as usize >= MIN_TTABLE_SIZE && n as usize <= MAX_TTABLE_SIZE {
-                        self.engine.set_table_size(n as usize); 
+                        self.resize_table(n as usize);
                     } else {
                         eprintln!("value out of bounds. Please select a value between {MIN_TTABLE_SIZE} and {MAX_TTABLE_SIZE}");
                     }
                 }
             },
-            Some("ponder") => { },
-            Some(s) => { eprintln!("unsupported options: '{s}'"); }
+            "ponder" => {
+                if let Some(b) = Self::parse_bool_arg(tokens, "Ponder") {
+                    self.ponder = b;
+                }
+            },
+            "skill level" => {
+                if let Some(n) = Self::parse_int_arg(tokens, "value") {
+                    if n <= MAX_SKILL_LEVEL as u64 {
+                        self.skill_level = n as u8;
+                    } else {
+                        eprintln!("value out of bounds. Please select a value between 0 and {MAX_SKILL_LEVEL}");
+                    }
+                }
+            },
+            "uci_limitstrength" => {
+                if let Some(b) = Self::parse_bool_arg(tokens, "UCI_LimitStrength") {
+                    self.limit_strength = b;
+                }
+            },
+            "contempt" => {
+                match tokens.next().and_then(|s| s.parse::<i16>().ok()) {
+                    Some(n) if (MIN_CONTEMPT..=MAX_CONTEMPT).contains(&n) => self.contempt = n,
+                    _ => eprintln!("invalid value. Please select a value between {MIN_CONTEMPT} and {MAX_CONTEMPT}")
+                }
+            },
+            "move overhead" => {
+                if let Some(n) = Self::parse_int_arg(tokens, "value") {
+                    if n <= MAX_MOVE_OVERHEAD {
+                        self.move_overhead = n;
+                    } else {
+                        eprintln!("value out of bounds. Please select a value between 0 and {MAX_MOVE_OVERHEAD}");
+                    }
+                }
+            },
+            "uci_analysemode" => {
+                if let Some(b) = Self::parse_bool_arg(tokens, "UCI_AnalyseMode") {
+                    self.analyse_mode = b;
+                }
+            },
+            "uci_elo" => {
+                if let Some(n) = Self::parse_int_arg(tokens, "value") {
+                    if n >= MIN_ELO as u64 && n <= MAX_ELO as u64 {
+                        self.elo = n as u32;
+                    } else {
+                        eprintln!("value out of bounds. Please select a value between {MIN_ELO} and {MAX_ELO}");
+                    }
+                }
+            },
+            "tablebasepath" => {
+                //paths can contain spaces
+                let path = tokens.collect::<Vec<_>>().join(" ");
+                if path.is_empty() || path == "<empty>" {
+                    self.engine.set_tablebases(Arc::new(Tablebases::new()));
+                } else {
+                    match self.engine.load_tablebases(Path::new(&path)) {
+                        Ok(n) => send!("info string loaded {n} tablebases from '{path}'"),
+                        Err(e) => send!("info string could not load tablebases from '{path}': {e}")
+                    }
+                }
+            },
+            "bookfile" => {
+                let path = tokens.collect::<Vec<_>>().join(" ");
+                if path.is_empty() || path == "<empty>" {
+                    self.engine.set_book(Arc::new(Book::new()));
+                } else {
+                    match self.engine.load_book(Path::new(&path)) {
+                        Ok(n) => send!("info string loaded {n} book positions from '{path}'"),
+                        Err(e) => send!("info string could not load book '{path}': {e}")
+                    }
+                }
+            },
+            "debug log file" => {
+                log::close();
+                let path = tokens.collect::<Vec<_>>().join(" ");
+                if !path.is_empty() && path != "<empty>" {
+                    if let Err(e) = log::open(Path::new(&path)) {
+                        send!("info string could not open log file '{path}': {e}");
+                    }
+                }
+            },
+            s => { eprintln!("unsupported options: '{s}'"); }
         }
     }
 
     fn parse_go<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
+        if let Some(opt) = self.parse_go_options(tokens) {
+            self.state = if opt.ponder { State::Pondering } else { State::Searching };
+            self.engine.start(self.position.clone(), opt);
+        }
+    }
+
+    //the search options of a go command. 'go perft' is run right away and has no options.
+    fn parse_go_options<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) -> Option<EngineOptions> {
         let mut opt = EngineOptions {
             search_moves: Vec::new(),
             ponder: false,
@@ -120,6 +550,11 @@ impl UciHandler {
             nodes: None,
             mate_in: None,
             move_time: None,
+            skill_level: Some(self.skill_level()),
+            contempt: self.contempt,
+            analyse_mode: self.analyse_mode,
+            move_overhead: self.move_overhead,
+            pondering_enabled: self.ponder
         };
 
         let mut search_moves_flag = false;
@@ -144,18 +579,15 @@ impl UciHandler {
                 "mate" => { opt.mate_in = Self::parse_int_arg(tokens, "mate"); search_moves_flag = false; },
                 "movetime" => { opt.move_time = Self::parse_int_arg(tokens, "movetime"); search_moves_flag = false; },
                 "perft" => { 
-                    let depth = Self::parse_int_arg(tokens, "perft");
-                    if let Some(depth) = depth {
-                        Self::split_perft(&mut self.position, depth.clamp(0, u32::MAX as u64) as u32);
-                    }
-                    return;
+                    self.parse_perft(tokens);
+                    return None;
                 }
 
                 arg => {
                     if search_moves_flag { 
                         match Move::from_string(arg, &mut self.position) {
                             Ok(m) => opt.search_moves.push(m),
-                            Err(_) => eprintln!("invalid or illegal move: '{arg}'")
+                            Err(e) => send!("info string invalid searchmoves argument: {e}")
                         }
                     } else {
                         eprintln!("invalid argument for go command: '{arg}'");
@@ -164,7 +596,65 @@ impl UciHandler {
             }
         }
 
-        self.engine.start(self.position.clone(), opt);
+        Some(opt)
+    }
+
+    /*
+     * hash save <file>, hash load <file>
+     * non standard extension to keep the transposition table of long analyses. The file name may contain spaces.
+     */
+    fn parse_hash<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
+        let command = tokens.next();
+        let file = tokens.collect::<Vec<_>>().join(" ");
+        if file.is_empty() {
+            eprintln!("expected a file name after 'hash save' or 'hash load'");
+            return;
+        }
+
+        match command {
+            Some("save") => match self.engine.save_table(Path::new(&file)) {
+                Ok(()) => send!("info string hash saved to '{file}'"),
+                Err(e) => send!("info string could not save hash to '{file}': {e}")
+            },
+            //the old table is kept until the new one is loaded, so it survives a file that can't be loaded
+            Some("load") => self.start_task(move || match TTable::load(Path::new(&file), MAX_TTABLE_SIZE) {
+                Ok(table) => {
+                    send!("info string hash loaded from '{file}'");
+                    Some(table)
+                },
+                Err(e) => {
+                    send!("info string could not load hash from '{file}': {e}");
+                    None
+                }
+            }),
+            _ => eprintln!("expected 'save' or 'load' after 'hash'")
+        }
+    }
+
+    //a large table takes a while to allocate, so it is allocated on another thread. In the meantime the engine answers 'isready'.
+    fn resize_table(&mut self, size_in_mb: usize) {
+        if self.engine.table_size() == Some(size_in_mb) {
+            return;
+        }
+
+        //the old table is replaced by the smallest one first, so the memory of both tables is never needed at once.
+        //Its entries are lost anyway, and nothing can search before the new table arrives.
+        self.engine.set_table(TTable::new(MIN_TTABLE_SIZE));
+        self.start_task(move || Some(TTable::new(size_in_mb)));
+    }
+
+    //the default of a string option with a path
+    fn path_option(path: &Option<PathBuf>) -> String {
+        path.as_ref().map_or("<empty>".to_string(), |p| p.display().to_string())
+    }
+
+    //UCI_LimitStrength takes precedence over the skill level
+    fn skill_level(&self) -> u8 {
+        if self.limit_strength {
+            strength::skill_level_from_elo(self.elo)
+        } else {
+            self.skill_level
+        }
     }
 
     fn parse_position<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
@@ -199,8 +689,8 @@ impl UciHandler {
 
                 match Position::from_fen_string(fen.as_str()) {
                     Ok(p) => p,
-                    Err(_) => { 
-                        eprintln!("invalid fen: '{fen}'"); 
+                    Err(e) => { 
+                        send!("info string invalid fen '{fen}': {e}"); 
                         return; 
                     }
                 }
@@ -214,8 +704,8 @@ impl UciHandler {
         for move_str in tokens {
             match Move::from_string(move_str, &mut new_position) {
                 Ok(m) => new_position.make_move(m),
-                Err(_) => {
-                    eprintln!("invalid move format or illegal move: '{move_str}'");
+                Err(e) => {
+                    send!("info string invalid move in position command: {e}");
                     break;
                 }
             }
@@ -224,9 +714,20 @@ impl UciHandler {
         self.position = new_position;
     }
 
+    fn parse_bool_arg<'a, I: Iterator<Item = &'a str>>(tokens: &mut I, option: &str) -> Option<bool> {
+        match tokens.next() {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => {
+                eprintln!("expected 'true' or 'false' as value for {option}");
+                None
+            }
+        }
+    }
+
     fn parse_int_arg<'a, I: Iterator<Item = &'a str>>(tokens: &mut I, last_token: &str) -> Option<u64> {
         match tokens.next() {
-            Some(s) => match u64::from_str_radix(s, 10) {
+            Some(s) => match s.parse::<u64>() {
                 Ok(i) => Some(i),
                 Err(e) => match e.kind() {
                     IntErrorKind::PosOverflow => {
@@ -246,24 +747,147 @@ impl UciHandler {
         }
     }
 
-    fn split_perft(pos: &mut Position, depth: u32) {
+    /*
+     * go perft <depth> [stats] [threads <n>]
+     * without 'stats' the node count of every root move is printed, with 'stats' the detailed move statistics for every depth.
+     */
+    fn parse_perft<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
+        let depth = match Self::parse_int_arg(tokens, "perft") {
+            Some(d) => d.clamp(0, u32::MAX as u64) as u32,
+            None => return
+        };
+
+        let mut stats = false;
+        let mut threads = self.threads;
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "stats" => stats = true,
+                "threads" => {
+                    if let Some(n) = Self::parse_int_arg(tokens, "threads") {
+                        threads = n.clamp(1, 1024) as usize;
+                    }
+                },
+                t => eprintln!("invalid argument for perft: '{t}'")
+            }
+        }
+
+        let mut position = self.position.clone();
+        self.start_task(move || {
+            if stats {
+                Self::perft_stats(&mut position, depth, threads);
+            } else {
+                Self::split_perft(&mut position, depth, threads);
+            }
+            None
+        });
+    }
+
+    fn split_perft(pos: &mut Position, depth: u32, threads: usize) {
         if depth == 0 {
-            println!("1");
+            send!("1");
         } else {
-            let mut result = 0;
-            let mut hash_map = HashMap::new();
-            for m in pos.legal_moves().into_iter() {
-    
-                pos.make_move(m);
-                
-                let child_nodes = pos.perft_with_hash_map(depth-1, &mut hash_map);
-                println!("{}: {child_nodes}", m);
-                result += child_nodes;
+            let table = PerftTable::new(PerftTable::DEFAULT_MB_SIZE);
+            let results = perft::split_root_moves(pos, threads, |pos| perft::perft(pos, depth-1, &table));
 
-                pos.unmake_move(m);
+            for (m, child_nodes) in results.iter() {
+                send!("{m}: {child_nodes}");
             }
 
-            println!("\n{result}");
+            send!("\n{}", results.iter().map(|(_, n)| n).sum::<u64>());
+        }
+    }
+
+    fn perft_stats(pos: &mut Position, depth: u32, threads: usize) {
+        for d in 1..=depth {
+            //the moves of the last ply are classified, so the root moves can only be split from depth 2 on
+            let stats = if d == 1 {
+                perft::perft_stats(pos, 1)
+            } else {
+                let mut stats = perft::PerftStats::default();
+                for (_, s) in perft::split_root_moves(pos, threads, |pos| perft::perft_stats(pos, d-1)) {
+                    stats += s;
+                }
+                stats
+            };
+
+            send!("depth {d} {stats}");
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn game_history() {
+        let mut handler = UciHandler::new(&Config { hash: 1, ..Config::default() }).unwrap();
+        let moves = "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1";
+
+        //the moves of the position command are the history for the repetition detection
+        handler.execute(&format!("position startpos moves {moves}"));
+        assert!(!handler.position.has_repetition(0));
+        assert!(handler.position.has_upcoming_repetition(0));
+
+        handler.execute(&format!("position startpos moves {moves} f6g8"));
+        assert_eq!(handler.position.outcome(), Some(GameOutcome::Repetition));
+
+        //the repetitions only have to follow the last pawn move
+        handler.execute(&format!("position startpos moves e2e4 e7e5 {moves} f6g8"));
+        assert_eq!(handler.position.outcome(), Some(GameOutcome::Repetition));
+        handler.execute(&format!("position startpos moves {moves} e7e5"));
+        assert!(!handler.position.has_upcoming_repetition(0));
+    }
+
+    #[test]
+    fn search_states() {
+        let mut handler = UciHandler::new(&Config { hash: 1, ..Config::default() }).unwrap();
+
+        handler.execute("position startpos moves e2e4");
+        handler.execute("go infinite");
+        assert_eq!(handler.state, State::Searching);
+
+        //rejected without stopping the search
+        handler.execute("position startpos");
+        handler.execute("go depth 1");
+        handler.execute("ponderhit");
+        assert_eq!(handler.state, State::Searching);
+        assert_eq!(handler.position.current_player(), Color::Black);
+
+        handler.execute("stop");
+        assert_eq!(handler.state, State::Idle);
+        assert!(handler.events.try_recv().is_err());
+
+        handler.execute("go ponder depth 1");
+        assert_eq!(handler.state, State::Pondering);
+        handler.execute("ponderhit");
+        assert_eq!(handler.state, State::Searching);
+        handler.finish_search();
+        assert_eq!(handler.state, State::Idle);
+
+        handler.execute("setoption name Hash value 2");
+        assert!(handler.task_running);
+        handler.wait_for_task();
+        assert_eq!(handler.engine.table_size(), Some(2));
+
+        //the slow commands run on another thread, like the table allocation
+        for command in ["go perft 3", "bench 1", "hash load no_such_file.hash"] {
+            handler.execute(command);
+            assert!(handler.task_running, "{command}");
+            handler.wait_for_task();
+            assert_eq!(handler.state, State::Idle);
+        }
+        assert_eq!(handler.engine.table_size(), Some(2), "a failed load keeps the table");
+
+        let path = std::env::temp_dir().join(format!("carlito_uci_test_{}.hash", std::process::id()));
+        handler.execute(&format!("hash save {}", path.display()));
+        handler.execute("setoption name Hash value 1");
+        handler.wait_for_task();
+        assert_eq!(handler.engine.table_size(), Some(1));
+        handler.execute(&format!("hash load {}", path.display()));
+        handler.wait_for_task();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(handler.engine.table_size(), Some(2));
+    }
+}
@@ -0,0 +1,635 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::position::*;
+use crate::chess_move::*;
+use crate::engine::*;
+use crate::uci::{NAME, MIN_TTABLE_SIZE, MAX_TTABLE_SIZE, DEFAULT_MOVE_OVERHEAD};
+use crate::config::{Config, ConfigError};
+use crate::log::{self, send};
+
+/*
+ * front end for the xboard/winboard protocol (CECP) version 2.
+ */
+
+pub fn input_loop(config: &Config) -> Result<(), ConfigError> {
+    XboardHandler::new(config)?.input_loop();
+    Ok(())
+}
+
+//the time per move if there is neither a time control nor a depth limit
+const DEFAULT_MOVE_TIME: u64 = 5000;
+
+enum Event {
+    Line(String),
+    EndOfInput,
+    BestMove(u64, Option<Move>, Option<Move>)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum SearchKind {
+    Think,
+    Ponder,
+    Analyze
+}
+
+struct XboardObserver {
+    sender: mpsc::Sender<Event>,
+    post: AtomicBool,
+    search_id: AtomicU64
+}
+
+struct XboardHandler {
+    position: Position,
+    moves: Vec<Move>,
+
+    engine: Engine,
+    observer: Arc<XboardObserver>,
+    sender: mpsc::Sender<Event>,
+    events: mpsc::Receiver<Event>,
+
+    //None in force mode
+    engine_color: Option<Color>,
+
+    search: Option<(u64, SearchKind)>,
+    next_search_id: u64,
+
+    pondering_enabled: bool,
+    ponder_move: Option<Move>,
+    ponder_result: Option<(Option<Move>, Option<Move>)>,
+
+    analyzing: bool,
+
+    //time control
+    moves_per_session: u64,
+    base_time: u64,
+    increment: u64,
+    move_time: Option<u64>,
+    max_depth: Option<u64>,
+    engine_time: Option<u64>,
+    opponent_time: Option<u64>
+}
+
+impl SearchObserver for XboardObserver {
+    fn iteration_complete(&self, report: &IterationReport) {
+        if !self.post.load(Ordering::Relaxed) {
+            return;
+        }
+
+        //thinking output: ply score time nodes pv. Time is in centiseconds, mate scores are 100000 + moves
+        let score = match report.score.mate() {
+            Some(m) if m > 0 => 100000 + m as i32,
+            Some(m) => -100000 + m as i32,
+            None => report.score.centi_pawns().unwrap() as i32
+        };
+
+        let mut line = format!("{} {} {} {}", report.depth, score, report.time.as_millis() / 10, report.nodes);
+        for m in report.pv.iter() {
+            line += &format!(" {m}");
+        }
+        send!("{line}");
+    }
+
+    fn best_move(&self, best_move: Option<Move>, ponder_move: Option<Move>) {
+        //the receiver only disappears when the program is shutting down
+        let _ = self.sender.send(Event::BestMove(self.search_id.load(Ordering::Acquire), best_move, ponder_move));
+    }
+}
+
+impl XboardHandler {
+    pub fn new(config: &Config) -> Result<XboardHandler, ConfigError> {
+        let (sender, events) = mpsc::channel();
+
+        let observer = Arc::new(XboardObserver {
+            sender: sender.clone(),
+            post: AtomicBool::new(false),
+            search_id: AtomicU64::new(0)
+        });
+        let engine = config.create_engine(observer.clone())?;
+
+        Ok(XboardHandler {
+            position: Position::new(),
+            moves: Vec::new(),
+            engine,
+            observer,
+            sender,
+            events,
+            engine_color: Some(Color::Black),
+            search: None,
+            next_search_id: 1,
+            pondering_enabled: false,
+            ponder_move: None,
+            ponder_result: None,
+            analyzing: false,
+            moves_per_session: 0,
+            base_time: 0,
+            increment: 0,
+            move_time: None,
+            max_depth: None,
+            engine_time: None,
+            opponent_time: None
+        })
+    }
+
+    pub fn input_loop(&mut self) {
+        let input_sender = self.sender.clone();
+        thread::spawn(move || {
+            for line in io::stdin().lines().map(|r| r.expect("error reading stdin")) {
+                if input_sender.send(Event::Line(line)).is_err() {
+                    return;
+                }
+            }
+            let _ = input_sender.send(Event::EndOfInput);
+        });
+
+        while let Ok(event) = self.events.recv() {
+            match event {
+                Event::Line(line) => {
+                    log::input(&line);
+                    if !self.parse_command(&line) {
+                        break;
+                    }
+                },
+                Event::BestMove(id, m, ponder_move) => self.on_best_move(id, m, ponder_move),
+                Event::EndOfInput => break
+            }
+        }
+
+        self.cancel_search();
+    }
+
+    //returns false if the engine should quit
+    fn parse_command(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+
+        let command = match tokens.next() {
+            Some(c) => c,
+            None => return true
+        };
+
+        match command {
+            "protover" => {
+                send!("feature myname=\"{NAME}\" setboard=1 usermove=1 time=1 analyze=1 ping=1 memory=1 colors=0 sigint=0 sigterm=0 reuse=1 done=1");
+            },
+            "new" => {
+                self.cancel_search();
+                self.position = Position::new();
+                self.moves.clear();
+                self.engine_color = Some(Color::Black);
+                self.max_depth = None;
+                self.engine_time = None;
+                self.opponent_time = None;
+                self.engine.clear_table();
+            },
+            "force" => {
+                self.cancel_search();
+                self.engine_color = None;
+            },
+            "go" => {
+                self.cancel_search();
+                self.engine_color = Some(self.position.current_player());
+                self.think();
+            },
+            "playother" => {
+                self.cancel_search();
+                self.engine_color = Some(!self.position.current_player());
+            },
+            "usermove" => match tokens.next() {
+                Some(m) => self.user_move(m),
+                None => send!("Error (missing move): usermove")
+            },
+            "setboard" => {
+                let fen = tokens.collect::<Vec<_>>().join(" ");
+                match Position::from_fen_string(&fen) {
+                    Ok(p) => {
+                        self.cancel_search();
+                        self.position = p;
+                        self.moves.clear();
+                        self.restart_analysis();
+                    },
+                    Err(e) => send!("tellusererror Illegal position: {e}")
+                }
+            },
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "level" => self.parse_level(&mut tokens),
+            "st" => {
+                if let Some(t) = tokens.next().and_then(|t| t.parse::<f64>().ok()) {
+                    self.move_time = Some((t * 1000.0) as u64);
+                } else {
+                    send!("Error (invalid argument): {line}");
+                }
+            },
+            "sd" => {
+                if let Some(d) = tokens.next().and_then(|t| t.parse::<u64>().ok()) {
+                    self.max_depth = Some(d);
+                } else {
+                    send!("Error (invalid argument): {line}");
+                }
+            },
+            "time" => self.engine_time = tokens.next().and_then(|t| t.parse::<u64>().ok()).map(|cs| cs * 10),
+            "otim" => self.opponent_time = tokens.next().and_then(|t| t.parse::<u64>().ok()).map(|cs| cs * 10),
+            "analyze" => {
+                self.cancel_search();
+                self.analyzing = true;
+                self.restart_analysis();
+            },
+            "exit" => {
+                self.cancel_search();
+                self.analyzing = false;
+            },
+            "post" => self.observer.post.store(true, Ordering::Relaxed),
+            "nopost" => self.observer.post.store(false, Ordering::Relaxed),
+            "hard" => self.pondering_enabled = true,
+            "easy" => {
+                self.pondering_enabled = false;
+                if self.ponder_move.is_some() {
+                    self.cancel_search();
+                }
+            },
+            "result" => {
+                self.cancel_search();
+                self.engine_color = None;
+            },
+            "?" => {
+                //move now. The best move is reported through the observer.
+                if let Some((_, SearchKind::Think)) = self.search {
+                    self.engine.stop();
+                }
+            },
+            "ping" => send!("pong {}", tokens.next().unwrap_or("")),
+            "memory" => {
+                match tokens.next().and_then(|t| t.parse::<usize>().ok()) {
+                    Some(mb) => {
+                        self.cancel_search();
+                        self.engine.set_table_size(mb.clamp(MIN_TTABLE_SIZE, MAX_TTABLE_SIZE));
+                    },
+                    None => send!("Error (invalid argument): {line}")
+                }
+            },
+            "quit" => return false,
+            "xboard" | "accepted" | "rejected" | "random" | "computer" | "name" | "rating" | "ics" | "." | "draw" | "hint" | "bk" | "otherboard" => (),
+            "variant" => {
+                if tokens.next() != Some("normal") {
+                    send!("Error (unsupported variant): {line}");
+                }
+            },
+            m => {
+                //moves without the usermove prefix, in case the gui did not accept the feature
+                if Move::from_string(m, &mut self.position).is_ok() {
+                    self.user_move(m);
+                } else {
+                    send!("Error (unknown command): {m}");
+                }
+            }
+        }
+
+        true
+    }
+
+    fn user_move(&mut self, move_str: &str) {
+        let m = match Move::from_string(move_str, &mut self.position) {
+            Ok(m) => m,
+            Err(_) => {
+                send!("Illegal move: {move_str}");
+                return;
+            }
+        };
+
+        if self.ponder_move == Some(m) && self.engine_color == Some(!self.position.current_player()) {
+            //ponder hit
+            self.ponder_move = None;
+            self.make_move(m);
+
+            match self.search {
+                Some((id, SearchKind::Ponder)) => {
+                    self.search = Some((id, SearchKind::Think));
+                    self.engine.ponderhit();
+                },
+                _ => {
+                    //the ponder search already finished
+                    if let Some((best_move, ponder_move)) = self.ponder_result.take() {
+                        self.play_move(best_move, ponder_move);
+                    }
+                }
+            }
+
+            return;
+        }
+
+        self.cancel_search();
+        self.make_move(m);
+
+        if self.analyzing {
+            self.restart_analysis();
+        } else if self.engine_color == Some(self.position.current_player()) {
+            self.think();
+        }
+    }
+
+    fn take_back(&mut self, n: usize) {
+        self.cancel_search();
+
+        for _ in 0..n {
+            match self.moves.pop() {
+                Some(m) => self.position.unmake_move(m),
+                None => {
+                    send!("Error (no moves to take back): undo");
+                    break;
+                }
+            }
+        }
+
+        self.restart_analysis();
+    }
+
+    fn make_move(&mut self, m: Move) {
+        self.position.make_move(m);
+        self.moves.push(m);
+    }
+
+    fn on_best_move(&mut self, id: u64, best_move: Option<Move>, ponder_move: Option<Move>) {
+        let kind = match self.search {
+            Some((search_id, kind)) if search_id == id => kind,
+            _ => return //result of a cancelled search
+        };
+
+        self.search = None;
+
+        match kind {
+            SearchKind::Think => self.play_move(best_move, ponder_move),
+            SearchKind::Ponder => self.ponder_result = Some((best_move, ponder_move)),
+            SearchKind::Analyze => ()
+        }
+    }
+
+    fn play_move(&mut self, best_move: Option<Move>, ponder_move: Option<Move>) {
+        if let Some(m) = best_move {
+            self.make_move(m);
+            send!("move {m}");
+        }
+
+        if let Some(outcome) = self.position.outcome() {
+            send!("{}", Self::result_string(outcome));
+            return;
+        }
+
+        if let (true, Some(ponder_move)) = (self.pondering_enabled, ponder_move) {
+            self.start_pondering(ponder_move);
+        }
+    }
+
+    //ponders on the move that the last search expects the opponent to play
+    fn start_pondering(&mut self, ponder_move: Move) {
+        let mut position = self.position.clone();
+        if !position.legal_moves().contains(&ponder_move) {
+            return;
+        }
+        position.make_move(ponder_move);
+
+        if position.outcome().is_some() {
+            return;
+        }
+
+        let mut options = self.time_options(!self.position.current_player());
+        options.ponder = true;
+
+        self.ponder_move = Some(ponder_move);
+        self.start_search(SearchKind::Ponder, position, options);
+    }
+
+    fn think(&mut self) {
+        if self.position.outcome().is_some() {
+            return;
+        }
+
+        let options = self.time_options(self.position.current_player());
+        self.start_search(SearchKind::Think, self.position.clone(), options);
+    }
+
+    fn restart_analysis(&mut self) {
+        if !self.analyzing {
+            return;
+        }
+
+        self.cancel_search();
+
+        let options = EngineOptions {
+            infinite: true,
+            analyse_mode: true,
+            ..Default::default()
+        };
+        self.start_search(SearchKind::Analyze, self.position.clone(), options);
+    }
+
+    fn start_search(&mut self, kind: SearchKind, position: Position, options: EngineOptions) {
+        //make sure a best move of a previous search is not reported with the new id
+        self.engine.stop();
+
+        let id = self.next_search_id;
+        self.next_search_id += 1;
+
+        self.observer.search_id.store(id, Ordering::Release);
+        self.search = Some((id, kind));
+        self.engine.start(position, options);
+    }
+
+    fn cancel_search(&mut self) {
+        self.search = None;
+        self.ponder_move = None;
+        self.ponder_result = None;
+        self.engine.stop();
+    }
+
+    //search options for the engine playing with the given color
+    fn time_options(&self, color: Color) -> EngineOptions {
+        let mut options = EngineOptions {
+            depth: self.max_depth,
+            move_time: self.move_time,
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            pondering_enabled: self.pondering_enabled,
+            ..Default::default()
+        };
+
+        if self.move_time.is_none() {
+            let engine_time = self.engine_time.unwrap_or(self.base_time);
+            let opponent_time = self.opponent_time.unwrap_or(self.base_time);
+
+            let (own_time, own_inc, other_time) = (Some(engine_time), Some(self.increment), Some(opponent_time));
+
+            match color {
+                Color::White => {
+                    (options.wtime, options.winc, options.btime, options.binc) = (own_time, own_inc, other_time, Some(self.increment));
+                },
+                Color::Black => {
+                    (options.btime, options.binc, options.wtime, options.winc) = (own_time, own_inc, other_time, Some(self.increment));
+                }
+            }
+
+            if self.moves_per_session > 0 {
+                //the move number also counts the moves before a setboard. White has already moved when black is to move.
+                let moves_played = self.position.full_move_number() as u64 - 1 + (color == Color::White && self.position.current_player() == Color::Black) as u64;
+                options.moves_to_go = Some(self.moves_per_session - moves_played % self.moves_per_session);
+            }
+
+            if engine_time == 0 && self.base_time == 0 && self.increment == 0 {
+                //no time control at all
+                options.wtime = None;
+                options.btime = None;
+                if self.max_depth.is_none() {
+                    options.move_time = Some(DEFAULT_MOVE_TIME);
+                }
+            }
+        }
+
+        options
+    }
+
+    //level MPS BASE INC, where BASE is either minutes or minutes:seconds and INC is in seconds
+    fn parse_level<'a, I: Iterator<Item = &'a str>>(&mut self, tokens: &mut I) {
+        let moves_per_session = tokens.next().and_then(|t| t.parse::<u64>().ok());
+
+        let base_time = tokens.next().and_then(|t| {
+            let mut parts = t.split(':');
+            let minutes = parts.next()?.parse::<u64>().ok()?;
+            let seconds = match parts.next() {
+                Some(s) => s.parse::<u64>().ok()?,
+                None => 0
+            };
+            Some((minutes * 60 + seconds) * 1000)
+        });
+
+        let increment = tokens.next().and_then(|t| t.parse::<f64>().ok()).map(|s| (s * 1000.0) as u64);
+
+        match (moves_per_session, base_time, increment) {
+            (Some(m), Some(b), Some(i)) => {
+                self.moves_per_session = m;
+                self.base_time = b;
+                self.increment = i;
+                self.move_time = None;
+            },
+            _ => send!("Error (invalid arguments): level")
+        }
+    }
+
+    fn result_string(outcome: GameOutcome) -> &'static str {
+        match outcome {
+            GameOutcome::Checkmate { winner: Color::White } => "1-0 {White mates}",
+            GameOutcome::Checkmate { winner: Color::Black } => "0-1 {Black mates}",
+            GameOutcome::Stalemate => "1/2-1/2 {Stalemate}",
+            GameOutcome::InsufficientMaterial => "1/2-1/2 {Insufficient material}",
+            GameOutcome::FiftyMoveRule => "1/2-1/2 {Fifty move rule}",
+            GameOutcome::Repetition => "1/2-1/2 {Threefold repetition}"
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn handler() -> XboardHandler {
+        let mut handler = XboardHandler::new(&Config { hash: 1, ..Config::default() }).unwrap();
+        for command in ["xboard", "protover 2", "new"] {
+            handler.parse_command(command);
+        }
+        handler
+    }
+
+    //handles the best moves until the engine waits for input. A ponder search only ends with the opponent's move.
+    fn finish_search(handler: &mut XboardHandler) {
+        while matches!(handler.search, Some((_, SearchKind::Think))) {
+            if let Ok(Event::BestMove(id, m, ponder_move)) = handler.events.recv() {
+                handler.on_best_move(id, m, ponder_move);
+            }
+        }
+    }
+
+    #[test]
+    fn play_and_force_mode() {
+        let mut handler = handler();
+
+        //the engine plays black after 'new'
+        handler.parse_command("sd 2");
+        handler.parse_command("usermove e2e4");
+        assert!(matches!(handler.search, Some((_, SearchKind::Think))));
+        finish_search(&mut handler);
+        assert_eq!(handler.moves.len(), 2);
+        assert_eq!(handler.position.current_player(), Color::White);
+
+        handler.parse_command("force");
+        handler.parse_command("usermove d2d4");
+        handler.parse_command("e7e6");
+        assert!(handler.search.is_none());
+        assert_eq!(handler.moves.len(), 4);
+
+        handler.parse_command("remove");
+        assert_eq!(handler.moves.len(), 2);
+        handler.parse_command("usermove e2e5");
+        assert_eq!(handler.moves.len(), 2, "illegal move");
+
+        handler.parse_command("go");
+        assert_eq!(handler.engine_color, Some(Color::White));
+        finish_search(&mut handler);
+        assert_eq!(handler.position.current_player(), Color::Black);
+    }
+
+    #[test]
+    fn pondering_and_analysis() {
+        let mut handler = handler();
+        handler.parse_command("hard");
+        handler.parse_command("sd 3");
+        handler.parse_command("go");
+        finish_search(&mut handler);
+
+        //the engine ponders on the expected reply. Playing it turns the ponder search into a normal one.
+        let Some(ponder_move) = handler.ponder_move else {
+            panic!("no ponder move");
+        };
+        assert!(matches!(handler.search, Some((_, SearchKind::Ponder))));
+        handler.parse_command(&format!("usermove {ponder_move}"));
+        assert!(matches!(handler.search, Some((_, SearchKind::Think))));
+
+        finish_search(&mut handler);
+        assert_eq!(handler.moves.len(), 3);
+
+        handler.parse_command("force");
+        handler.parse_command("analyze");
+        assert!(matches!(handler.search, Some((_, SearchKind::Analyze))));
+        handler.parse_command("exit");
+        assert!(handler.search.is_none());
+    }
+
+    #[test]
+    fn time_controls() {
+        let mut handler = handler();
+
+        //without a time control the engine must still move on its own
+        let options = handler.time_options(Color::White);
+        assert!(!options.infinite);
+        assert_eq!(options.move_time, Some(DEFAULT_MOVE_TIME));
+
+        handler.parse_command("sd 4");
+        assert_eq!(handler.time_options(Color::White).move_time, None);
+
+        //40 moves in 5 minutes. The move number of the setboard position counts, although no moves were made.
+        handler.parse_command("level 40 5 0");
+        handler.parse_command("setboard r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 11");
+        handler.parse_command("time 30000");
+        handler.parse_command("otim 20000");
+
+        let options = handler.time_options(Color::White);
+        assert_eq!((options.wtime, options.btime, options.moves_to_go), (Some(300_000), Some(200_000), Some(30)));
+        assert_eq!(handler.time_options(Color::Black).moves_to_go, Some(30));
+
+        handler.parse_command("force");
+        handler.parse_command("usermove f1c4");
+        assert_eq!(handler.time_options(Color::White).moves_to_go, Some(29));
+        assert_eq!(handler.time_options(Color::Black).moves_to_go, Some(30));
+
+        handler.parse_command("st 2");
+        assert_eq!(handler.time_options(Color::Black).move_time, Some(2000));
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+/*
+ * records a uci session with a search that is stopped by the clock and replays the log. The replay
+ * searches as many nodes as the logged search, so it has to send the same best move.
+ */
+
+const CARLITO: &str = env!("CARGO_BIN_EXE_carlito");
+
+fn best_move_lines(output: &str) -> Vec<&str> {
+    output.lines().filter(|l| l.starts_with("bestmove")).collect()
+}
+
+#[test]
+fn record_and_replay() {
+    let path = std::env::temp_dir().join(format!("carlito_replay_test_{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut engine = Command::new(CARLITO)
+        .args(["--hash", "16", "--log-file", path.to_str().unwrap(), "uci"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut input = engine.stdin.take().unwrap();
+    let mut output = BufReader::new(engine.stdout.take().unwrap()).lines().map(|l| l.unwrap());
+    let mut recorded = Vec::new();
+
+    for position in ["position startpos moves e2e4 c7c5", "position fen r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"] {
+        writeln!(input, "{position}\ngo movetime 300").unwrap();
+        recorded.push(output.by_ref().find(|l| l.starts_with("bestmove")).unwrap());
+    }
+    writeln!(input, "quit").unwrap();
+    assert!(engine.wait().unwrap().success());
+
+    let log = fs::read_to_string(&path).unwrap();
+    assert_eq!(log.lines().filter(|l| l.contains(" -- nodes ")).count(), 2, "{log}");
+    assert_eq!(best_move_lines(&log.lines().filter_map(|l| l.split_once(" << ").map(|(_, line)| line)).collect::<Vec<_>>().join("\n")), recorded);
+
+    let replay = Command::new(CARLITO).args(["--hash", "16", "replay", path.to_str().unwrap()]).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(replay.status.success());
+
+    let replay_output = String::from_utf8(replay.stdout).unwrap();
+    assert_eq!(best_move_lines(&replay_output), recorded, "{replay_output}");
+    assert!(replay_output.contains("same as in the log"));
+}